@@ -0,0 +1,155 @@
+/*!
+ * Burst grouping module
+ *
+ * Sports and wildlife shooters take long continuous sequences at 10-30 frames per
+ * second. Frames of one camera that follow each other within a configurable gap form
+ * a burst; bursts are numbered per capture day and labeled with that day
+ * (`20251019_BURST003`), so the `{burst}` template token can put each one in its own
+ * folder or mark its file names even when a folder spans several days.
+ */
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::exif::{is_video_metadata, ExifData};
+
+/// Gap between frames used when the template has `{burst}` but no gap is configured
+pub const DEFAULT_BURST_GAP_MS: u32 = 300;
+
+/// Metadata fields with the serial number of the camera body
+const SERIAL_FIELDS: [&str; 4] = ["BodySerialNumber", "SerialNumber", "InternalSerialNumber", "CameraSerialNumber"];
+
+/// Metadata fields that identify the camera of a frame
+pub const CAMERA_FIELDS: [&str; 6] = [
+    SERIAL_FIELDS[0], SERIAL_FIELDS[1], SERIAL_FIELDS[2], SERIAL_FIELDS[3], "Make", "Model",
+];
+
+/// Burst a frame belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Burst {
+    /// Capture day of the first frame
+    pub day: NaiveDate,
+    /// Number of the burst on that day, from 1 in order of capture time
+    pub number: u32,
+}
+
+/// Number consecutive frames of the same camera taken at most `gap` apart as bursts
+///
+/// Stills only: videos never join a burst. A burst needs frames with at least two
+/// different file stems, so a RAW+JPEG pair of one frame is not a burst. Burst numbers
+/// start at 1 on every capture day, in order of capture time.
+pub fn assign_bursts<'a>(files: impl IntoIterator<Item = (&'a Path, &'a mut ExifData)>, gap: Duration) {
+    let mut by_camera: HashMap<String, Vec<(&Path, &mut ExifData)>> = HashMap::new();
+    for (path, exif_data) in files {
+        exif_data.burst = None;
+        if !is_video_metadata(&exif_data._metadata) {
+            by_camera.entry(camera_key(exif_data)).or_default().push((path, exif_data));
+        }
+    }
+
+    let mut bursts = Vec::new();
+    for mut frames in by_camera.into_values() {
+        frames.sort_by(|a, b| instant(a.1).cmp(&instant(b.1)).then(a.0.cmp(b.0)));
+        let mut current: Vec<(&Path, &mut ExifData)> = Vec::new();
+        for frame in frames {
+            let follows = current.last().is_some_and(|(_, last)| instant(frame.1) - instant(last) <= gap);
+            if !follows && !current.is_empty() {
+                bursts.push(std::mem::take(&mut current));
+            }
+            current.push(frame);
+        }
+        bursts.push(current);
+    }
+
+    bursts.retain(|frames| {
+        let stems: HashSet<_> = frames.iter()
+            .filter_map(|(path, _)| path.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()))
+            .collect();
+        stems.len() > 1
+    });
+    bursts.sort_by_key(|frames| frames.first().map(|(path, exif_data)| (instant(exif_data), path.to_path_buf())));
+
+    let mut per_day: HashMap<NaiveDate, u32> = HashMap::new();
+    for frames in bursts {
+        let Some(day) = frames.first().map(|(_, exif_data)| exif_data.timestamp.date_naive()) else { continue };
+        let number = per_day.entry(day).or_default();
+        *number += 1;
+        debug!("Burst {} of {}: {} frames", number, day, frames.len());
+        for (_, exif_data) in frames {
+            exif_data.burst = Some(Burst { day, number: *number });
+        }
+    }
+}
+
+/// Capture time including subseconds, comparable across timezones
+fn instant(exif_data: &ExifData) -> NaiveDateTime {
+    let utc = exif_data.timestamp.naive_utc();
+    utc.with_nanosecond(exif_data.nanoseconds).unwrap_or(utc)
+}
+
+/// Camera that took a file: its serial number, or make and model
+fn camera_key(exif_data: &ExifData) -> String {
+    let metadata = &exif_data._metadata;
+    let value = |field: &str| metadata.get(field)
+        .map(|value| value.trim_matches(|c: char| c == '\0' || c == '"' || c.is_whitespace()).to_lowercase())
+        .filter(|value| !value.is_empty());
+    match SERIAL_FIELDS.iter().find_map(|field| value(field)) {
+        Some(serial) => serial,
+        None => format!("{}|{}", value("Make").unwrap_or_default(), value("Model").unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Frames as (file name, RFC 3339 capture time, camera serial)
+    fn bursts(frames: &[(&str, &str, &str)]) -> Vec<(String, Option<Burst>)> {
+        let paths: Vec<PathBuf> = frames.iter().map(|(name, _, _)| PathBuf::from(name)).collect();
+        let mut exif: Vec<ExifData> = frames.iter()
+            .map(|(_, time, serial)| ExifData::for_test(time, &[("SerialNumber", serial)]))
+            .collect();
+        assign_bursts(paths.iter().map(PathBuf::as_path).zip(exif.iter_mut()), Duration::milliseconds(300));
+        frames.iter().zip(exif).map(|((name, _, _), exif_data)| (name.to_string(), exif_data.burst)).collect()
+    }
+
+    fn burst(day: &str, number: u32) -> Option<Burst> {
+        Some(Burst { day: day.parse().unwrap(), number })
+    }
+
+    #[test]
+    fn frames_within_the_gap_form_bursts_numbered_per_day() {
+        let found = bursts(&[
+            ("A1.jpg", "2025-10-19T14:30:52.000Z", "1"),
+            ("A2.jpg", "2025-10-19T14:30:52.250Z", "1"),
+            ("A3.jpg", "2025-10-19T14:30:52.500Z", "1"),
+            ("B1.jpg", "2025-10-19T16:00:00.000Z", "1"),
+            ("B2.jpg", "2025-10-19T16:00:00.100Z", "1"),
+            ("single.jpg", "2025-10-19T18:00:00.000Z", "1"),
+            ("C1.jpg", "2025-10-20T09:00:00.000Z", "1"),
+            ("C2.jpg", "2025-10-20T09:00:00.200Z", "1"),
+        ]);
+        let expected = [
+            burst("2025-10-19", 1), burst("2025-10-19", 1), burst("2025-10-19", 1),
+            burst("2025-10-19", 2), burst("2025-10-19", 2),
+            None,
+            burst("2025-10-20", 1), burst("2025-10-20", 1),
+        ];
+        for ((name, found), expected) in found.into_iter().zip(expected) {
+            assert_eq!(found, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn pairs_and_other_cameras_are_not_bursts() {
+        let found = bursts(&[
+            ("IMG_1.cr3", "2025-10-19T14:30:52.000Z", "1"),
+            ("IMG_1.jpg", "2025-10-19T14:30:52.000Z", "1"),
+            ("IMG_2.jpg", "2025-10-19T14:30:52.100Z", "2"),
+        ]);
+        assert!(found.iter().all(|(_, burst)| burst.is_none()), "{:?}", found);
+    }
+}
@@ -0,0 +1,377 @@
+/*!
+ * Checkpoint module for resuming interrupted batch runs
+ *
+ * A checkpoint stores the scanned file list plus cached analysis and hash results
+ * for one batch invocation. Completed operations are taken from the journal, so a
+ * rerun of the same invocation only does the work that was not committed yet.
+ *
+ * Results of a running pass are appended to a JSON lines log next to the checkpoint and
+ * folded into it once the pass finishes, so saving never rewrites the whole checkpoint.
+ */
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use xxhash_rust::xxh3;
+
+use crate::file_ops::AnalysisResult;
+
+/// Results appended to the log between two flushes during a long pass
+const SAVE_EVERY_RESULTS: usize = 500;
+
+/// Longest time between two flushes of the log during a long pass
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Size and modification time used to detect files that changed since they were cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedAnalysis {
+    pub stamp: FileStamp,
+    pub result: AnalysisResult,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedHash {
+    pub stamp: FileStamp,
+    pub hash: String,
+}
+
+/// One line of the checkpoint log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum LogRecord {
+    Analysis(CachedAnalysis),
+    Hash { path: PathBuf, cached: CachedHash },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Description of the batch invocation this checkpoint belongs to
+    pub invocation: String,
+    /// Journal runs started by this invocation
+    pub run_ids: Vec<String>,
+    /// Files found by the initial directory scan
+    pub files: Vec<PathBuf>,
+    pub analyses: HashMap<PathBuf, CachedAnalysis>,
+    pub hashes: HashMap<PathBuf, CachedHash>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    log_path: PathBuf,
+}
+
+impl Checkpoint {
+    /// Load the checkpoint for `invocation` from `output_dir`, or start an empty one
+    pub fn load_or_new(output_dir: &Path, invocation: &str) -> Result<Self> {
+        let key = xxh3::xxh3_64(invocation.as_bytes());
+        let path = output_dir.join(format!(".sortify-checkpoint-{:016x}.json", key));
+        let log_path = output_dir.join(format!(".sortify-checkpoint-{:016x}.log.jsonl", key));
+
+        if path.exists() {
+            let data = fs::read(&path)
+                .with_context(|| format!("Failed to read checkpoint: {}", path.display()))?;
+            match serde_json::from_slice::<Checkpoint>(&data) {
+                Ok(mut checkpoint) if checkpoint.invocation == invocation => {
+                    checkpoint.path = path;
+                    checkpoint.log_path = log_path;
+                    checkpoint.replay_log();
+                    info!("Resuming from checkpoint {} ({} files, {} runs)",
+                          checkpoint.path.display(), checkpoint.files.len(), checkpoint.run_ids.len());
+                    return Ok(checkpoint);
+                }
+                Ok(_) => warn!("Checkpoint {} belongs to a different invocation, starting fresh", path.display()),
+                Err(e) => warn!("Ignoring unreadable checkpoint {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(Self {
+            invocation: invocation.to_string(),
+            path,
+            log_path,
+            ..Default::default()
+        })
+    }
+
+    /// Apply the results logged by a pass that was interrupted before it finished
+    ///
+    /// Lines that cannot be read, such as the last one of a run that stopped mid-write, are skipped.
+    fn replay_log(&mut self) {
+        let Ok(data) = fs::read_to_string(&self.log_path) else {
+            return;
+        };
+        let mut skipped = 0;
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<LogRecord>(line) {
+                Ok(record) => self.apply(record),
+                Err(_) => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            warn!("Skipped {} unreadable lines of checkpoint log {}", skipped, self.log_path.display());
+        }
+    }
+
+    fn apply(&mut self, record: LogRecord) {
+        match record {
+            LogRecord::Analysis(cached) => {
+                self.analyses.insert(cached.result.file_path.clone(), cached);
+            }
+            LogRecord::Hash { path, cached } => {
+                self.hashes.insert(path, cached);
+            }
+        }
+    }
+
+    /// Persist the checkpoint atomically so an interruption never leaves a torn file, and
+    /// drop the log whose results it now contains
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        let data = serde_json::to_vec(self).context("Failed to serialize checkpoint")?;
+        fs::write(&tmp_path, data)
+            .with_context(|| format!("Failed to write checkpoint: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace checkpoint: {}", self.path.display()))?;
+        if self.log_path.exists() {
+            fs::remove_file(&self.log_path)
+                .with_context(|| format!("Failed to remove checkpoint log: {}", self.log_path.display()))?;
+        }
+        debug!("Saved checkpoint {}", self.path.display());
+        Ok(())
+    }
+
+    /// Delete the checkpoint once the invocation has finished
+    pub fn remove(&self) -> Result<()> {
+        for path in [&self.path, &self.log_path] {
+            if path.exists() {
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove checkpoint: {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cached analysis for `path`, if the file is unchanged since it was analyzed
+    pub fn cached_analysis(&self, path: &Path) -> Option<AnalysisResult> {
+        let cached = self.analyses.get(path)?;
+        (FileStamp::of(path)? == cached.stamp).then(|| cached.result.clone())
+    }
+
+    /// Cached content hash for `path`, if the file is unchanged since it was hashed
+    pub fn cached_hash(&self, path: &Path) -> Option<String> {
+        let cached = self.hashes.get(path)?;
+        (FileStamp::of(path)? == cached.stamp).then(|| cached.hash.clone())
+    }
+}
+
+fn analysis_record(result: &AnalysisResult) -> Option<LogRecord> {
+    let stamp = FileStamp::of(&result.file_path)?;
+    Some(LogRecord::Analysis(CachedAnalysis { stamp, result: result.clone() }))
+}
+
+fn hash_record(path: &Path, hash: &str) -> Option<LogRecord> {
+    let stamp = FileStamp::of(path)?;
+    Some(LogRecord::Hash { path: path.to_path_buf(), cached: CachedHash { stamp, hash: hash.to_string() } })
+}
+
+/// Stores results of a parallel pass in a checkpoint and appends them to its log, flushed
+/// every `SAVE_EVERY_RESULTS` results or `SAVE_INTERVAL`, so an interruption in the middle
+/// of a long analysis or hash pass loses little work
+pub struct CheckpointWriter<'a> {
+    state: Mutex<WriterState<'a>>,
+}
+
+struct WriterState<'a> {
+    checkpoint: &'a mut Checkpoint,
+    /// Missing when the log cannot be opened; results are then only saved by `finish`
+    log: Option<BufWriter<File>>,
+    unsaved: usize,
+    saved_at: Instant,
+}
+
+impl<'a> CheckpointWriter<'a> {
+    pub fn new(checkpoint: &'a mut Checkpoint) -> Self {
+        let log = OpenOptions::new().create(true).append(true).open(&checkpoint.log_path)
+            .map(BufWriter::new)
+            .map_err(|e| warn!("Failed to open checkpoint log {}: {}", checkpoint.log_path.display(), e))
+            .ok();
+        Self {
+            state: Mutex::new(WriterState { checkpoint, log, unsaved: 0, saved_at: Instant::now() }),
+        }
+    }
+
+    pub fn store_analysis(&self, result: &AnalysisResult) {
+        if let Some(record) = analysis_record(result) {
+            self.store(record);
+        }
+    }
+
+    pub fn store_hash(&self, path: &Path, hash: &str) {
+        if let Some(record) = hash_record(path, hash) {
+            self.store(record);
+        }
+    }
+
+    fn store(&self, record: LogRecord) {
+        let line = serde_json::to_string(&record);
+        let mut state = self.state.lock().unwrap();
+        state.checkpoint.apply(record);
+
+        let Some(log) = state.log.as_mut() else {
+            return;
+        };
+        // A failed log write only costs progress; the final save reports errors
+        if let Err(e) = line.map_err(anyhow::Error::from).and_then(|line| Ok(writeln!(log, "{}", line)?)) {
+            warn!("Failed to append to checkpoint log: {}", e);
+        }
+        state.unsaved += 1;
+        if state.unsaved >= SAVE_EVERY_RESULTS || state.saved_at.elapsed() >= SAVE_INTERVAL {
+            if let Some(Err(e)) = state.log.as_mut().map(BufWriter::flush) {
+                warn!("Failed to flush checkpoint log: {}", e);
+            }
+            state.unsaved = 0;
+            state.saved_at = Instant::now();
+        }
+    }
+
+    /// Fold the logged results into the checkpoint and remove the log
+    pub fn finish(self) -> Result<()> {
+        let state = self.state.into_inner().unwrap();
+        drop(state.log);
+        state.checkpoint.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(path: &Path) -> AnalysisResult {
+        AnalysisResult {
+            file_path: path.to_path_buf(),
+            success: false,
+            error: Some("No valid EXIF timestamp".to_string()),
+            exif_data: None,
+            new_filename: None,
+        }
+    }
+
+    #[test]
+    fn cached_results_are_dropped_when_the_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.jpg");
+        fs::write(&file, "photo").unwrap();
+
+        let mut checkpoint = Checkpoint::load_or_new(dir.path(), "batch a").unwrap();
+        let writer = CheckpointWriter::new(&mut checkpoint);
+        writer.store_analysis(&analysis(&file));
+        writer.store_hash(&file, "0123");
+        writer.finish().unwrap();
+        assert!(checkpoint.cached_analysis(&file).is_some());
+        assert_eq!(checkpoint.cached_hash(&file).as_deref(), Some("0123"));
+
+        fs::write(&file, "edited photo").unwrap();
+        assert!(checkpoint.cached_analysis(&file).is_none());
+        assert!(checkpoint.cached_hash(&file).is_none());
+        assert!(checkpoint.cached_hash(&dir.path().join("missing.jpg")).is_none());
+    }
+
+    #[test]
+    fn checkpoints_belong_to_one_invocation() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut checkpoint = Checkpoint::load_or_new(dir.path(), "batch a").unwrap();
+        checkpoint.run_ids.push("run".to_string());
+        checkpoint.save().unwrap();
+
+        assert_eq!(Checkpoint::load_or_new(dir.path(), "batch a").unwrap().run_ids, ["run"]);
+        assert!(Checkpoint::load_or_new(dir.path(), "batch b").unwrap().run_ids.is_empty());
+
+        checkpoint.remove().unwrap();
+        assert!(Checkpoint::load_or_new(dir.path(), "batch a").unwrap().run_ids.is_empty());
+    }
+
+    #[test]
+    fn writer_saves_while_the_pass_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..SAVE_EVERY_RESULTS + 1)
+            .map(|index| {
+                let file = dir.path().join(format!("{}.jpg", index));
+                fs::write(&file, index.to_string()).unwrap();
+                file
+            })
+            .collect();
+
+        let mut checkpoint = Checkpoint::load_or_new(dir.path(), "batch").unwrap();
+        checkpoint.save().unwrap();
+        let writer = CheckpointWriter::new(&mut checkpoint);
+        for file in &files[..SAVE_EVERY_RESULTS] {
+            writer.store_analysis(&analysis(file));
+        }
+        // Logged without finishing, as after a Ctrl+C
+        let saved = Checkpoint::load_or_new(dir.path(), "batch").unwrap();
+        assert_eq!(saved.analyses.len(), SAVE_EVERY_RESULTS);
+
+        writer.store_hash(&files[SAVE_EVERY_RESULTS], "0123");
+        writer.finish().unwrap();
+        let saved = Checkpoint::load_or_new(dir.path(), "batch").unwrap();
+        assert_eq!(saved.cached_hash(&files[SAVE_EVERY_RESULTS]).as_deref(), Some("0123"));
+        assert!(!saved.log_path.exists(), "the log is folded into the checkpoint");
+    }
+
+    #[test]
+    fn resumes_from_a_partially_written_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..3)
+            .map(|index| {
+                let file = dir.path().join(format!("{}.jpg", index));
+                fs::write(&file, index.to_string()).unwrap();
+                file
+            })
+            .collect();
+
+        let mut checkpoint = Checkpoint::load_or_new(dir.path(), "batch").unwrap();
+        checkpoint.files = files.clone();
+        checkpoint.save().unwrap();
+        let log_path = checkpoint.log_path.clone();
+        let writer = CheckpointWriter::new(&mut checkpoint);
+        writer.store_analysis(&analysis(&files[0]));
+        writer.store_hash(&files[1], "0123");
+        // The run stops while the last line is being written
+        drop(writer);
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        write!(log, "{{\"kind\":\"analysis\",\"stamp\":").unwrap();
+
+        let resumed = Checkpoint::load_or_new(dir.path(), "batch").unwrap();
+        assert_eq!(resumed.files, files);
+        assert!(resumed.cached_analysis(&files[0]).is_some());
+        assert_eq!(resumed.cached_hash(&files[1]).as_deref(), Some("0123"));
+        assert!(resumed.cached_analysis(&files[2]).is_none());
+    }
+}
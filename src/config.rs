@@ -0,0 +1,144 @@
+/*!
+ * Configuration file module
+ *
+ * Settings that are too detailed for the command line, such as per-camera
+ * tables, are read from a TOML file passed with `--config`.
+ */
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::events::EventNaming;
+use crate::exif::{ClockOffset, ConflictPolicy, VideoTimePolicy};
+use crate::sidecar::XmpPrecedence;
+
+/// Key of the entry used for cameras that match no other entry
+pub const ANY_CAMERA: &str = "*";
+
+/// Metadata fields identifying a camera, most specific first
+const CAMERA_FIELDS: [&str; 6] = [
+    "BodySerialNumber", "SerialNumber", "InternalSerialNumber", "CameraSerialNumber",
+    "Model", "Make",
+];
+
+/// Contents of a sortify config file
+///
+/// ```toml
+/// filename_patterns = ['DSC_(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})']
+/// xmp = "fallback"
+/// burst_gap_ms = 300
+/// event_gap_hours = 6
+/// event_naming = "range"
+///
+/// [timestamp_policy]
+/// preset = "original-first"
+/// ignore = ["ModifyDate"]
+/// conflict = "earliest"
+///
+/// [video_time]
+/// "*" = "local"
+/// "GoPro" = "utc"
+/// "DJI Mini 3" = "local"
+///
+/// [clock_offset]
+/// "Canon EOS R6" = "+00:03:12"
+/// "083024001234" = "-01:00:00"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Extra filename date patterns for `--fallback filename`, tried before the built-in ones
+    pub filename_patterns: Vec<String>,
+    /// How XMP sidecar metadata combines with embedded metadata (`prefer`, `fallback`, `off`)
+    pub xmp: Option<XmpPrecedence>,
+    /// Largest gap in milliseconds between frames of one burst
+    pub burst_gap_ms: Option<u32>,
+    /// Hours without a file that start a new event
+    pub event_gap_hours: Option<u32>,
+    /// How event folders are labeled (`numbered`, `range`)
+    pub event_naming: Option<EventNaming>,
+    /// Whether video timestamps of a camera are stored in UTC or local time
+    pub video_time: HashMap<String, VideoTimePolicy>,
+    /// Correction added to the timestamps of a camera whose clock was off
+    pub clock_offset: HashMap<String, ClockOffset>,
+    pub timestamp_policy: TimestampPolicyConfig,
+}
+
+/// `[timestamp_policy]` section: which metadata fields are tried for the capture time
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimestampPolicyConfig {
+    /// Built-in preset to start from (`default`, `original-first`, `strict`)
+    pub preset: Option<String>,
+    /// Replace the photo field list of the preset
+    pub photo: Option<Vec<String>>,
+    /// Replace the video field list of the preset
+    pub video: Option<Vec<String>>,
+    /// Fields moved to the front of both lists
+    pub prefer: Vec<String>,
+    /// Fields removed from both lists
+    pub ignore: Vec<String>,
+    /// Which timestamp to use when fields disagree (`first`, `earliest`, `majority`, `skip`)
+    pub conflict: Option<ConflictPolicy>,
+    /// Seconds fields may differ before they count as disagreeing
+    pub conflict_threshold: Option<u32>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+}
+
+/// Per-camera settings keyed on serial number, Model or Make
+///
+/// Keys are compared case-insensitively. A serial number entry wins over a model
+/// entry, which wins over a make entry; the `*` entry applies to every other camera.
+#[derive(Debug, Clone)]
+pub struct CameraTable<T> {
+    entries: HashMap<String, T>,
+}
+
+impl<T> Default for CameraTable<T> {
+    fn default() -> Self {
+        Self { entries: HashMap::new() }
+    }
+}
+
+impl<T> CameraTable<T> {
+    pub fn insert(&mut self, camera: &str, value: T) {
+        self.entries.insert(normalize_camera(camera), value);
+    }
+
+    /// Entry for the camera that wrote `metadata`
+    pub fn lookup(&self, metadata: &HashMap<String, String>) -> Option<&T> {
+        CAMERA_FIELDS.iter()
+            .filter_map(|field| metadata.get(*field))
+            .map(|value| normalize_camera(value))
+            .chain(std::iter::once(ANY_CAMERA.to_string()))
+            .find_map(|key| self.entries.get(&key))
+    }
+}
+
+/// Parse a `Camera=value` command line entry
+pub fn parse_camera_entry<T>(entry: &str) -> Result<(String, T)>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    let (camera, value) = entry.rsplit_once('=')
+        .with_context(|| format!("Expected Camera=value, got '{}'", entry))?;
+    if camera.trim().is_empty() {
+        anyhow::bail!("Missing camera name in '{}'", entry);
+    }
+    Ok((camera.trim().to_string(), value.parse()?))
+}
+
+fn normalize_camera(value: &str) -> String {
+    value.trim_matches(|c: char| c == '\0' || c == '"' || c.is_whitespace()).to_lowercase()
+}
@@ -0,0 +1,135 @@
+/*!
+ * Event clustering module
+ *
+ * Shoots are browsed as sessions rather than calendar months: a wedding, a hike, a
+ * game. All capture times of a run are sorted and split wherever the gap between two
+ * files exceeds a threshold; each resulting event gets a folder label such as
+ * `2024-05-01_event-1` for the `{event}` template token.
+ */
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDate};
+use log::debug;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::exif::ExifData;
+
+/// Gap between two files that starts a new event unless another one is configured
+pub const DEFAULT_EVENT_GAP_HOURS: u32 = 4;
+
+/// How event folders are labeled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventNaming {
+    /// Start date and the number of the event on that day: `2024-05-01_event-2`
+    #[default]
+    Numbered,
+    /// First and last date of the event: `2024-05-01_2024-05-03`, or `2024-05-01` for one day
+    Range,
+}
+
+impl FromStr for EventNaming {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "numbered" => Ok(Self::Numbered),
+            "range" => Ok(Self::Range),
+            _ => anyhow::bail!("Invalid event naming '{}' (expected numbered or range)", value),
+        }
+    }
+}
+
+/// Split all files into events wherever consecutive capture times are more than `gap`
+/// apart, and label every file with its event
+///
+/// Dates are local capture dates. With range naming, events that would get the same
+/// label (two sessions on one day) are told apart by an `_event-N` suffix.
+pub fn assign_events<'a>(
+    files: impl IntoIterator<Item = (&'a Path, &'a mut ExifData)>,
+    gap: Duration,
+    naming: EventNaming,
+) {
+    let mut files: Vec<(&Path, &mut ExifData)> = files.into_iter().collect();
+    files.sort_by(|a, b| a.1.timestamp.cmp(&b.1.timestamp).then(a.0.cmp(b.0)));
+
+    let mut events: Vec<Vec<&mut ExifData>> = Vec::new();
+    for (_, exif_data) in files {
+        let follows = events.last()
+            .and_then(|event| event.last())
+            .is_some_and(|last| exif_data.timestamp - last.timestamp <= gap);
+        if follows {
+            events.last_mut().unwrap().push(exif_data);
+        } else {
+            events.push(vec![exif_data]);
+        }
+    }
+
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    for event in events {
+        let dates: Vec<NaiveDate> = event.iter().map(|exif_data| exif_data.timestamp.date_naive()).collect();
+        let (first, last) = match (dates.iter().min(), dates.iter().max()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => continue,
+        };
+        let base = match naming {
+            EventNaming::Numbered => first.format("%Y-%m-%d").to_string(),
+            EventNaming::Range if first == last => first.format("%Y-%m-%d").to_string(),
+            EventNaming::Range => format!("{}_{}", first.format("%Y-%m-%d"), last.format("%Y-%m-%d")),
+        };
+        let count = labels.entry(base.clone()).or_default();
+        *count += 1;
+        let label = match naming {
+            EventNaming::Numbered => format!("{}_event-{}", base, count),
+            EventNaming::Range if *count == 1 => base,
+            EventNaming::Range => format!("{}_event-{}", base, count),
+        };
+        debug!("Event {}: {} files", label, event.len());
+        for exif_data in event {
+            exif_data.event = Some(label.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn events(times: &[&str], naming: EventNaming) -> Vec<String> {
+        let paths: Vec<PathBuf> = (0..times.len()).map(|index| PathBuf::from(format!("{}.jpg", index))).collect();
+        let mut exif: Vec<ExifData> = times.iter().map(|time| ExifData::for_test(time, &[])).collect();
+        assign_events(paths.iter().map(PathBuf::as_path).zip(exif.iter_mut()), Duration::hours(4), naming);
+        exif.into_iter().map(|exif_data| exif_data.event.unwrap()).collect()
+    }
+
+    const TIMES: [&str; 5] = [
+        "2024-05-01T09:00:00Z",
+        "2024-05-01T11:00:00Z",
+        "2024-05-01T20:00:00Z",
+        "2024-05-01T23:00:00Z",
+        "2024-05-02T02:00:00Z",
+    ];
+
+    #[test]
+    fn numbered_events_split_at_the_gap() {
+        assert_eq!(events(&TIMES, EventNaming::Numbered), [
+            "2024-05-01_event-1", "2024-05-01_event-1",
+            "2024-05-01_event-2", "2024-05-01_event-2", "2024-05-01_event-2",
+        ]);
+    }
+
+    #[test]
+    fn range_events_span_days_and_number_repeated_labels() {
+        assert_eq!(events(&TIMES, EventNaming::Range), [
+            "2024-05-01", "2024-05-01",
+            "2024-05-01_2024-05-02", "2024-05-01_2024-05-02", "2024-05-01_2024-05-02",
+        ]);
+        assert_eq!(events(&["2024-05-01T09:00:00Z", "2024-05-01T20:00:00Z"], EventNaming::Range), [
+            "2024-05-01", "2024-05-01_event-2",
+        ]);
+        assert!("weekly".parse::<EventNaming>().is_err());
+    }
+}
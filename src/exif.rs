@@ -1,4 +1,4 @@
-/**
+/*!
  * EXIF processing module with fast-exif-rs implementation
  * 
  * Processing order (fastest to slowest):
@@ -7,13 +7,41 @@
  */
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, NaiveDateTime, Utc, Datelike};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
 use fast_exif_reader::{
     FastExifReader, OptimalExifParser
 };
-use log::debug;
-use std::collections::HashMap;
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::burst::Burst;
+use crate::config::{CameraTable, ANY_CAMERA};
+use crate::field_policy::{FieldPolicy, FieldSpec};
+use crate::filename_date::FilenameDateExtractor;
+use crate::sidecar::{self, XmpPrecedence};
+
+/// Offset tags in the order they are tried when the timestamp field has no matching one
+const OFFSET_TAGS: [&str; 3] = ["OffsetTimeOriginal", "OffsetTime", "OffsetTimeDigitized"];
+
+/// QuickTime header dates, which the specification defines as UTC
+const QUICKTIME_UTC_FIELDS: [&str; 8] = [
+    "CreateDate", "Create Date", "ModifyDate", "Modify Date",
+    "MediaCreateDate", "MediaModifyDate", "TrackCreateDate", "TrackModifyDate",
+];
+
+/// Default for how far apart metadata timestamps may be before they count as a conflict
+pub const DEFAULT_CONFLICT_THRESHOLD_SECS: i64 = 60;
+
+/// Years whose January 1st is a clock epoch written by cameras and muxers without a real date
+const EPOCH_DEFAULT_YEARS: [i32; 2] = [1970, 1904];
+
+/// Cameras known to follow the QuickTime specification and store video times in UTC
+const UTC_VIDEO_MAKES: [&str; 3] = ["Apple", "Google", "samsung"];
 
 /// Simple EXIF writer for basic tag writing
 struct ExifWriter {
@@ -235,22 +263,531 @@ impl ExifWriter {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExifData {
-    pub timestamp: DateTime<Utc>,
-    pub milliseconds: u16,
+    /// Capture time as local wall-clock time with its UTC offset
+    pub timestamp: DateTime<FixedOffset>,
+    /// Fractional seconds of `timestamp` with the full precision the metadata provides
+    pub nanoseconds: u32,
+    /// Where the UTC offset of `timestamp` came from
+    #[serde(default)]
+    pub offset_source: OffsetSource,
+    /// Where the timestamp came from and how much it can be trusted
+    #[serde(default)]
+    pub provenance: Provenance,
+    /// Every candidate timestamp when the metadata fields disagree, empty when they agree
+    #[serde(default)]
+    pub conflicts: Vec<TimestampCandidate>,
+    /// Burst this frame belongs to, set by burst grouping
+    #[serde(skip)]
+    pub burst: Option<Burst>,
+    /// Label of the event (session) this file belongs to, set by event clustering
+    #[serde(skip)]
+    pub event: Option<String>,
     pub _metadata: HashMap<String, String>,
 }
 
+#[cfg(test)]
+impl ExifData {
+    /// Capture at `timestamp` (RFC 3339, fractional seconds become `nanoseconds`) with the
+    /// given metadata fields
+    pub(crate) fn for_test(timestamp: &str, metadata: &[(&str, &str)]) -> Self {
+        use chrono::Timelike;
+        let timestamp = DateTime::parse_from_rfc3339(timestamp).expect("test timestamp is RFC 3339");
+        Self {
+            timestamp: timestamp.with_nanosecond(0).unwrap(),
+            nanoseconds: timestamp.nanosecond(),
+            offset_source: OffsetSource::Timestamp,
+            provenance: Provenance::metadata("DateTimeOriginal"),
+            conflicts: Vec::new(),
+            burst: None,
+            event: None,
+            _metadata: metadata.iter().map(|(field, value)| (field.to_string(), value.to_string())).collect(),
+        }
+    }
+}
+
+/// A metadata field and the capture time it holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampCandidate {
+    pub field: String,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
+/// Origin of a capture time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TimestampSource {
+    /// EXIF/QuickTime metadata of the file
+    #[default]
+    Metadata,
+    /// An XMP sidecar next to the file
+    Sidecar,
+    /// A date encoded in the file name (`--fallback filename`)
+    Filename,
+    /// The filesystem modification time (`--fallback mtime`)
+    Mtime,
+}
+
+impl TimestampSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Sidecar => "sidecar",
+            Self::Filename => "filename",
+            Self::Mtime => "mtime",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Sidecar => "XMP sidecar",
+            Self::Filename => "file name",
+            Self::Mtime => "file modification time",
+        }
+    }
+}
+
+/// How reliably a timestamp reflects the moment of capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Modification dates, date-only file names and filesystem times
+    #[default]
+    Low,
+    /// Digitized/creation dates that usually, but not always, match the capture
+    Medium,
+    /// Original capture time written by the camera
+    High,
+}
+
+impl Confidence {
+    /// Confidence of a metadata field such as `DateTimeOriginal+SubSecTimeOriginal`
+    fn for_field(field: &str) -> Self {
+        if field.contains("Modify") {
+            Self::Low
+        } else if field.contains("Original") || field == "CreationDate" {
+            Self::High
+        } else {
+            Self::Medium
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Which source and field a timestamp was taken from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Provenance {
+    pub source: TimestampSource,
+    /// Metadata field(s), filename pattern name, or `FileModifyDate` for the mtime fallback
+    pub field: String,
+    pub confidence: Confidence,
+}
+
+impl Provenance {
+    fn metadata(field: &str) -> Self {
+        Self {
+            source: TimestampSource::Metadata,
+            field: field.to_string(),
+            confidence: Confidence::for_field(field),
+        }
+    }
+}
+
+/// Where to look for a capture time when the metadata has none, tried in the given order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFallback {
+    Filename,
+    Mtime,
+}
+
+impl FromStr for TimestampFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "filename" => Ok(Self::Filename),
+            "mtime" => Ok(Self::Mtime),
+            _ => anyhow::bail!("Invalid fallback '{}' (expected filename or mtime)", value),
+        }
+    }
+}
+
+/// Origin of the UTC offset attached to a capture time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OffsetSource {
+    /// The timestamp value carried its own offset
+    Timestamp,
+    /// An OffsetTime* tag recorded by the camera
+    OffsetTag,
+    /// No offset was recorded, the `--timezone` default was applied
+    DefaultTimezone,
+    /// A time stored in UTC (QuickTime dates, some file names), shown in the zone of the
+    /// offset tags or `--timezone`, or in UTC without either
+    StoredUtc,
+    /// No offset was recorded and no default was given, the time is treated as UTC
+    #[default]
+    Unknown,
+}
+
+/// Timezone assumed for timestamps that carry no offset information
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultTimezone {
+    /// Timezone of the machine running sortify, including daylight saving rules; only used
+    /// when asked for explicitly with `--timezone local`
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl FromStr for DefaultTimezone {
+    type Err = anyhow::Error;
+
+    /// Accepts `local`, `UTC`/`Z` or an offset such as `+02:00`, `-0500`
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "utc" | "z" => Ok(Self::Fixed(Utc.fix())),
+            other => parse_offset(other)
+                .map(Self::Fixed)
+                .with_context(|| format!("Invalid timezone '{}' (expected local, UTC or +HH:MM)", value)),
+        }
+    }
+}
+
+impl DefaultTimezone {
+    /// Offset in effect at the given local wall-clock time
+    fn offset_at(&self, local: &NaiveDateTime) -> FixedOffset {
+        match self {
+            Self::Fixed(offset) => *offset,
+            Self::Local => Local.from_local_datetime(local)
+                .earliest()
+                .map(|dt| dt.offset().fix())
+                // Wall-clock times skipped by a DST change do not exist; use the offset just after
+                .unwrap_or_else(|| Local.offset_from_utc_datetime(local).fix()),
+        }
+    }
+
+    /// Offset in effect at the given UTC time
+    fn offset_at_utc(&self, utc: &NaiveDateTime) -> FixedOffset {
+        match self {
+            Self::Fixed(offset) => *offset,
+            Self::Local => Local.offset_from_utc_datetime(utc).fix(),
+        }
+    }
+}
+
+/// How a camera fills the QuickTime date fields of its videos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoTimePolicy {
+    /// Times are UTC as the specification requires and are converted to local time
+    Utc,
+    /// The camera writes local wall-clock time, used as is
+    Local,
+}
+
+impl FromStr for VideoTimePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "utc" => Ok(Self::Utc),
+            "local" => Ok(Self::Local),
+            _ => anyhow::bail!("Invalid video time policy '{}' (expected utc or local)", value),
+        }
+    }
+}
+
+/// Which timestamp to use when metadata fields disagree by more than the conflict threshold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// The first field of the timestamp field policy
+    #[default]
+    First,
+    /// The earliest candidate, which survives edits that clobbered the original date
+    Earliest,
+    /// The candidate most other fields agree with; field order breaks ties
+    Majority,
+    /// Leave the file alone and report it
+    Skip,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "first" => Ok(Self::First),
+            "earliest" => Ok(Self::Earliest),
+            "majority" => Ok(Self::Majority),
+            "skip" => Ok(Self::Skip),
+            _ => anyhow::bail!("Invalid conflict policy '{}' (expected first, earliest, majority or skip)", value),
+        }
+    }
+}
+
+/// Correction for a camera clock that was set wrong, e.g. `+00:03:12` or `-01:00:00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClockOffset(chrono::Duration);
+
+impl FromStr for ClockOffset {
+    type Err = anyhow::Error;
+
+    /// Accepts `[+|-]H:MM:SS` or `[+|-]MM:SS`
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid clock offset '{}' (expected +HH:MM:SS)", value);
+        let trimmed = value.trim();
+        let (sign, rest) = match trimmed.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+
+        let parts = rest.split(':')
+            .map(|part| part.parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>>>()?;
+        let (hours, minutes, seconds) = match parts[..] {
+            [hours, minutes, seconds] => (hours, minutes, seconds),
+            [minutes, seconds] => (0, minutes, seconds),
+            _ => return Err(invalid()),
+        };
+        if minutes >= 60 || seconds >= 60 {
+            return Err(invalid());
+        }
+
+        let total = i64::from(hours) * 3600 + i64::from(minutes) * 60 + i64::from(seconds);
+        Ok(Self(chrono::Duration::seconds(sign * total)))
+    }
+}
+
+impl TryFrom<String> for ClockOffset {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+/// Capture times accepted as plausible; anything else falls through to the next candidate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SanityRange {
+    /// Earliest accepted capture time
+    pub min: NaiveDateTime,
+    /// How far past the current time a capture time may lie (clock drift, timezones)
+    pub future_grace: chrono::Duration,
+}
+
+impl Default for SanityRange {
+    fn default() -> Self {
+        Self {
+            min: NaiveDate::from_ymd_opt(1900, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            future_grace: chrono::Duration::days(1),
+        }
+    }
+}
+
+impl SanityRange {
+    /// Why `local` is not a plausible capture time, or None when it is
+    pub fn check(&self, local: &NaiveDateTime) -> Option<String> {
+        // Cameras with a reset clock and empty QuickTime dates report their epoch; allow a
+        // day either way since the epoch may have been shifted into a local timezone
+        for epoch_year in EPOCH_DEFAULT_YEARS {
+            let epoch = NaiveDate::from_ymd_opt(epoch_year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+            if (*local - epoch).num_hours().abs() <= 24 {
+                return Some(format!("{} is a {} epoch default", local, epoch_year));
+            }
+        }
+        if *local < self.min {
+            return Some(format!("{} is before the minimum date {}", local, self.min.date()));
+        }
+        if *local > Utc::now().naive_utc() + self.future_grace {
+            return Some(format!("{} is in the future", local));
+        }
+        None
+    }
+}
+
+/// Settings that influence how timestamps are extracted from metadata
+#[derive(Debug, Clone)]
+pub struct ExifOptions {
+    /// Timezone for timestamps without offset information (None keeps them as UTC)
+    pub default_timezone: Option<DefaultTimezone>,
+    /// Video time policy per camera, seeded with the known UTC writers
+    pub video_time: CameraTable<VideoTimePolicy>,
+    /// Clock corrections per camera
+    pub clock_offsets: CameraTable<ClockOffset>,
+    /// Fallbacks for files without a metadata timestamp (none by default)
+    pub fallbacks: Vec<TimestampFallback>,
+    /// Patterns used by the filename fallback
+    pub filename_dates: FilenameDateExtractor,
+    /// Order in which metadata fields are tried for the capture time
+    pub field_policy: FieldPolicy,
+    /// Plausible capture times
+    pub sanity: SanityRange,
+    /// How to choose between disagreeing metadata timestamps
+    pub conflict_policy: ConflictPolicy,
+    /// Largest difference between metadata timestamps that still counts as agreement
+    pub conflict_threshold: chrono::Duration,
+    /// How XMP sidecar metadata combines with embedded metadata
+    pub xmp: XmpPrecedence,
+}
+
+impl Default for ExifOptions {
+    fn default() -> Self {
+        let mut video_time = CameraTable::default();
+        video_time.insert(ANY_CAMERA, VideoTimePolicy::Local);
+        for make in UTC_VIDEO_MAKES {
+            video_time.insert(make, VideoTimePolicy::Utc);
+        }
+        Self {
+            default_timezone: None,
+            video_time,
+            clock_offsets: CameraTable::default(),
+            fallbacks: Vec::new(),
+            filename_dates: FilenameDateExtractor::default(),
+            field_policy: FieldPolicy::default(),
+            sanity: SanityRange::default(),
+            conflict_policy: ConflictPolicy::default(),
+            conflict_threshold: chrono::Duration::seconds(DEFAULT_CONFLICT_THRESHOLD_SECS),
+            xmp: XmpPrecedence::default(),
+        }
+    }
+}
+
+/// A timestamp as written in the metadata, before any timezone is assumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedTimestamp {
+    pub local: NaiveDateTime,
+    pub offset: Option<FixedOffset>,
+    pub nanoseconds: u32,
+}
+
+/// Parse a UTC offset such as `+02:00`, `-0430` or `Z`
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim().trim_matches('"');
+    if value.eq_ignore_ascii_case("z") {
+        return Some(Utc.fix());
+    }
+    let (sign, digits) = match value.as_bytes().first()? {
+        b'+' => (1, &value[1..]),
+        b'-' => (-1, &value[1..]),
+        _ => return None,
+    };
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Metadata fields only video containers have
+pub const VIDEO_FIELDS: [&str; 2] = ["MediaCreateDate", "MediaModifyDate"];
+
+/// Whether metadata was read from a video container rather than a still image
+pub fn is_video_metadata(metadata: &HashMap<String, String>) -> bool {
+    VIDEO_FIELDS.iter().any(|field| metadata.contains_key(*field))
+}
+
+/// Whether a timestamp field records when the content was captured or created, rather than
+/// when it or its file was last modified (`ModifyDate`, `FileModifyDate`, `MetadataDate`)
+fn is_capture_field(field: &str) -> bool {
+    let base = field.split('+').next().unwrap_or(field);
+    let name = base.rsplit(':').next().unwrap_or(base).trim();
+    !(name.contains("Modify") || name.starts_with("File") || name == "MetadataDate")
+}
+
+/// Offset tag that belongs to a timestamp field, following the EXIF 2.31 pairing
+fn offset_tag_for(field: &str) -> &'static str {
+    if field.contains("Original") {
+        "OffsetTimeOriginal"
+    } else if field.contains("Digitized") || field.contains("CreateDate") {
+        "OffsetTimeDigitized"
+    } else {
+        "OffsetTime"
+    }
+}
+
+fn timestamp_regex() -> &'static Regex {
+    static TIMESTAMP_RE: OnceLock<Regex> = OnceLock::new();
+    TIMESTAMP_RE.get_or_init(|| {
+        Regex::new(r"^(\d{4})[:-](\d{2})[:-](\d{2})[ T](\d{2}):(\d{2}):(\d{2})(?:\.(\d+))?\s*(Z|[+-]\d{2}:?\d{2})?$")
+            .expect("timestamp regex is valid")
+    })
+}
+
+/// Convert fractional seconds digits to nanoseconds (`5` means 500ms, digits past
+/// nanosecond precision are truncated)
+pub(crate) fn parse_subseconds(subsec: &str) -> Result<u32> {
+    let subsec = subsec.trim().trim_matches('"');
+    if !subsec.bytes().all(|b| b.is_ascii_digit()) {
+        anyhow::bail!("Failed to parse subseconds: {}", subsec);
+    }
+    let subsec = if subsec.len() > 9 { &subsec[..9] } else { subsec };
+    format!("{:0<9}", subsec).parse()
+        .with_context(|| format!("Failed to parse subseconds: {}", subsec))
+}
+
+/// Parse an EXIF or ISO timestamp, keeping any UTC offset it carries
+///
+/// Accepted forms include `2025:09:24 08:20:49`, `2025:10:12 16:26:03.12-04:00`,
+/// `2025-09-24T08:20:49Z` and `2025:10:12 16:26:03+0200`.
+pub(crate) fn parse_timestamp(timestamp_str: &str) -> Result<ParsedTimestamp> {
+    let timestamp_str = timestamp_str.trim().trim_matches('"');
+    let captures = timestamp_regex().captures(timestamp_str)
+        .with_context(|| format!("Invalid timestamp format: {}", timestamp_str))?;
+    let number = |index: usize| captures[index].parse::<u32>().unwrap_or(0);
+
+    let local = NaiveDate::from_ymd_opt(number(1) as i32, number(2), number(3))
+        .and_then(|date| date.and_hms_opt(number(4), number(5), number(6)))
+        .with_context(|| format!("Timestamp out of range: {}", timestamp_str))?;
+
+    let nanoseconds = match captures.get(7) {
+        Some(subsec) => parse_subseconds(subsec.as_str())?,
+        None => 0,
+    };
+
+    let offset = match captures.get(8) {
+        Some(offset) => Some(parse_offset(offset.as_str())
+            .with_context(|| format!("Invalid UTC offset in timestamp: {}", timestamp_str))?),
+        None => None,
+    };
+
+    Ok(ParsedTimestamp { local, offset, nanoseconds })
+}
+
 pub struct ExifProcessor {
     /// Optimal EXIF parser for automatic optimization
     optimal_parser: OptimalExifParser,
     /// Essential fields for timestamp extraction only
     _essential_fields: Vec<String>,
+    options: ExifOptions,
+}
+
+impl Default for ExifProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ExifProcessor {
     pub fn new() -> Self {
+        Self::with_options(ExifOptions::default())
+    }
+
+    pub fn with_options(options: ExifOptions) -> Self {
         // Define essential fields for timestamp extraction only
         let essential_fields = vec![
             "Make".to_string(),
@@ -266,11 +803,15 @@ impl ExifProcessor {
             "SubSecCreateDate".to_string(),
             "SubSecModifyDate".to_string(),
             "SubSecDateTimeOriginal".to_string(),
+            "OffsetTime".to_string(),
+            "OffsetTimeOriginal".to_string(),
+            "OffsetTimeDigitized".to_string(),
         ];
 
         Self {
             optimal_parser: OptimalExifParser::new(),
             _essential_fields: essential_fields,
+            options,
         }
     }
 
@@ -280,9 +821,85 @@ impl ExifProcessor {
     /// 1. Optimal EXIF parser (automatic optimization with ultra-seek, memory mapping, SIMD)
     /// 2. fast-exif-rs (ultra-fast pure Rust, works for all formats)
     /// 
-    /// Note: File modification time fallback has been removed as it's unreliable.
-    /// Files without valid EXIF timestamps will be ignored.
+    /// Note: File modification time is unreliable and only used with `--fallback mtime`.
+    /// Files without valid EXIF timestamps and no matching fallback will be ignored.
     pub fn extract_exif_data(&mut self, file_path: &Path) -> Result<ExifData> {
+        let error = match self.extract_raw_exif_data(file_path) {
+            Ok(data) => return Ok(data),
+            Err(e) => e,
+        };
+
+        for fallback in &self.options.fallbacks {
+            let fallback_data = match fallback {
+                TimestampFallback::Filename => self.extract_filename_data(file_path)?,
+                TimestampFallback::Mtime => self.extract_mtime_data(file_path)?,
+            };
+            if let Some(data) = fallback_data {
+                debug!("Dated {} from {:?} fallback: {}", file_path.display(), data.provenance, data.timestamp);
+                return Ok(data);
+            }
+        }
+        Err(error)
+    }
+
+    /// Date a file from its filesystem modification time, if that time is plausible
+    fn extract_mtime_data(&self, file_path: &Path) -> Result<Option<ExifData>> {
+        let modified: DateTime<Utc> = std::fs::metadata(file_path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to read modification time: {}", file_path.display()))?
+            .into();
+        let parsed = ParsedTimestamp {
+            local: modified.naive_utc(),
+            offset: None,
+            nanoseconds: modified.timestamp_subsec_nanos().min(999_999_999),
+        };
+        if let Some(reason) = self.options.sanity.check(&parsed.local) {
+            debug!("Rejected modification time of {}: {}", file_path.display(), reason);
+            return Ok(None);
+        }
+        let (timestamp, offset_source) = self.localize(&parsed, None, true)?;
+        Ok(Some(ExifData {
+            timestamp,
+            nanoseconds: parsed.nanoseconds,
+            offset_source,
+            provenance: Provenance {
+                source: TimestampSource::Mtime,
+                field: "FileModifyDate".to_string(),
+                confidence: Confidence::Low,
+            },
+            conflicts: Vec::new(),
+            burst: None,
+            event: None,
+            _metadata: HashMap::new(),
+        }))
+    }
+
+    /// Date a file from a timestamp encoded in its file name
+    fn extract_filename_data(&self, file_path: &Path) -> Result<Option<ExifData>> {
+        let plausible = |parsed: &ParsedTimestamp| self.options.sanity.check(&parsed.local).is_none();
+        let (pattern, parsed) = match self.options.filename_dates.extract(file_path, plausible) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let (timestamp, offset_source) = self.localize(&parsed, None, pattern.utc)?;
+        Ok(Some(ExifData {
+            timestamp,
+            nanoseconds: parsed.nanoseconds,
+            offset_source,
+            provenance: Provenance {
+                source: TimestampSource::Filename,
+                field: pattern.name.clone(),
+                // A name without a time of day only places the file on the right date
+                confidence: if pattern.has_time { Confidence::Medium } else { Confidence::Low },
+            },
+            conflicts: Vec::new(),
+            burst: None,
+            event: None,
+            _metadata: HashMap::new(),
+        }))
+    }
+
+    fn extract_raw_exif_data(&mut self, file_path: &Path) -> Result<ExifData> {
         debug!("Processing file: {}", file_path.display());
 
         // Get file size to determine optimal parsing strategy
@@ -319,6 +936,21 @@ impl ExifProcessor {
             }
         }
 
+        // Method 3: A sidecar on its own, for files the parsers cannot read
+        let mut metadata = HashMap::new();
+        let sidecar_fields = self.merge_sidecar(file_path, &mut metadata);
+        if !sidecar_fields.is_empty() {
+            match self.extract_best_timestamp(metadata, &sidecar_fields) {
+                Ok(data) => {
+                    debug!("XMP sidecar succeeded for: {}", file_path.display());
+                    return Ok(data);
+                }
+                Err(e) => {
+                    debug!("XMP sidecar failed for {}: {}", file_path.display(), e);
+                }
+            }
+        }
+
         // No valid EXIF timestamp found - ignore the file
         anyhow::bail!("No valid EXIF timestamp found for: {}", file_path.display())
     }
@@ -328,21 +960,43 @@ impl ExifProcessor {
         debug!("Using optimal EXIF parser for: {}", file_path.display());
         
         let file_path_str = file_path.to_string_lossy().to_string();
-        let metadata = self.optimal_parser.parse_file(&file_path_str)
+        let mut metadata = self.optimal_parser.parse_file(&file_path_str)
             .map_err(|e| anyhow::anyhow!("optimal parser failed: {}", e))?;
+        let sidecar_fields = self.merge_sidecar(file_path, &mut metadata);
 
         // Extract best timestamp
-        let (timestamp, milliseconds) = self.extract_best_timestamp(&metadata)?;
+        self.extract_best_timestamp(metadata, &sidecar_fields)
+    }
 
-        Ok(ExifData {
-            timestamp,
-            milliseconds,
-            _metadata: metadata,
-        })
+    /// Merge the file's XMP sidecar, if there is one, into `metadata` and return the
+    /// fields taken from it
+    fn merge_sidecar(&self, file_path: &Path, metadata: &mut HashMap<String, String>) -> HashSet<String> {
+        if self.options.xmp == XmpPrecedence::Off {
+            return HashSet::new();
+        }
+        let sidecar_path = match sidecar::find_xmp(file_path) {
+            Some(path) => path,
+            None => return HashSet::new(),
+        };
+        match sidecar::read_xmp(&sidecar_path) {
+            Ok(properties) => {
+                let sidecar_fields = sidecar::merge_xmp(metadata, properties, self.options.xmp);
+                debug!("Merged {} fields from {}", sidecar_fields.len(), sidecar_path.display());
+                sidecar_fields
+            }
+            Err(e) => {
+                warn!("Ignoring XMP sidecar {}: {:#}", sidecar_path.display(), e);
+                HashSet::new()
+            }
+        }
     }
 
     /// Analyze a single file and return analysis result
-    pub fn analyze_single_file(&mut self, file_path: &Path) -> crate::file_ops::AnalysisResult {
+    pub fn analyze_single_file(
+        &mut self,
+        file_path: &Path,
+        filename_generator: &crate::naming::FilenameGenerator,
+    ) -> crate::file_ops::AnalysisResult {
         // Skip symlinks
         if file_path.is_symlink() {
             return crate::file_ops::AnalysisResult {
@@ -360,12 +1014,10 @@ impl ExifProcessor {
                 // Generate filename for the extracted EXIF data
                 let extension = self.get_file_extension(file_path);
                 debug!("Generated extension: '{}' for file: {}", extension, file_path.display());
-                debug!("EXIF timestamp: {} ({}ms)", exif_data.timestamp, exif_data.milliseconds);
+                debug!("EXIF timestamp: {} ({}ns)", exif_data.timestamp, exif_data.nanoseconds);
                 
-                let filename_generator = crate::naming::FilenameGenerator::new();
                 let new_filename = filename_generator.generate_filename(
-                    exif_data.timestamp,
-                    exif_data.milliseconds,
+                    &exif_data,
                     &extension,
                     &[], // Will be updated with existing files later
                 );
@@ -406,7 +1058,7 @@ impl ExifProcessor {
                     _ => ext,
                 }
             })
-            .unwrap_or_else(|| "".to_string())
+            .unwrap_or_default()
     }
 
     /// Extract EXIF data using fast-exif-rs (ultra-fast pure Rust implementation)
@@ -418,158 +1070,281 @@ impl ExifProcessor {
         let file_path_str = file_path.to_string_lossy().to_string();
         
         // Use optimized file reading with minimal buffering
-        let metadata = fast_reader.read_file(&file_path_str)
+        let mut metadata = fast_reader.read_file(&file_path_str)
             .map_err(|e| anyhow::anyhow!("fast-exif-rs failed: {}", e))?;
+        let sidecar_fields = self.merge_sidecar(file_path, &mut metadata);
 
         // Extract best timestamp
-        let (timestamp, milliseconds) = self.extract_best_timestamp(&metadata)?;
-
-        Ok(ExifData {
-            timestamp,
-            milliseconds,
-            _metadata: metadata,
-        })
+        self.extract_best_timestamp(metadata, &sidecar_fields)
     }
 
-    /// Extract the best available timestamp from EXIF data using comprehensive fallback hierarchy
-    /// 
-    /// Priority order for photos:
-    /// 1. SubSecCreateDate (with subseconds)
-    /// 2. SubSecDateTimeOriginal (with subseconds) 
-    /// 3. SubSecModifyDate (with subseconds)
-    /// 4. DateTimeOriginal + SubSecTimeOriginal (combined)
-    /// 5. ModifyDate + SubSecTime (combined)
-    /// 6. DateTimeDigitized + SubSecTimeDigitized (combined)
-    /// 7. DateTimeOriginal (fallback)
-    /// 8. ModifyDate (fallback)
-    /// 9. DateTimeDigitized (fallback)
-    /// 10. CreateDate (LAST RESORT)
-    /// 
-    /// Priority order for videos:
-    /// 1. DateTimeOriginal
-    /// 2. NikonDateTime
-    /// 3. MediaCreateDate
-    /// 4. MediaModifyDate
-    /// 5. ModifyDate
-    /// 6. CreateDate (LAST RESORT)
-    fn extract_best_timestamp(&self, metadata: &HashMap<String, String>) -> Result<(DateTime<Utc>, u16)> {
+    /// Extract the best available timestamp from EXIF data using the field priority policy
+    ///
+    /// Photos and videos have separate field lists (see `FieldPolicy`); the default
+    /// prefers subsecond-precise fields and uses creation and modification dates last.
+    ///
+    /// The UTC offset is taken from the timestamp itself, then from the matching
+    /// OffsetTime* tag, then from the configured default timezone; without any of
+    /// these the wall-clock time is treated as UTC.
+    ///
+    /// The clock offset of the camera is added to every candidate read from the embedded
+    /// metadata before candidates are compared, so conflicts and the conflict policy see
+    /// corrected times. Dates from an XMP sidecar were usually corrected in an editor
+    /// already and are left alone. Implausible candidates are dropped after the correction,
+    /// so an offset can rescue a camera whose clock was reset.
+    fn extract_best_timestamp(
+        &self,
+        metadata: HashMap<String, String>,
+        sidecar_fields: &HashSet<String>,
+    ) -> Result<ExifData> {
         // Check if this is a video file
-        let is_video = metadata.contains_key("MediaCreateDate") || metadata.contains_key("MediaModifyDate");
+        let is_video = is_video_metadata(&metadata);
 
-        if is_video {
-            self.extract_video_timestamp(metadata)
+        let candidates = if is_video {
+            self.extract_video_timestamp(&metadata)?
         } else {
-            self.extract_photo_timestamp(metadata)
-        }
-    }
+            self.extract_photo_timestamp(&metadata)?
+        };
 
-    fn extract_video_timestamp(&self, metadata: &HashMap<String, String>) -> Result<(DateTime<Utc>, u16)> {
-        debug!("Extracting video timestamp from {} metadata fields", metadata.len());
-        
-        // Priority order for video timestamps (avoiding unreliable file system dates)
-        let timestamp_fields = [
-            "DateTimeOriginal",
-            "CreationDate",
-            "MediaCreateDate", 
-            "TrackCreateDate",
-            "Create Date",
-            "MakerNotes:CreateDate",
-            "MediaModifyDate",
-            "TrackModifyDate",
-            "Modify Date",
-            "MakerNotes:ModifyDate",
-            "NikonDateTime",
-            "ModifyDate",
-        ];
+        let from_sidecar = |field: &str| sidecar_fields.contains(field.split('+').next().unwrap_or_default());
+        let clock_offset = self.options.clock_offsets.lookup(&metadata).map(|ClockOffset(offset)| *offset);
 
-        for field in timestamp_fields {
-            if let Some(timestamp_str) = metadata.get(field) {
-                debug!("Found field {}: '{}'", field, timestamp_str);
-                
-                // Skip file system dates that are unreliable
-                if field.contains("File") && self.is_recent_timestamp(timestamp_str) {
-                    debug!("Skipping file system date: {}", field);
-                    continue;
-                }
-                
-                match self.parse_timestamp_with_subseconds(timestamp_str) {
-                    Ok((dt, ms)) => {
-                        debug!("Successfully parsed {}: {} ({}ms)", field, dt, ms);
-                        return Ok((dt, ms));
+        let mut resolved = Vec::new();
+        let mut rejected = Vec::new();
+        for (field, parsed) in candidates {
+            match self.resolve_timestamp(&field, &parsed, &metadata, is_video) {
+                Ok((mut timestamp, offset_source)) => {
+                    if let Some(offset) = clock_offset.filter(|_| !from_sidecar(&field)) {
+                        debug!("Applying clock offset of {}s to {}", offset.num_seconds(), field);
+                        timestamp += offset;
                     }
-                    Err(e) => {
-                        debug!("Failed to parse {}: {}", field, e);
+                    if let Some(reason) = self.options.sanity.check(&timestamp.naive_local()) {
+                        debug!("Implausible {}: {}", field, reason);
+                        rejected.push(format!("{} {}", field, reason));
+                        continue;
                     }
+                    debug!("Resolved {} to {} (offset from {:?})", field, timestamp, offset_source);
+                    resolved.push((field, parsed, timestamp, offset_source));
                 }
-            } else {
-                debug!("Field {} not found in metadata", field);
+                Err(e) => debug!("Failed to resolve {}: {}", field, e),
             }
         }
+        if resolved.is_empty() && !rejected.is_empty() {
+            let kind = if is_video { "video" } else { "photo" };
+            anyhow::bail!("No plausible timestamp found in {} EXIF data ({})", kind, rejected.join("; "));
+        }
+        let candidates: Vec<(&str, DateTime<FixedOffset>)> = resolved.iter()
+            .map(|(field, _, timestamp, _)| (field.as_str(), *timestamp))
+            .collect();
+        let chosen = self.choose_candidate(&candidates)
+            .context("No timestamp could be resolved to a point in time")?;
 
-        debug!("No valid timestamp found in video EXIF data");
-        anyhow::bail!("No valid timestamp found in video EXIF data");
+        let conflicts = if self.is_conflict(&candidates) {
+            candidates.iter()
+                .filter(|(field, _)| is_capture_field(field))
+                .map(|(field, timestamp)| TimestampCandidate { field: field.to_string(), timestamp: *timestamp })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let (field, parsed, timestamp, offset_source) = resolved.swap_remove(chosen);
+        if !conflicts.is_empty() {
+            debug!("Conflicting timestamps, using {} ({:?} policy)", field, self.options.conflict_policy);
+        }
+        let mut provenance = Provenance::metadata(&field);
+        if from_sidecar(&field) {
+            provenance.source = TimestampSource::Sidecar;
+        }
+        Ok(ExifData {
+            timestamp,
+            nanoseconds: parsed.nanoseconds,
+            offset_source,
+            provenance,
+            conflicts,
+            burst: None,
+            event: None,
+            _metadata: metadata,
+        })
     }
 
-    fn extract_photo_timestamp(&self, metadata: &HashMap<String, String>) -> Result<(DateTime<Utc>, u16)> {
-        // 1. Pre-combined subsecond timestamps (highest priority)
-        let pre_combined_fields = [
-            "SubSecCreateDate",
-            "SubSecDateTimeOriginal", 
-            "SubSecModifyDate"
-        ];
+    /// Whether candidate capture times are further apart than the conflict threshold
+    ///
+    /// Modification dates are left out: they differ from the capture time of every edited photo.
+    fn is_conflict(&self, candidates: &[(&str, DateTime<FixedOffset>)]) -> bool {
+        let instants = || candidates.iter()
+            .filter(|(field, _)| is_capture_field(field))
+            .map(|(_, instant)| *instant);
+        match (instants().min(), instants().max()) {
+            (Some(earliest), Some(latest)) => latest - earliest > self.options.conflict_threshold,
+            _ => false,
+        }
+    }
 
-        for field in pre_combined_fields {
-            if let Some(timestamp_str) = metadata.get(field) {
-                if let Ok((dt, ms)) = self.parse_timestamp_with_subseconds(timestamp_str) {
-                    return Ok((dt, ms));
-                }
+    /// Index of the candidate to use according to the conflict policy
+    ///
+    /// Candidates are in field policy order, so `first` (and `skip`, which only reports)
+    /// keeps the behaviour of using the highest-priority field. `earliest` and `majority`
+    /// choose among the capture times and fall back to the first field without any.
+    fn choose_candidate(&self, candidates: &[(&str, DateTime<FixedOffset>)]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let capture_times: Vec<usize> = (0..candidates.len())
+            .filter(|index| is_capture_field(candidates[*index].0))
+            .collect();
+        let instant = |index: usize| candidates[index].1;
+        let index = match self.options.conflict_policy {
+            ConflictPolicy::First | ConflictPolicy::Skip => 0,
+            ConflictPolicy::Earliest => capture_times.iter().copied()
+                .min_by_key(|index| instant(*index))
+                .unwrap_or(0),
+            ConflictPolicy::Majority => {
+                let agreeing = |index: usize| capture_times.iter()
+                    .filter(|other| (instant(**other) - instant(index)).abs() <= self.options.conflict_threshold)
+                    .count();
+                // max_by_key returns the last maximum, so search from the back to prefer field order
+                capture_times.iter().copied().rev()
+                    .max_by_key(|index| agreeing(*index))
+                    .unwrap_or(0)
             }
+        };
+        Some(index)
+    }
+
+    /// Attach a UTC offset to a timestamp read from `field`
+    ///
+    /// QuickTime dates of cameras whose video time policy is `utc` are converted from
+    /// UTC to the local time given by the offset tags, `--timezone` or the system timezone.
+    fn resolve_timestamp(
+        &self,
+        field: &str,
+        parsed: &ParsedTimestamp,
+        metadata: &HashMap<String, String>,
+        is_video: bool,
+    ) -> Result<(DateTime<FixedOffset>, OffsetSource)> {
+        let matching_tag = offset_tag_for(field);
+        let tag_offset = std::iter::once(matching_tag)
+            .chain(OFFSET_TAGS.into_iter().filter(|tag| *tag != matching_tag))
+            .filter_map(|tag| metadata.get(tag))
+            .find_map(|value| parse_offset(value));
+
+        let stored_as_utc = is_video
+            && QUICKTIME_UTC_FIELDS.contains(&field)
+            && self.options.video_time.lookup(metadata) == Some(&VideoTimePolicy::Utc);
+        self.localize(parsed, tag_offset, stored_as_utc)
+    }
+
+    /// Attach a UTC offset to a parsed timestamp
+    ///
+    /// An offset in the timestamp itself always wins. Otherwise photos and videos follow
+    /// one rule: the zone is `tag_offset`, then `--timezone`, then UTC. Wall-clock times
+    /// are kept in that zone and times stored in UTC are converted to it, so a photo and a
+    /// video of the same moment agree once a zone is known. The zone of the machine running
+    /// the sort is never assumed, so names do not depend on where sortify runs.
+    fn localize(
+        &self,
+        parsed: &ParsedTimestamp,
+        tag_offset: Option<FixedOffset>,
+        stored_as_utc: bool,
+    ) -> Result<(DateTime<FixedOffset>, OffsetSource)> {
+        if stored_as_utc && parsed.offset.is_none() {
+            let offset = tag_offset
+                .or_else(|| self.options.default_timezone.map(|timezone| timezone.offset_at_utc(&parsed.local)))
+                .unwrap_or_else(|| {
+                    static NO_ZONE: std::sync::Once = std::sync::Once::new();
+                    NO_ZONE.call_once(|| warn!("Times stored in UTC are named in UTC; pass --timezone to name them in local time"));
+                    Utc.fix()
+                });
+            return Ok((offset.from_utc_datetime(&parsed.local), OffsetSource::StoredUtc));
         }
 
-        // 2. Combine base timestamps with subsecond data
-        let combinations = [
-            ("DateTimeOriginal", "SubSecTimeOriginal"),
-            ("ModifyDate", "SubSecTime"),
-            ("DateTimeDigitized", "SubSecTimeDigitized")
-        ];
+        let (offset, offset_source) = match (parsed.offset, tag_offset, self.options.default_timezone) {
+            (Some(offset), _, _) => (offset, OffsetSource::Timestamp),
+            (None, Some(offset), _) => (offset, OffsetSource::OffsetTag),
+            (None, None, Some(timezone)) => (timezone.offset_at(&parsed.local), OffsetSource::DefaultTimezone),
+            (None, None, None) => (Utc.fix(), OffsetSource::Unknown),
+        };
+        let timestamp = offset.from_local_datetime(&parsed.local)
+            .single()
+            .context("Timestamp cannot be represented with its UTC offset")?;
+        Ok((timestamp, offset_source))
+    }
 
-        for (base_field, subsec_field) in combinations {
-            if let (Some(base_time), Some(subsec_value)) = (metadata.get(base_field), metadata.get(subsec_field)) {
-                let padded_subsec = format!("{:0<3}", subsec_value);
-                let combined_timestamp = format!("{}.{}", base_time, padded_subsec);
-                
-                if let Ok((dt, ms)) = self.parse_timestamp_with_subseconds(&combined_timestamp) {
-                    return Ok((dt, ms));
-                }
+    fn extract_video_timestamp(
+        &self,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Vec<(String, ParsedTimestamp)>> {
+        debug!("Extracting video timestamp from {} metadata fields", metadata.len());
+        self.extract_candidate_timestamps(&self.options.field_policy.video, metadata, "video")
+    }
+
+    fn extract_photo_timestamp(
+        &self,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Vec<(String, ParsedTimestamp)>> {
+        self.extract_candidate_timestamps(&self.options.field_policy.photo, metadata, "photo")
+    }
+
+    /// Timestamps of all fields in `fields` that are present, set and parse, in policy order
+    ///
+    /// A field combined with its subseconds and the same field on its own count as one
+    /// candidate, the first entry in the policy providing it.
+    fn extract_candidate_timestamps(
+        &self,
+        fields: &[FieldSpec],
+        metadata: &HashMap<String, String>,
+        kind: &str,
+    ) -> Result<Vec<(String, ParsedTimestamp)>> {
+        let mut candidates = Vec::new();
+        let mut seen_fields = HashSet::new();
+        let mut rejected = Vec::new();
+
+        for spec in fields {
+            if seen_fields.contains(&spec.field) {
+                continue;
             }
-        }
+            let timestamp_str = match metadata.get(&spec.field) {
+                Some(value) => value,
+                None => continue,
+            };
+            debug!("Found field {}: '{}'", spec, timestamp_str);
 
-        // 3. Fallback to base timestamps only (avoiding file system dates)
-        let fallback_fields = [
-            "DateTimeOriginal",
-            "CreationDate",
-            "Create Date",
-            "MakerNotes:CreateDate",
-            "ModifyDate",
-            "Modify Date", 
-            "MakerNotes:ModifyDate",
-            "DateTimeDigitized"
-        ];
+            if self.is_zero_timestamp(timestamp_str) {
+                debug!("Skipping unset timestamp in {}", spec);
+                rejected.push(format!("{} is unset", spec));
+                continue;
+            }
 
-        for field in fallback_fields {
-            if let Some(timestamp_str) = metadata.get(field) {
-                // Skip file system dates that are unreliable
-                if field.contains("File") && self.is_recent_timestamp(timestamp_str) {
-                    continue;
-                }
-                
-                if let Ok((dt, ms)) = self.parse_timestamp_with_subseconds(timestamp_str) {
-                    return Ok((dt, ms));
+            let parsed = self.parse_timestamp_with_subseconds(timestamp_str);
+            let parsed = match (&spec.subsec, parsed) {
+                // Combined entries need the subsecond field as well
+                (Some(subsec_field), Ok(parsed)) => match metadata.get(subsec_field) {
+                    Some(subsec_value) => parse_subseconds(subsec_value)
+                        .map(|nanoseconds| ParsedTimestamp { nanoseconds, ..parsed }),
+                    None => continue,
+                },
+                (None, parsed) => parsed,
+                (_, Err(e)) => Err(e),
+            };
+
+            match parsed {
+                Ok(parsed) => {
+                    debug!("Successfully parsed {}: {} ({}ns)", spec, parsed.local, parsed.nanoseconds);
+                    seen_fields.insert(spec.field.clone());
+                    candidates.push((spec.to_string(), parsed));
                 }
+                Err(e) => debug!("Failed to parse {}: {}", spec, e),
             }
         }
 
-        anyhow::bail!("No valid timestamp found in photo EXIF data");
+        if !candidates.is_empty() {
+            return Ok(candidates);
+        }
+        if rejected.is_empty() {
+            anyhow::bail!("No valid timestamp found in {} EXIF data", kind);
+        }
+        anyhow::bail!("No valid timestamp found in {} EXIF data ({})", kind, rejected.join("; "))
     }
 
     /// Write EXIF data to a file
@@ -662,76 +1437,268 @@ impl ExifProcessor {
         }
     }
 
-    pub fn parse_timestamp_with_subseconds(&self, timestamp_str: &str) -> Result<(DateTime<Utc>, u16)> {
-        let timestamp_str = timestamp_str.trim();
-        
-        // Handle EXIF format timestamps with timezone information
-        // Format: 2025:10:12 16:26:03.12-04:00 or 2025:09:24 08:20:49.680
-        let (main_part, subsec_part) = if timestamp_str.contains('.') {
-            // Find the last dot before any timezone info
-            let dot_pos = timestamp_str.rfind('.').unwrap();
-            let after_dot = &timestamp_str[dot_pos + 1..];
-            
-            // Check if there's timezone info after the subseconds
-            let timezone_pos = after_dot.find(|c: char| c == '+' || c == '-');
-            
-            if let Some(tz_pos) = timezone_pos {
-                // Has timezone info: 2025:10:12 16:26:03.12-04:00
-                let subsec_with_tz = &timestamp_str[dot_pos + 1..];
-                let subsec_part = &subsec_with_tz[..tz_pos];
-                let main_part = &timestamp_str[..dot_pos];
-                (main_part.to_string(), subsec_part.to_string())
-            } else {
-                // No timezone info: 2025:09:24 08:20:49.680
-                let parts: Vec<&str> = timestamp_str.split('.').collect();
-                if parts.len() != 2 {
-                    anyhow::bail!("Invalid timestamp format: {}", timestamp_str);
-                }
-                (parts[0].to_string(), parts[1].to_string())
-            }
-        } else {
-            // No subseconds
-            (timestamp_str.to_string(), "0".to_string())
+    /// Parse an EXIF or ISO timestamp, keeping any UTC offset it carries
+    pub fn parse_timestamp_with_subseconds(&self, timestamp_str: &str) -> Result<ParsedTimestamp> {
+        parse_timestamp(timestamp_str)
+    }
+
+    /// Check for unset timestamps such as `0000:00:00 00:00:00` or blanks
+    fn is_zero_timestamp(&self, timestamp_str: &str) -> bool {
+        timestamp_str.replace([':', ' ', '0'], "").is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(field, value)| (field.to_string(), value.to_string())).collect()
+    }
+
+    fn extract(options: ExifOptions, fields: &[(&str, &str)]) -> ExifData {
+        extract_with_sidecar(options, fields, &[])
+    }
+
+    /// Extract as if `sidecar_fields` had been merged from an XMP sidecar
+    fn extract_with_sidecar(options: ExifOptions, fields: &[(&str, &str)], sidecar_fields: &[&str]) -> ExifData {
+        let sidecar_fields = sidecar_fields.iter().map(|field| field.to_string()).collect();
+        ExifProcessor::with_options(options)
+            .extract_best_timestamp(metadata(fields), &sidecar_fields)
+            .unwrap()
+    }
+
+    fn zone(value: &str) -> ExifOptions {
+        ExifOptions { default_timezone: Some(value.parse().unwrap()), ..Default::default() }
+    }
+
+    #[test]
+    fn parses_exif_and_iso_timestamps() {
+        let processor = ExifProcessor::new();
+        let parsed = processor.parse_timestamp_with_subseconds("2025:10:12 16:26:03.12-04:00").unwrap();
+        assert_eq!(parsed.local.to_string(), "2025-10-12 16:26:03");
+        assert_eq!(parsed.nanoseconds, 120_000_000);
+        assert_eq!(parsed.offset, FixedOffset::west_opt(4 * 3600));
+        assert_eq!(processor.parse_timestamp_with_subseconds("2025-09-24T08:20:49Z").unwrap().offset, Some(Utc.fix()));
+        assert!(processor.parse_timestamp_with_subseconds("2025:02:30 10:00:00").is_err());
+        assert!(processor.is_zero_timestamp("0000:00:00 00:00:00"));
+        assert_eq!(parse_subseconds("1234567891").unwrap(), 123_456_789);
+        assert_eq!(parse_offset("+0530"), FixedOffset::east_opt(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("+05:60"), None);
+    }
+
+    #[test]
+    fn photo_offsets_come_from_the_value_then_the_tag_then_the_timezone() {
+        let with_tag = extract(zone("+09:00"), &[("DateTimeOriginal", "2024:05:01 10:00:00"), ("OffsetTimeOriginal", "+02:00")]);
+        assert_eq!(with_tag.timestamp.to_rfc3339(), "2024-05-01T10:00:00+02:00");
+        assert_eq!(with_tag.offset_source, OffsetSource::OffsetTag);
+
+        let with_zone = extract(zone("+09:00"), &[("DateTimeOriginal", "2024:05:01 10:00:00")]);
+        assert_eq!(with_zone.timestamp.to_rfc3339(), "2024-05-01T10:00:00+09:00");
+
+        let without = extract(ExifOptions::default(), &[("DateTimeOriginal", "2024:05:01 10:00:00")]);
+        assert_eq!(without.timestamp.to_rfc3339(), "2024-05-01T10:00:00+00:00");
+        assert_eq!(without.offset_source, OffsetSource::Unknown);
+    }
+
+    #[test]
+    fn utc_video_times_use_the_same_zone_as_photos() {
+        let video = [("Make", "Apple"), ("MediaCreateDate", "2024:05:01 08:00:00")];
+        let photo = [("Make", "Apple"), ("DateTimeOriginal", "2024:05:01 10:00:00")];
+
+        // Without a zone nothing depends on the machine: the video stays in UTC
+        let video_utc = extract(ExifOptions::default(), &video);
+        assert_eq!(video_utc.timestamp.to_rfc3339(), "2024-05-01T08:00:00+00:00");
+        assert_eq!(video_utc.offset_source, OffsetSource::StoredUtc);
+
+        // With the zone the photo and the video of the same moment agree
+        let video_local = extract(zone("+02:00"), &video);
+        let photo_local = extract(zone("+02:00"), &photo);
+        assert_eq!(video_local.timestamp.naive_local(), photo_local.timestamp.naive_local());
+        assert_eq!(video_local.timestamp, photo_local.timestamp);
+
+        // Cameras writing local time into QuickTime dates are taken as is
+        let gopro = extract(zone("+02:00"), &[("Make", "GoPro"), ("MediaCreateDate", "2024:05:01 10:00:00")]);
+        assert_eq!(gopro.timestamp.to_rfc3339(), "2024-05-01T10:00:00+02:00");
+    }
+
+    #[test]
+    fn clock_offsets_parse_with_and_without_hours() {
+        assert_eq!("+00:03:12".parse::<ClockOffset>().unwrap(), ClockOffset(chrono::Duration::seconds(192)));
+        assert_eq!("-1:00:00".parse::<ClockOffset>().unwrap(), ClockOffset(chrono::Duration::hours(-1)));
+        assert_eq!("03:12".parse::<ClockOffset>().unwrap(), ClockOffset(chrono::Duration::seconds(192)));
+        assert!("+00:60:00".parse::<ClockOffset>().is_err());
+        assert!("+3m".parse::<ClockOffset>().is_err());
+        assert!("1:2:3:4".parse::<ClockOffset>().is_err());
+    }
+
+    #[test]
+    fn clock_offset_shifts_embedded_candidates_before_they_are_compared() {
+        let mut options = ExifOptions { conflict_policy: ConflictPolicy::Earliest, ..Default::default() };
+        options.clock_offsets.insert("Canon EOS R6", "+00:03:12".parse().unwrap());
+        let embedded = extract(options.clone(), &[
+            ("Model", "Canon EOS R6"),
+            ("DateTimeOriginal", "2024:05:01 10:00:00"),
+            ("DateTimeDigitized", "2024:05:01 10:00:00"),
+        ]);
+        assert_eq!(embedded.timestamp.to_rfc3339(), "2024-05-01T10:03:12+00:00");
+        assert!(embedded.conflicts.is_empty());
+
+        // DateTimeOriginal corrected in an editor: only the embedded DateTimeDigitized is
+        // shifted, and the two agree instead of conflicting
+        let corrected = extract_with_sidecar(options, &[
+            ("Model", "Canon EOS R6"),
+            ("DateTimeOriginal", "2024:05:01 10:03:12"),
+            ("DateTimeDigitized", "2024:05:01 10:00:00"),
+        ], &["DateTimeOriginal"]);
+        assert_eq!(corrected.timestamp.to_rfc3339(), "2024-05-01T10:03:12+00:00");
+        assert_eq!(corrected.provenance.source, TimestampSource::Sidecar);
+        assert!(corrected.conflicts.is_empty());
+    }
+
+    #[test]
+    fn mtime_fallback_dates_files_without_metadata_unless_implausible() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("scan.jpg");
+        std::fs::write(&file, "no metadata").unwrap();
+        let set_mtime = |time: DateTime<Utc>| std::fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(time.into())
+            .unwrap();
+        let options = ExifOptions { fallbacks: vec![TimestampFallback::Mtime], ..zone("UTC") };
+
+        set_mtime(Utc.with_ymd_and_hms(2023, 5, 6, 7, 8, 9).unwrap());
+        let dated = ExifProcessor::with_options(options.clone()).extract_exif_data(&file).unwrap();
+        assert_eq!(dated.timestamp.to_rfc3339(), "2023-05-06T07:08:09+00:00");
+        assert_eq!((dated.provenance.source, dated.provenance.confidence), (TimestampSource::Mtime, Confidence::Low));
+        assert!(ExifProcessor::new().extract_exif_data(&file).is_err(), "only used as a fallback");
+
+        // A modification time reset to the Unix epoch is no capture time
+        set_mtime(DateTime::UNIX_EPOCH);
+        assert!(ExifProcessor::with_options(options).extract_exif_data(&file).is_err());
+    }
+
+    #[test]
+    fn provenance_names_the_source_field_and_confidence() {
+        let provenance = |data: ExifData| {
+            (data.provenance.source, data.provenance.field, data.provenance.confidence)
         };
+        let original = [("DateTimeOriginal", "2024:01:01 10:00:00")];
+        assert_eq!(
+            provenance(extract(zone("UTC"), &original)),
+            (TimestampSource::Metadata, "DateTimeOriginal".to_string(), Confidence::High),
+        );
+        assert_eq!(
+            provenance(extract(zone("UTC"), &[("ModifyDate", "2024:01:01 10:00:00")])),
+            (TimestampSource::Metadata, "ModifyDate".to_string(), Confidence::Low),
+        );
+        assert_eq!(
+            provenance(extract_with_sidecar(zone("UTC"), &original, &["DateTimeOriginal"])),
+            (TimestampSource::Sidecar, "DateTimeOriginal".to_string(), Confidence::High),
+        );
 
-        // Parse the main datetime part - try different formats
-        let naive_dt = if main_part.contains('-') {
-            // ISO format: 2025-09-24 08:20:49
-            NaiveDateTime::parse_from_str(&main_part, "%Y-%m-%d %H:%M:%S")
-                .context("Failed to parse ISO timestamp")?
-        } else {
-            // EXIF format: 2025:09:24 08:20:49
-            NaiveDateTime::parse_from_str(&main_part, "%Y:%m:%d %H:%M:%S")
-                .context("Failed to parse EXIF timestamp")?
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExifOptions { fallbacks: vec![TimestampFallback::Filename], ..zone("UTC") };
+        let from_name = |name: &str| {
+            let file = dir.path().join(name);
+            std::fs::write(&file, "no metadata").unwrap();
+            provenance(ExifProcessor::with_options(options.clone()).extract_exif_data(&file).unwrap())
         };
+        assert_eq!(from_name("IMG_20240101_100000.jpg"), (TimestampSource::Filename, "date-time".to_string(), Confidence::Medium));
+        // A name without a time of day only gives the date
+        assert_eq!(from_name("IMG-20240101-WA0001.jpg"), (TimestampSource::Filename, "whatsapp".to_string(), Confidence::Low));
+    }
 
-        // Parse subseconds and convert to milliseconds
-        let subsec_str = if subsec_part.len() > 3 {
-            &subsec_part[..3]
-        } else {
-            &subsec_part
+    #[test]
+    fn sanity_range_rejects_epochs_early_dates_and_the_future() {
+        let range = SanityRange::default();
+        let at = |value: &str| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap();
+        let reason = |local: NaiveDateTime| range.check(&local).unwrap_or_default();
+
+        assert!(reason(at("1970-01-01 00:00:00")).contains("1970 epoch default"));
+        // A day either way, for epochs shifted into a local timezone
+        assert!(reason(at("1969-12-31 14:00:00")).contains("1970 epoch default"));
+        assert_eq!(range.check(&at("1970-01-02 01:00:00")), None);
+        assert!(reason(at("1904-01-01 05:00:00")).contains("1904 epoch default"));
+
+        assert_eq!(range.check(&range.min), None);
+        assert!(reason(range.min - chrono::Duration::seconds(1)).contains("before the minimum date 1900-01-01"));
+
+        let now = Utc::now().naive_utc();
+        assert_eq!(range.check(&(now + range.future_grace - chrono::Duration::minutes(1))), None);
+        assert!(reason(now + range.future_grace + chrono::Duration::minutes(1)).contains("in the future"));
+    }
+
+    #[test]
+    fn plausibility_is_checked_after_the_clock_offset() {
+        let extract_result = |offset: &str, fields: &[(&str, &str)]| {
+            let mut options = zone("UTC");
+            options.clock_offsets.insert("Canon EOS R6", offset.parse().unwrap());
+            ExifProcessor::with_options(options).extract_best_timestamp(metadata(fields), &HashSet::new())
         };
-        // Remove quotes if present
-        let subsec_str = subsec_str.trim_matches('"');
-        let padded_subsec = format!("{:0<3}", subsec_str);
-        let milliseconds: u16 = padded_subsec.parse()
-            .context("Failed to parse subseconds")?;
 
-        let dt = DateTime::<Utc>::from_naive_utc_and_offset(naive_dt, Utc);
-        Ok((dt, milliseconds))
+        // A clock reset to the epoch, corrected by the offset to 2024
+        let reset = extract_result("+473352:00:00", &[("Model", "Canon EOS R6"), ("DateTimeOriginal", "1970:01:01 08:00:00")]);
+        assert_eq!(reset.unwrap().timestamp.to_rfc3339(), "2024-01-01T08:00:00+00:00");
+
+        // A wrong offset pushing a plausible time into the future
+        let pushed = extract_result("+175320:00:00", &[("Model", "Canon EOS R6"), ("DateTimeOriginal", "2024:01:01 08:00:00")]);
+        assert!(pushed.unwrap_err().to_string().contains("in the future"));
     }
 
-    fn _is_zero_timestamp(&self, timestamp_str: &str) -> bool {
-        timestamp_str.replace(':', "").replace(' ', "").replace('0', "").is_empty()
+    fn policy(conflict_policy: ConflictPolicy) -> ExifProcessor {
+        ExifProcessor::with_options(ExifOptions { conflict_policy, ..Default::default() })
     }
 
-    /// Check if a timestamp is suspiciously recent (likely a file system date)
-    fn is_recent_timestamp(&self, timestamp_str: &str) -> bool {
-        if let Ok((dt, _)) = self.parse_timestamp_with_subseconds(timestamp_str) {
-            // If timestamp is after 2024, it's likely a file system date
-            dt.year() > 2024
-        } else {
-            false
-        }
+    /// Candidates as (field, RFC 3339 time) in field policy order
+    fn candidates(times: &[(&'static str, &str)]) -> Vec<(&'static str, DateTime<FixedOffset>)> {
+        times.iter().map(|(field, time)| (*field, DateTime::parse_from_rfc3339(time).unwrap())).collect()
+    }
+
+    #[test]
+    fn only_capture_fields_are_compared_for_conflicts() {
+        let processor = policy(ConflictPolicy::First);
+        let edited = candidates(&[
+            ("DateTimeOriginal", "2024-01-01T10:00:00Z"),
+            ("ModifyDate+SubSecTime", "2024-06-01T10:00:00Z"),
+            ("FileModifyDate", "2024-07-01T10:00:00Z"),
+            ("MetadataDate", "2024-07-01T10:00:00Z"),
+        ]);
+        assert!(!processor.is_conflict(&edited), "an edit is no conflict");
+
+        // The threshold itself still counts as agreement
+        let threshold = DEFAULT_CONFLICT_THRESHOLD_SECS;
+        let apart = |seconds: i64| processor.is_conflict(&candidates(&[
+            ("DateTimeOriginal", "2024-01-01T10:00:00Z"),
+            ("MakerNotes:CreateDate", &(DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z").unwrap() + chrono::Duration::seconds(seconds)).to_rfc3339()),
+        ]));
+        assert!(!apart(threshold));
+        assert!(apart(threshold + 1));
+    }
+
+    #[test]
+    fn conflict_policies_choose_among_capture_times() {
+        let times = candidates(&[
+            ("SubSecModifyDate", "2023-12-01T09:00:00Z"),
+            ("DateTimeOriginal", "2024-01-01T12:00:00Z"),
+            ("CreateDate", "2024-01-01T10:00:00Z"),
+            ("DateTimeDigitized", "2024-01-01T12:00:30Z"),
+        ]);
+        assert_eq!(policy(ConflictPolicy::First).choose_candidate(&times), Some(0));
+        assert_eq!(policy(ConflictPolicy::Skip).choose_candidate(&times), Some(0));
+        // The modification date is earlier but not a capture time
+        assert_eq!(policy(ConflictPolicy::Earliest).choose_candidate(&times), Some(2));
+        // DateTimeOriginal and DateTimeDigitized agree within the threshold
+        assert_eq!(policy(ConflictPolicy::Majority).choose_candidate(&times), Some(1));
+
+        // Two disagreeing candidates are a 1-1 tie that falls back to field order
+        let tie = candidates(&[("CreateDate", "2024-01-01T12:00:00Z"), ("DateTimeOriginal", "2024-01-01T10:00:00Z")]);
+        assert_eq!(policy(ConflictPolicy::Majority).choose_candidate(&tie), Some(0));
+        assert_eq!(policy(ConflictPolicy::Earliest).choose_candidate(&tie), Some(1));
+
+        // Without capture times the first field is used
+        let modified = candidates(&[("ModifyDate", "2024-02-01T10:00:00Z"), ("FileModifyDate", "2024-01-01T10:00:00Z")]);
+        assert_eq!(policy(ConflictPolicy::Earliest).choose_candidate(&modified), Some(0));
+        assert_eq!(policy(ConflictPolicy::Majority).choose_candidate(&modified), Some(0));
+        assert_eq!(policy(ConflictPolicy::Majority).choose_candidate(&[]), None);
     }
 }
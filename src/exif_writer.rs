@@ -1,4 +1,4 @@
-/**
+/*!
  * EXIF Writer module - Custom implementation for writing EXIF data
  * 
  * Based on EXIF specification and exiftool algorithms:
@@ -430,7 +430,7 @@ impl ExifWriter {
         for entry in &ifd.entries {
             if entry.value.len() > 4 {
                 // Align to 2-byte boundary
-                while data.len() % 2 != 0 {
+                while !data.len().is_multiple_of(2) {
                     data.push(0);
                 }
                 data.write_all(&entry.value)?;
@@ -0,0 +1,253 @@
+/*!
+ * Timestamp field priority policy module
+ *
+ * Decides which metadata fields are tried for the capture time, and in which order,
+ * separately for photos and videos. Entries are a field name (`DateTimeOriginal`) or a
+ * base field combined with its subsecond field (`DateTimeOriginal+SubSecTimeOriginal`).
+ */
+
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// Preset used when no policy is configured
+pub const DEFAULT_PRESET: &str = "default";
+
+/// Photo field order of the `default` preset
+const DEFAULT_PHOTO_FIELDS: [&str; 14] = [
+    // Pre-combined subsecond timestamps
+    "SubSecCreateDate",
+    "SubSecDateTimeOriginal",
+    "SubSecModifyDate",
+    // Base timestamps combined with their subsecond data
+    "DateTimeOriginal+SubSecTimeOriginal",
+    "ModifyDate+SubSecTime",
+    "DateTimeDigitized+SubSecTimeDigitized",
+    // Base timestamps only
+    "DateTimeOriginal",
+    "CreationDate",
+    "Create Date",
+    "MakerNotes:CreateDate",
+    "ModifyDate",
+    "Modify Date",
+    "MakerNotes:ModifyDate",
+    "DateTimeDigitized",
+];
+
+/// Video field order of the `default` preset
+const DEFAULT_VIDEO_FIELDS: [&str; 12] = [
+    "DateTimeOriginal",
+    "CreationDate",
+    "MediaCreateDate",
+    "TrackCreateDate",
+    "Create Date",
+    "MakerNotes:CreateDate",
+    "MediaModifyDate",
+    "TrackModifyDate",
+    "Modify Date",
+    "MakerNotes:ModifyDate",
+    "NikonDateTime",
+    "ModifyDate",
+];
+
+/// Photo field order of the `original-first` and `strict` presets
+const ORIGINAL_FIRST_PHOTO_FIELDS: [&str; 14] = [
+    "SubSecDateTimeOriginal",
+    "DateTimeOriginal+SubSecTimeOriginal",
+    "DateTimeOriginal",
+    "CreationDate",
+    "SubSecCreateDate",
+    "DateTimeDigitized+SubSecTimeDigitized",
+    "DateTimeDigitized",
+    "Create Date",
+    "MakerNotes:CreateDate",
+    "SubSecModifyDate",
+    "ModifyDate+SubSecTime",
+    "ModifyDate",
+    "Modify Date",
+    "MakerNotes:ModifyDate",
+];
+
+/// Names of the built-in presets
+pub const PRESETS: [&str; 3] = [DEFAULT_PRESET, "original-first", "strict"];
+
+/// One candidate source of the capture time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub field: String,
+    /// Field holding the fractional seconds of `field`
+    pub subsec: Option<String>,
+}
+
+impl FromStr for FieldSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (field, subsec) = match value.split_once('+') {
+            Some((field, subsec)) => (field.trim(), Some(subsec.trim())),
+            None => (value.trim(), None),
+        };
+        if field.is_empty() || subsec.is_some_and(str::is_empty) {
+            anyhow::bail!("Invalid timestamp field '{}' (expected Field or Field+SubSecField)", value);
+        }
+        Ok(Self { field: field.to_string(), subsec: subsec.map(str::to_string) })
+    }
+}
+
+impl fmt::Display for FieldSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.subsec {
+            Some(subsec) => write!(f, "{}+{}", self.field, subsec),
+            None => write!(f, "{}", self.field),
+        }
+    }
+}
+
+/// Ordered timestamp fields for photos and videos; the first usable one wins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldPolicy {
+    pub photo: Vec<FieldSpec>,
+    pub video: Vec<FieldSpec>,
+}
+
+impl FieldPolicy {
+    /// Built-in preset by name
+    ///
+    /// - `default`: subsecond-precise fields first, modification dates before digitized ones
+    /// - `original-first`: original capture time first, modification dates last
+    /// - `strict`: only original capture and creation times, never modification dates
+    pub fn preset(name: &str) -> Result<Self> {
+        let policy = match name.trim().to_lowercase().as_str() {
+            DEFAULT_PRESET => Self::from_fields(&DEFAULT_PHOTO_FIELDS, &DEFAULT_VIDEO_FIELDS),
+            "original-first" => Self::from_fields(&ORIGINAL_FIRST_PHOTO_FIELDS, &DEFAULT_VIDEO_FIELDS),
+            "strict" => {
+                let mut policy = Self::from_fields(&ORIGINAL_FIRST_PHOTO_FIELDS, &DEFAULT_VIDEO_FIELDS);
+                policy.photo.retain(|spec| !spec.field.contains("Modify"));
+                policy.video.retain(|spec| !spec.field.contains("Modify"));
+                policy
+            }
+            _ => anyhow::bail!("Unknown timestamp policy preset '{}' (available: {})", name, PRESETS.join(", ")),
+        };
+        Ok(policy)
+    }
+
+    fn from_fields(photo: &[&str], video: &[&str]) -> Self {
+        let parse = |fields: &[&str]| fields.iter()
+            .map(|field| field.parse().expect("built-in field is valid"))
+            .collect();
+        Self { photo: parse(photo), video: parse(video) }
+    }
+
+    /// Parse an explicit field list such as `["DateTimeOriginal+SubSecTimeOriginal", "CreateDate"]`
+    pub fn parse_fields(fields: &[String]) -> Result<Vec<FieldSpec>> {
+        fields.iter().map(|field| field.parse()).collect()
+    }
+
+    /// Move the given fields to the front, in the given order
+    ///
+    /// A plain field name also moves the entries that combine it with a subsecond field.
+    /// Fields the policy does not know yet are added.
+    pub fn prefer(&mut self, fields: &[FieldSpec]) {
+        for list in [&mut self.photo, &mut self.video] {
+            let mut preferred = Vec::new();
+            for wanted in fields {
+                let (matching, rest): (Vec<_>, Vec<_>) = list.drain(..).partition(|spec| matches(spec, wanted));
+                *list = rest;
+                if matching.is_empty() {
+                    preferred.push(wanted.clone());
+                } else {
+                    preferred.extend(matching);
+                }
+            }
+            preferred.append(list);
+            *list = preferred;
+        }
+    }
+
+    /// Never use the given fields (a plain field name also excludes its combined entries)
+    pub fn ignore(&mut self, fields: &[FieldSpec]) {
+        for list in [&mut self.photo, &mut self.video] {
+            list.retain(|spec| !fields.iter().any(|ignored| matches(spec, ignored)));
+        }
+    }
+}
+
+impl Default for FieldPolicy {
+    fn default() -> Self {
+        Self::preset(DEFAULT_PRESET).expect("default preset exists")
+    }
+}
+
+/// Whether `spec` is selected by a user entry (exact match, or a plain name matching its base field)
+fn matches(spec: &FieldSpec, entry: &FieldSpec) -> bool {
+    spec == entry || (entry.subsec.is_none() && spec.field == entry.field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(fields: &[&str]) -> Vec<FieldSpec> {
+        fields.iter().map(|field| field.parse().unwrap()).collect()
+    }
+
+    fn names(list: &[FieldSpec]) -> Vec<String> {
+        list.iter().map(FieldSpec::to_string).collect()
+    }
+
+    #[test]
+    fn field_specs_parse_plain_and_combined_fields() {
+        let combined: FieldSpec = " DateTimeOriginal + SubSecTimeOriginal ".parse().unwrap();
+        assert_eq!(combined, FieldSpec { field: "DateTimeOriginal".to_string(), subsec: Some("SubSecTimeOriginal".to_string()) });
+        assert_eq!(combined.to_string(), "DateTimeOriginal+SubSecTimeOriginal");
+        assert_eq!("CreateDate".parse::<FieldSpec>().unwrap().subsec, None);
+        for invalid in ["", " ", "+SubSecTime", "ModifyDate+"] {
+            assert!(invalid.parse::<FieldSpec>().is_err(), "{:?}", invalid);
+        }
+        assert!(FieldPolicy::parse_fields(&["DateTimeOriginal".to_string(), "+".to_string()]).is_err());
+    }
+
+    #[test]
+    fn presets_are_looked_up_by_name() {
+        assert_eq!(FieldPolicy::preset(" Default ").unwrap(), FieldPolicy::default());
+        let original_first = FieldPolicy::preset("original-first").unwrap();
+        assert_eq!(names(&original_first.photo[..3]), ["SubSecDateTimeOriginal", "DateTimeOriginal+SubSecTimeOriginal", "DateTimeOriginal"]);
+
+        let strict = FieldPolicy::preset("STRICT").unwrap();
+        assert!(strict.photo.iter().chain(&strict.video).all(|spec| !spec.field.contains("Modify")));
+        assert_eq!(strict.photo[0], original_first.photo[0]);
+
+        let unknown = FieldPolicy::preset("newest").unwrap_err().to_string();
+        assert!(unknown.contains("'newest'") && unknown.contains("original-first"), "{}", unknown);
+    }
+
+    #[test]
+    fn prefer_moves_fields_to_the_front_in_the_given_order() {
+        let mut policy = FieldPolicy::default();
+        // A plain name also moves its combined entry; an unknown field is added
+        policy.prefer(&specs(&["CreateDate", "DateTimeOriginal"]));
+        assert_eq!(names(&policy.photo[..4]), ["CreateDate", "DateTimeOriginal+SubSecTimeOriginal", "DateTimeOriginal", "SubSecCreateDate"]);
+        assert_eq!(names(&policy.video[..3]), ["CreateDate", "DateTimeOriginal", "CreationDate"]);
+        assert_eq!(policy.photo.len(), DEFAULT_PHOTO_FIELDS.len() + 1);
+
+        // A combined entry moves only itself
+        let mut policy = FieldPolicy::default();
+        policy.prefer(&specs(&["ModifyDate+SubSecTime"]));
+        assert_eq!(names(&policy.photo[..2]), ["ModifyDate+SubSecTime", "SubSecCreateDate"]);
+        assert!(names(&policy.photo).contains(&"ModifyDate".to_string()));
+    }
+
+    #[test]
+    fn ignore_removes_fields_and_their_combined_entries() {
+        let mut policy = FieldPolicy::default();
+        policy.ignore(&specs(&["ModifyDate", "DateTimeOriginal+SubSecTimeOriginal"]));
+        let photo = names(&policy.photo);
+        for removed in ["ModifyDate", "ModifyDate+SubSecTime", "DateTimeOriginal+SubSecTimeOriginal"] {
+            assert!(!photo.contains(&removed.to_string()), "{}", removed);
+        }
+        for kept in ["SubSecModifyDate", "DateTimeOriginal"] {
+            assert!(photo.contains(&kept.to_string()), "{}", kept);
+        }
+        assert!(!names(&policy.video).contains(&"ModifyDate".to_string()));
+    }
+}
@@ -1,4 +1,4 @@
-/**
+/*!
  * File operations module for processing and renaming files
  */
 
@@ -7,16 +7,33 @@ use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, info, warn};
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use walkdir::WalkDir;
 
-use crate::exif::{ExifData, ExifProcessor};
-use crate::hashing::ContentHasher;
-use crate::naming::FilenameGenerator;
+use crate::burst;
+use crate::checkpoint::{Checkpoint, CheckpointWriter};
+use crate::events::{self, EventNaming};
+use crate::exif::{self, is_video_metadata, ConflictPolicy, ExifData, ExifOptions, ExifProcessor, Provenance, TimestampCandidate};
+use crate::hashing::{ContentHasher, HashMode, PARTIAL_HASH_BYTES};
+use crate::journal::{self, Journal};
+use crate::naming::{self, FilenameGenerator, NamingTemplate, NamingTime, TieBreakOrder};
+use crate::sidecar::{self, SidecarIndex};
+
+/// Directory in the output directory that receives deleted near duplicates, one folder per run
+pub const TRASH_DIR_NAME: &str = ".sortify-trash";
+
+/// Whether anything, including a dangling symlink, exists at `path`
+fn occupied(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
 
 /// Perform file operation based on mode
+///
+/// An existing target is never overwritten, whatever the mode.
 fn perform_file_operation(source_path: &Path, target_path: &Path, mode: &str) -> Result<()> {
     debug!("Attempting {} operation: '{}' -> '{}'", mode, source_path.display(), target_path.display());
     
@@ -24,6 +41,10 @@ fn perform_file_operation(source_path: &Path, target_path: &Path, mode: &str) ->
     if !source_path.exists() {
         anyhow::bail!("Source file does not exist: {}", source_path.display());
     }
+
+    if occupied(target_path) {
+        anyhow::bail!("Target already exists: {}", target_path.display());
+    }
     
     // Check if target directory exists
     if let Some(parent) = target_path.parent() {
@@ -77,13 +98,275 @@ fn perform_file_operation(source_path: &Path, target_path: &Path, mode: &str) ->
     Ok(())
 }
 
-#[derive(Debug, Clone)]
+/// Reverse a file operation that just succeeded, as part of rolling back a transaction
+fn revert_file_operation(source_path: &Path, target_path: &Path, mode: &str) -> Result<()> {
+    match mode {
+        "move" => perform_file_operation(target_path, source_path, "move"),
+        _ => fs::remove_file(target_path)
+            .with_context(|| format!("Failed to remove {}", target_path.display())),
+    }
+}
+
+/// Perform the operations of one file and its sidecars all or nothing, recording them
+/// in the journal when one is given
+///
+/// When an operation fails, the ones already done are reverted in reverse order. Every
+/// operation is journaled as started before any of them runs and as done once all completed,
+/// so a crash in between still leaves the moved files in the journal.
+fn perform_transaction(operations: &[(PathBuf, PathBuf)], mode: &str, journal: Option<&Journal>) -> Result<()> {
+    // A symlink to a relative path would resolve against the directory of the link, and
+    // undo compares the link with the journaled absolute source
+    let absolute_operations;
+    let operations = if mode == "symlink" {
+        absolute_operations = operations.iter()
+            .map(|(source_path, target_path)| Ok((journal::absolute_path(source_path)?, target_path.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        &absolute_operations
+    } else {
+        operations
+    };
+
+    let mut started = Vec::new();
+    if let Some(journal) = journal {
+        for (source_path, target_path) in operations {
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+            }
+            started.push(journal.begin(source_path, target_path, mode)
+                .with_context(|| format!("Failed to journal {} -> {}", source_path.display(), target_path.display()))?);
+        }
+    }
+
+    for (index, (source_path, target_path)) in operations.iter().enumerate() {
+        if let Err(e) = perform_file_operation(source_path, target_path, mode) {
+            for (done_source, done_target) in operations[..index].iter().rev() {
+                if let Err(revert_error) = revert_file_operation(done_source, done_target, mode) {
+                    warn!("Failed to roll back {} -> {}: {}", done_source.display(), done_target.display(), revert_error);
+                }
+            }
+            return Err(e);
+        }
+    }
+
+    if let Some(journal) = journal {
+        for entry in &started {
+            // The operation already happened, so a journal failure must not turn it into an error
+            if let Err(e) = journal.complete(entry) {
+                warn!("Failed to journal {} -> {}: {}", entry.source.display(), entry.target.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ProcessResult {
     pub file_path: PathBuf,
     pub success: bool,
     pub renamed: bool,
-    pub _new_path: Option<PathBuf>,
+    pub new_path: Option<PathBuf>,
     pub error: Option<String>,
+    /// Tie-breaking suffix (-2, -3, ...) assigned because of a name collision
+    pub tie_break_suffix: Option<u32>,
+    /// File with identical content that caused this file to be skipped
+    pub duplicate_of: Option<PathBuf>,
+    /// Where the capture time used for naming came from
+    pub provenance: Option<Provenance>,
+    /// Candidate timestamps when the metadata fields disagreed
+    pub conflicts: Vec<TimestampCandidate>,
+    /// Sidecar files handled together with this file, as (source, target)
+    pub sidecars: Vec<(PathBuf, PathBuf)>,
+    /// Other files of the same shot (RAW+JPEG) that received the same base name
+    pub paired_with: Vec<PathBuf>,
+}
+
+/// Largest capture time difference between files of one shot, e.g. a RAW and its JPEG
+const SHOT_TOLERANCE_SECS: i64 = 1;
+
+/// Largest capture time difference between the still and the video of a Live Photo paired
+/// by name; the video starts before the still is taken
+const LIVE_PHOTO_TOLERANCE_SECS: i64 = 3;
+
+/// Metadata fields with the identifier Apple writes into both halves of a Live Photo
+/// (Apple maker note of the HEIC/JPEG, QuickTime keys of the MOV)
+const LIVE_PHOTO_ID_FIELDS: [&str; 2] = ["ContentIdentifier", "com.apple.quicktime.content.identifier"];
+
+/// Base name decided by the first file of a shot, shared by the others
+struct SharedName<'a> {
+    exif_data: &'a ExifData,
+    suffix: Option<u32>,
+}
+
+/// Where a file and its sidecars go
+struct RenamePlan {
+    final_filename: String,
+    target_path: PathBuf,
+    tie_break_suffix: Option<u32>,
+    sidecars: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Split analysis results into shots that share one generated name
+///
+/// Files carrying the same Live Photo content identifier form a shot wherever they are.
+/// Otherwise files in the same directory with the same stem and different extensions
+/// form a shot when their capture times are at most `SHOT_TOLERANCE_SECS` apart
+/// (`LIVE_PHOTO_TOLERANCE_SECS` between a still and a video).
+///
+/// The first file of a shot decides the shared name: the one with the most trustworthy
+/// timestamp, preferring stills over videos and subsecond precision, then the path.
+fn group_shots(results: Vec<AnalysisResult>) -> Vec<Vec<AnalysisResult>> {
+    let mut shots = Vec::new();
+    let mut by_identifier: HashMap<String, Vec<AnalysisResult>> = HashMap::new();
+    let mut unidentified = Vec::new();
+    for result in results {
+        match live_photo_id(&result) {
+            Some(identifier) => by_identifier.entry(identifier).or_default().push(result),
+            None => unidentified.push(result),
+        }
+    }
+    for candidates in by_identifier.into_values() {
+        if candidates.len() > 1 {
+            shots.extend(split_shots(candidates, true));
+        } else {
+            unidentified.extend(candidates);
+        }
+    }
+
+    let mut by_stem: HashMap<(PathBuf, String), Vec<AnalysisResult>> = HashMap::new();
+    for result in unidentified {
+        let stem = result.file_path.file_stem().map(|stem| stem.to_string_lossy().to_lowercase());
+        match (&result.exif_data, stem) {
+            (Some(_), Some(stem)) => {
+                let directory = result.file_path.parent().map(Path::to_path_buf).unwrap_or_default();
+                by_stem.entry((directory, stem)).or_default().push(result);
+            }
+            _ => shots.push(vec![result]),
+        }
+    }
+    for candidates in by_stem.into_values() {
+        shots.extend(split_shots(candidates, false));
+    }
+    shots
+}
+
+/// Split files that may belong together into shots, each extension at most once per shot
+///
+/// `identified` files share a content identifier and are paired regardless of time.
+fn split_shots(mut candidates: Vec<AnalysisResult>, identified: bool) -> Vec<Vec<AnalysisResult>> {
+    let extension = |result: &AnalysisResult| result.file_path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    fn exif(result: &AnalysisResult) -> &ExifData {
+        result.exif_data.as_ref().expect("shot candidates have EXIF data")
+    }
+    candidates.sort_by(|a, b| {
+        let (a_exif, b_exif) = (exif(a), exif(b));
+        b_exif.provenance.confidence.cmp(&a_exif.provenance.confidence)
+            .then(is_video_metadata(&a_exif._metadata).cmp(&is_video_metadata(&b_exif._metadata)))
+            .then((a_exif.nanoseconds == 0).cmp(&(b_exif.nanoseconds == 0)))
+            .then(a.file_path.cmp(&b.file_path))
+    });
+
+    let mut candidates = VecDeque::from(candidates);
+    let mut shots = Vec::new();
+    while let Some(leader) = candidates.pop_front() {
+        let mut shot = vec![leader];
+        let leader_time = exif(&shot[0]).timestamp;
+        let leader_is_video = is_video_metadata(&exif(&shot[0])._metadata);
+        let leader_id = live_photo_id(&shot[0]);
+        let mut rest = VecDeque::new();
+        for candidate in candidates.drain(..) {
+            let candidate_exif = exif(&candidate);
+            let tolerance = if is_video_metadata(&candidate_exif._metadata) != leader_is_video {
+                LIVE_PHOTO_TOLERANCE_SECS
+            } else {
+                SHOT_TOLERANCE_SECS
+            };
+            let same_time = identified
+                || (candidate_exif.timestamp - leader_time).num_seconds().abs() <= tolerance;
+            // Files with different identifiers are different Live Photos despite their names
+            let same_id = match (&leader_id, live_photo_id(&candidate)) {
+                (Some(leader_id), Some(candidate_id)) => *leader_id == candidate_id,
+                _ => true,
+            };
+            let new_extension = shot.iter().all(|member| extension(member) != extension(&candidate));
+            if same_time && same_id && new_extension {
+                shot.push(candidate);
+            } else {
+                rest.push_back(candidate);
+            }
+        }
+        candidates = rest;
+        shots.push(shot);
+    }
+    shots
+}
+
+/// Live Photo content identifier in the metadata of an analyzed file
+fn live_photo_id(result: &AnalysisResult) -> Option<String> {
+    let metadata = &result.exif_data.as_ref()?._metadata;
+    LIVE_PHOTO_ID_FIELDS.iter()
+        .filter_map(|field| metadata.get(*field))
+        .map(|value| value.trim_matches(|c: char| c == '\0' || c == '"' || c.is_whitespace()).to_uppercase())
+        .find(|value| !value.is_empty())
+}
+
+/// Split groups of file indices into smaller groups of files with equal `key`, dropping
+/// files that are alone in their group
+///
+/// Keys are computed in parallel; files whose key cannot be computed are left out.
+fn refine_groups(
+    groups: Vec<Vec<usize>>,
+    paths: &[PathBuf],
+    key: impl Fn(&Path) -> Result<String> + Sync,
+) -> Vec<Vec<usize>> {
+    let members: Vec<(usize, usize)> = groups.iter()
+        .enumerate()
+        .flat_map(|(group, indices)| indices.iter().map(move |index| (group, *index)))
+        .collect();
+    let keyed: Vec<((usize, String), usize)> = members.into_par_iter()
+        .filter_map(|(group, index)| match key(&paths[index]) {
+            Ok(key) => Some(((group, key), index)),
+            Err(e) => {
+                warn!("Failed to calculate hash for {}: {}", paths[index].display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let mut refined: HashMap<(usize, String), Vec<usize>> = HashMap::new();
+    for (key, index) in keyed {
+        refined.entry(key).or_default().push(index);
+    }
+    refined.into_values().filter(|indices| indices.len() > 1).collect()
+}
+
+/// Options controlling how the rename plan is applied
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// Run analysis, tie-breaking and duplicate detection but never touch any files
+    pub dry_run: bool,
+    /// Template used for target paths and their grouping directories
+    pub naming_template: NamingTemplate,
+    /// Whether template date/time tokens use local capture time or UTC
+    pub naming_time: NamingTime,
+    /// Timestamp extraction settings such as the default timezone
+    pub exif_options: ExifOptions,
+    /// Folder (relative to the output directory) for files dated by their modification time
+    pub mtime_folder: String,
+    /// Order in which files with the same target name receive tie-break suffixes
+    pub tie_break: TieBreakOrder,
+    /// Largest gap between frames of one burst; bursts are only numbered when set
+    pub burst_gap: Option<chrono::Duration>,
+    /// Largest gap between files of one event; events are only formed when set
+    pub event_gap: Option<chrono::Duration>,
+    /// How event folders are labeled
+    pub event_naming: EventNaming,
+    /// Whether duplicates must match byte for byte or only in their image data
+    pub hash_mode: HashMode,
+    /// Compare every input with every other input and the output tree, not only files
+    /// whose timestamps or target names collide
+    pub dedupe_all: bool,
 }
 
 pub struct FileProcessor {
@@ -91,10 +374,15 @@ pub struct FileProcessor {
     _exif_processor: ExifProcessor,
     filename_generator: FilenameGenerator,
     content_hasher: ContentHasher,
+    options: ProcessOptions,
+    journal: Option<Journal>,
+    sidecars: SidecarIndex,
+    /// Inputs found by `--dedupe-all` to duplicate another file, mapped to that file
+    duplicates: HashMap<PathBuf, PathBuf>,
 }
 
 impl FileProcessor {
-    pub fn new(workers: Option<usize>) -> Self {
+    pub fn new(workers: Option<usize>, options: ProcessOptions) -> Self {
         // Configure rayon thread pool for optimal I/O bound performance
         if let Some(worker_count) = workers {
             // For I/O bound workloads, use fewer threads than CPU cores to reduce contention
@@ -127,19 +415,48 @@ impl FileProcessor {
 
         Self {
             _workers: workers,
-            _exif_processor: ExifProcessor::new(),
-            filename_generator: FilenameGenerator::new(),
-            content_hasher: ContentHasher::new(),
+            _exif_processor: ExifProcessor::with_options(options.exif_options.clone()),
+            filename_generator: FilenameGenerator::new(options.naming_template.clone(), options.naming_time, &options.mtime_folder),
+            content_hasher: ContentHasher::with_mode(options.hash_mode),
+            options,
+            journal: None,
+            sidecars: SidecarIndex::default(),
+            duplicates: HashMap::new(),
         }
     }
 
     /// Process multiple files with parallel processing and progress tracking
     pub fn process_files(&mut self, files: Vec<PathBuf>, output_dir: &Path, mode: &str) -> Result<Vec<ProcessResult>> {
+        self.process_files_with_checkpoint(files, output_dir, mode, None)
+    }
+
+    /// Process files like `process_files`, resuming from and updating `checkpoint` when given
+    ///
+    /// Files already committed by an earlier run of the same invocation are skipped, and
+    /// cached analysis and hash results are reused for files that did not change since.
+    pub fn process_files_with_checkpoint(
+        &mut self,
+        mut files: Vec<PathBuf>,
+        output_dir: &Path,
+        mode: &str,
+        mut checkpoint: Option<&mut Checkpoint>,
+    ) -> Result<Vec<ProcessResult>> {
         info!("Processing {} files", files.len());
 
+        if !matches!(mode, "move" | "copy" | "symlink") {
+            anyhow::bail!("Invalid mode: {}. Must be 'move', 'copy', or 'symlink'", mode);
+        }
+
         // Convert output directory to absolute path to avoid issues with relative paths
         let output_dir = output_dir.canonicalize()
-            .or_else(|_| {
+            .or_else(|e| {
+                if self.options.dry_run {
+                    // Dry run must not create anything, so resolve the path without touching disk
+                    debug!("Output directory not available ({}), resolving without creating it", e);
+                    return std::env::current_dir()
+                        .map(|cwd| cwd.join(output_dir))
+                        .context("Failed to resolve output directory");
+                }
                 // If canonicalize fails (e.g., directory doesn't exist), create it and try again
                 fs::create_dir_all(output_dir)
                     .context("Failed to create output directory")?;
@@ -147,19 +464,116 @@ impl FileProcessor {
                     .context("Failed to canonicalize output directory")
             })?;
 
+        // Every operation of this run is journaled so it can be reversed with `undo`
+        if !self.options.dry_run {
+            let journal = Journal::open(&output_dir)?;
+            info!("Recording operations in journal as run {}", journal.run_id());
+            self.journal = Some(journal);
+        }
+
+        // Targets written by earlier runs of this invocation, grouped by target directory,
+        // so tie-breaking continues where the interrupted run stopped
+        let mut completed_targets: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+        if let Some(checkpoint) = checkpoint.as_deref_mut() {
+            let entries = Journal::load(&output_dir)?;
+            let completed: HashMap<&Path, &Path> = journal::pending_entries(&entries)
+                .into_iter()
+                .filter(|e| checkpoint.run_ids.contains(&e.run_id))
+                .map(|e| (e.source.as_path(), e.target.as_path()))
+                .collect();
+
+            let before = files.len();
+            files.retain(|file| !journal::absolute_path(file).is_ok_and(|source| completed.contains_key(source.as_path())));
+            info!("Skipping {} files already processed by an earlier run", before - files.len());
+
+            for target in completed.values() {
+                if let (Some(parent), Ok(relative)) = (target.parent(), target.strip_prefix(&output_dir)) {
+                    completed_targets.entry(parent.to_path_buf())
+                        .or_default()
+                        .push(relative.to_string_lossy().to_string());
+                }
+            }
+
+            if let Some(journal) = &self.journal {
+                checkpoint.run_ids.push(journal.run_id().to_string());
+            }
+            checkpoint.save()?;
+        }
+
+        // Sidecars (.xmp, .aae, .thm, ...) are not inputs themselves but follow their primary file
+        self.sidecars = SidecarIndex::build(&files);
+
         // First pass: Extract EXIF data and generate filenames in parallel
-        let analysis_results = self.analyze_files_parallel(files.clone())?;
+        let mut cached_results = Vec::new();
+        if let Some(checkpoint) = checkpoint.as_deref() {
+            files.retain(|file| match checkpoint.cached_analysis(file) {
+                Some(result) => {
+                    cached_results.push(result);
+                    false
+                }
+                None => true,
+            });
+            info!("Reusing cached analysis for {} files", cached_results.len());
+        }
+
+        // Results are checkpointed while the pass runs, so an interruption keeps most of them
+        let writer = checkpoint.as_deref_mut().map(CheckpointWriter::new);
+        let mut analysis_results = self.analyze_files_parallel(files, writer.as_ref())?;
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+        analysis_results.extend(cached_results);
+
+        // Number bursts and split events across all files, so the {burst} and {event} tokens
+        // can keep each one together
+        if let Some(gap) = self.options.burst_gap {
+            burst::assign_bursts(
+                analysis_results.iter_mut().filter_map(|AnalysisResult { file_path, exif_data, .. }| {
+                    Some((file_path.as_path(), exif_data.as_mut()?))
+                }),
+                gap,
+            );
+        }
+        if let Some(gap) = self.options.event_gap {
+            events::assign_events(
+                analysis_results.iter_mut().filter_map(|AnalysisResult { file_path, exif_data, .. }| {
+                    Some((file_path.as_path(), exif_data.as_mut()?))
+                }),
+                gap,
+                self.options.event_naming,
+            );
+        }
+
+        // With --dedupe-all, duplicates are found whatever their timestamps
+        if self.options.dedupe_all {
+            self.duplicates = self.find_global_duplicates(&analysis_results, &output_dir, checkpoint.as_deref());
+        }
 
         // Build content hash index for duplicate detection
-        let hash_index = self.build_content_hash_index(&analysis_results, &output_dir)?;
+        let hash_index = self.build_content_hash_index(&analysis_results, &output_dir, checkpoint.as_deref_mut())?;
 
         // Second pass: Handle file operations with parallel directory processing
-        let results = self.rename_files_parallel(analysis_results, &hash_index, &output_dir, mode)?;
+        let results = self.rename_files_parallel(analysis_results, &hash_index, &output_dir, mode, &completed_targets)?;
+
+        // The run is complete once every file went through the rename pass; files that failed
+        // (no date, unreadable) are listed in the summary and would fail again on a resume
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.remove()?;
+            let failed = results.iter().filter(|r| !r.success).count();
+            if failed > 0 {
+                info!("Removed checkpoint; {} files failed and are listed in the summary", failed);
+            }
+        }
 
         Ok(results)
     }
 
-    fn analyze_files_parallel(&mut self, files: Vec<PathBuf>) -> Result<Vec<AnalysisResult>> {
+    fn analyze_files_parallel(
+        &mut self,
+        files: Vec<PathBuf>,
+        checkpoint: Option<&CheckpointWriter>,
+    ) -> Result<Vec<AnalysisResult>> {
         let pb = ProgressBar::new(files.len() as u64);
         pb.set_style(
             ProgressStyle::default_bar()
@@ -175,13 +589,19 @@ impl FileProcessor {
         let pb = Arc::new(pb);
         
         // Create a new ExifProcessor for each thread to avoid borrowing issues
+        let filename_generator = &self.filename_generator;
+        let exif_options = &self.options.exif_options;
+        let checkpointed_fields = self.checkpointed_metadata_fields();
         let results: Vec<AnalysisResult> = files
             .par_chunks(chunk_size)
             .flat_map(|chunk| {
                 chunk.par_iter().map(|file_path| {
                     // Create a new processor for each thread
-                    let mut temp_processor = crate::exif::ExifProcessor::new();
-                    let result = temp_processor.analyze_single_file(file_path);
+                    let mut temp_processor = crate::exif::ExifProcessor::with_options(exif_options.clone());
+                    let result = temp_processor.analyze_single_file(file_path, filename_generator);
+                    if let Some(checkpoint) = checkpoint {
+                        checkpoint.store_analysis(&result.slimmed(&checkpointed_fields));
+                    }
                     pb.inc(1);
                     result
                 }).collect::<Vec<_>>()
@@ -192,6 +612,16 @@ impl FileProcessor {
         Ok(results)
     }
 
+    /// Metadata fields read after the analysis pass, the only ones a checkpoint keeps
+    fn checkpointed_metadata_fields(&self) -> HashSet<&str> {
+        self.filename_generator.metadata_fields().into_iter()
+            .chain(naming::SEQUENCE_FIELDS)
+            .chain(burst::CAMERA_FIELDS)
+            .chain(LIVE_PHOTO_ID_FIELDS)
+            .chain(exif::VIDEO_FIELDS)
+            .collect()
+    }
+
     fn _analyze_single_file(&mut self, file_path: &Path) -> AnalysisResult {
         // Skip symlinks
         if file_path.is_symlink() {
@@ -208,11 +638,10 @@ impl FileProcessor {
             Ok(exif_data) => {
                 let extension = self.get_file_extension(file_path);
                 debug!("Generated extension: '{}' for file: {}", extension, file_path.display());
-                debug!("EXIF timestamp: {} ({}ms)", exif_data.timestamp, exif_data.milliseconds);
+                debug!("EXIF timestamp: {} ({}ns)", exif_data.timestamp, exif_data.nanoseconds);
                 
                 let new_filename = self.filename_generator.generate_filename(
-                    exif_data.timestamp,
-                    exif_data.milliseconds,
+                    &exif_data,
                     &extension,
                     &[], // Will be updated with existing files later
                 );
@@ -243,6 +672,7 @@ impl FileProcessor {
         &self,
         analysis_results: &[AnalysisResult],
         output_dir: &Path,
+        checkpoint: Option<&mut Checkpoint>,
     ) -> Result<HashMap<PathBuf, String>> {
         let mut files_to_hash = Vec::new();
         let mut target_paths = HashMap::new();
@@ -256,7 +686,7 @@ impl FileProcessor {
                     target_paths.insert(result.file_path.clone(), target_path.clone());
                     
                     // Group by timestamp
-                    let timestamp_key = format!("{}_{}", exif_data.timestamp.timestamp(), exif_data.milliseconds);
+                    let timestamp_key = format!("{}_{}", exif_data.timestamp.timestamp(), exif_data.nanoseconds);
                     timestamp_groups.entry(timestamp_key).or_insert_with(Vec::new).push(result.file_path.clone());
                     
                     // Only hash files that would have collisions
@@ -282,6 +712,19 @@ impl FileProcessor {
             return Ok(HashMap::new());
         }
 
+        // Reuse hashes from an earlier run for files that did not change since
+        let mut cached_hashes = HashMap::new();
+        if let Some(checkpoint) = checkpoint.as_deref() {
+            files_to_hash.retain(|path| match checkpoint.cached_hash(path) {
+                Some(hash) => {
+                    cached_hashes.insert(path.clone(), hash);
+                    false
+                }
+                None => true,
+            });
+            debug!("Reusing {} cached hashes", cached_hashes.len());
+        }
+
         info!("Building hash index for {} potentially conflicting files", files_to_hash.len());
         
         let pb = ProgressBar::new(files_to_hash.len() as u64);
@@ -294,12 +737,16 @@ impl FileProcessor {
         pb.set_message("Building hash index");
 
         // Use parallel processing for hash calculation
-        let hash_results = Arc::new(Mutex::new(HashMap::new()));
+        let hash_results = Arc::new(Mutex::new(cached_hashes));
         let pb = Arc::new(pb);
+        let writer = checkpoint.map(CheckpointWriter::new);
 
         files_to_hash.par_iter().for_each(|file_path| {
             match self.content_hasher.calculate_file_hash(file_path) {
                 Ok(hash) => {
+                    if let Some(writer) = &writer {
+                        writer.store_hash(file_path, &hash);
+                    }
                     let mut hash_results = hash_results.lock().unwrap();
                     hash_results.insert(file_path.clone(), hash);
                 }
@@ -311,17 +758,108 @@ impl FileProcessor {
         });
 
         pb.finish_with_message("Hash index complete");
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
         
         let hash_index = Arc::try_unwrap(hash_results).unwrap().into_inner().unwrap();
         Ok(hash_index)
     }
 
+    /// Find inputs with the same content as another input or a file already in the output
+    /// tree, independent of timestamps and target names
+    ///
+    /// Files are narrowed down by size, then by a hash of their first and last 64 KiB, and
+    /// only the remaining candidates are hashed in full. In image-data hash mode sizes and
+    /// file ends differ with the metadata, so files are narrowed down by a hash of the first
+    /// 64 KiB of their image data instead. Inputs without a capture time take part too, so
+    /// a copy whose metadata could not be read is still found; symlinks do not.
+    ///
+    /// Of each set of equal files, one already in the output tree is kept, else an input
+    /// with a capture time, else the first by path; the other inputs map to it.
+    fn find_global_duplicates(
+        &self,
+        analysis_results: &[AnalysisResult],
+        output_dir: &Path,
+        checkpoint: Option<&Checkpoint>,
+    ) -> HashMap<PathBuf, PathBuf> {
+        let inputs: Vec<&AnalysisResult> = analysis_results.iter()
+            .filter(|result| !fs::symlink_metadata(&result.file_path).is_ok_and(|metadata| metadata.file_type().is_symlink()))
+            .collect();
+        let canonical_inputs: std::collections::HashSet<PathBuf> = inputs.iter()
+            .filter_map(|result| result.file_path.canonicalize().ok())
+            .collect();
+
+        // (path, already in the output tree, has a capture time); journal, checkpoint, trash
+        // and other hidden files of the output tree are not compared
+        let mut files: Vec<(PathBuf, bool, bool)> = inputs.iter()
+            .map(|result| (result.file_path.clone(), false, result.exif_data.is_some()))
+            .collect();
+        files.extend(WalkDir::new(output_dir)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .filter(|path| path.canonicalize().map_or(true, |canonical| !canonical_inputs.contains(&canonical)))
+            .map(|path| (path, true, true)));
+        info!("Looking for duplicates among {} inputs and {} files in the output tree",
+              inputs.len(), files.len() - inputs.len());
+
+        let paths: Vec<PathBuf> = files.iter().map(|(path, _, _)| path.clone()).collect();
+        let sizes: Vec<u64> = paths.iter().map(|path| fs::metadata(path).map_or(0, |metadata| metadata.len())).collect();
+        let full_hash = |path: &Path| match checkpoint.and_then(|checkpoint| checkpoint.cached_hash(path)) {
+            Some(hash) => Ok(hash),
+            None => self.content_hasher.calculate_file_hash(path),
+        };
+
+        let non_empty = (0..paths.len()).filter(|index| sizes[*index] > 0);
+        let groups = if self.options.hash_mode == HashMode::ImageData {
+            let by_partial = refine_groups(vec![non_empty.collect()], &paths, |path| {
+                self.content_hasher.calculate_partial_image_data_hash(path)
+            });
+            refine_groups(by_partial, &paths, full_hash)
+        } else {
+            let by_size = refine_groups(vec![non_empty.collect()], &paths, |path| {
+                Ok(fs::metadata(path)?.len().to_string())
+            });
+            let by_partial = refine_groups(by_size, &paths, |path| self.content_hasher.calculate_partial_hash(path));
+            // Partial hashes of small files already cover the whole file
+            let (complete, large): (Vec<_>, Vec<_>) = by_partial.into_iter()
+                .partition(|group| sizes[group[0]] <= 2 * PARTIAL_HASH_BYTES);
+            let mut groups = refine_groups(large, &paths, full_hash);
+            groups.extend(complete);
+            groups
+        };
+
+        let mut duplicates = HashMap::new();
+        let mut undated = 0;
+        for mut group in groups {
+            group.sort_by_key(|index| {
+                let (path, in_output, dated) = &files[*index];
+                (!in_output, !dated, path.clone())
+            });
+            let kept = &files[group[0]].0;
+            for index in &group[1..] {
+                let (path, in_output, dated) = &files[*index];
+                if !in_output {
+                    debug!("{} duplicates {}", path.display(), kept.display());
+                    duplicates.insert(path.clone(), kept.clone());
+                    undated += usize::from(!dated);
+                }
+            }
+        }
+        info!("Found {} inputs duplicating another file, {} of them without a capture time", duplicates.len(), undated);
+        duplicates
+    }
+
     fn rename_files_parallel(
         &self,
         analysis_results: Vec<AnalysisResult>,
         hash_index: &HashMap<PathBuf, String>,
         output_dir: &Path,
         mode: &str,
+        completed_targets: &HashMap<PathBuf, Vec<String>>,
     ) -> Result<Vec<ProcessResult>> {
         let pb = ProgressBar::new(analysis_results.len() as u64);
         pb.set_style(
@@ -332,25 +870,25 @@ impl FileProcessor {
         );
         pb.set_message("Renaming files");
 
-        // Group files by target directory to minimize conflicts
+        // Group files by target directory to minimize conflicts; files of one shot stay
+        // together and follow the directory of their first file
         let mut grouped_results = HashMap::new();
         let mut files_without_exif = Vec::new();
         
-        for result in analysis_results {
-            if let Some(exif_data) = &result.exif_data {
-                let target_dir = output_dir.join(format!("{}/{}", 
-                    exif_data.timestamp.format("%Y"), 
-                    exif_data.timestamp.format("%m-%b")));
-                grouped_results.entry(target_dir).or_insert_with(Vec::new).push(result);
+        for shot in group_shots(analysis_results) {
+            if let Some(exif_data) = &shot[0].exif_data {
+                let target_dir = output_dir.join(self.filename_generator.generate_directory(exif_data));
+                grouped_results.entry(target_dir).or_insert_with(Vec::new).push(shot);
             } else {
                 // Files without EXIF data (like symlinks) go to a special group
-                files_without_exif.push(result);
+                files_without_exif.push(shot);
             }
         }
         
         // Add files without EXIF data to a special group
+        // (extending rather than replacing, since a template without directories groups there too)
         if !files_without_exif.is_empty() {
-            grouped_results.insert(output_dir.to_path_buf(), files_without_exif);
+            grouped_results.entry(output_dir.to_path_buf()).or_insert_with(Vec::new).extend(files_without_exif);
         }
 
         // Process each directory group in parallel
@@ -363,23 +901,20 @@ impl FileProcessor {
         // Process directory groups in parallel
         let group_results: Vec<Vec<ProcessResult>> = grouped_results
             .into_par_iter()
-            .map(|(_target_dir, mut results)| {
-                // Sort results by file path to ensure consistent duplicate detection
-                results.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+            .map(|(target_dir, mut shots)| {
+                // Sort deterministically so tie-break suffixes follow shooting order and
+                // duplicate detection always keeps the same file
+                shots.sort_by(|a, b| self.options.tie_break.compare(
+                    (&a[0].file_path, a[0].exif_data.as_ref()),
+                    (&b[0].file_path, b[0].exif_data.as_ref()),
+                ));
                 
                 let mut group_results = Vec::new();
-                let mut existing_files = Vec::new();
+                let mut existing_files = completed_targets.get(&target_dir).cloned().unwrap_or_default();
                 
                 // Within each group, process files sequentially to avoid conflicts
-                for result in results {
-                    let process_result = self.process_single_file_rename(
-                        result,
-                        &hash_index,
-                        &output_dir,
-                        &mut existing_files,
-                        mode,
-                    );
-                    group_results.push(process_result);
+                for shot in shots {
+                    group_results.extend(self.process_shot(shot, &hash_index, &output_dir, &mut existing_files, mode));
                 }
                 group_results
             })
@@ -411,6 +946,138 @@ impl FileProcessor {
         Ok(all_results)
     }
 
+    /// Rename the files of one shot; several files (RAW+JPEG) share the base name of the first
+    ///
+    /// Whether the shot is skipped, a duplicate or blocked by a sidecar is decided once for
+    /// all of its files, so a shot is either renamed as a whole or left in place as a whole.
+    fn process_shot(
+        &self,
+        mut shot: Vec<AnalysisResult>,
+        hash_index: &HashMap<PathBuf, String>,
+        output_dir: &Path,
+        existing_files: &mut Vec<String>,
+        mode: &str,
+    ) -> Vec<ProcessResult> {
+        if shot.len() == 1 {
+            let result = shot.pop().unwrap();
+            return vec![self.process_single_file_rename(result, hash_index, output_dir, existing_files, mode)];
+        }
+
+        let paths: Vec<PathBuf> = shot.iter().map(|result| result.file_path.clone()).collect();
+        let leader_exif = shot[0].exif_data.clone().expect("shots of several files have EXIF data");
+        let extensions: Vec<String> = paths.iter().map(|path| self.get_file_extension(path)).collect();
+        let members: Vec<(&Path, &str)> = paths.iter().map(PathBuf::as_path).zip(extensions.iter().map(String::as_str)).collect();
+        let shared_name = SharedName {
+            exif_data: &leader_exif,
+            suffix: self.free_suffix(&leader_exif, &members, output_dir, existing_files),
+        };
+        debug!("Naming {} files of one shot together: {:?}", shot.len(), paths);
+
+        let held_back: Vec<Option<ProcessResult>> = shot.iter()
+            .map(|result| self.hold_back(result, hash_index, output_dir, existing_files, Some(&leader_exif)))
+            .collect();
+        let plans: Vec<RenamePlan> = shot.iter()
+            .map(|result| self.plan_rename(&result.file_path, &shared_name, output_dir))
+            .collect();
+        let blocked_sidecar = plans.iter()
+            .flat_map(|plan| &plan.sidecars)
+            .find(|(sidecar, sidecar_target)| sidecar_target != sidecar && sidecar_target.exists())
+            .map(|(_, sidecar_target)| sidecar_target.clone());
+
+        let results: Vec<ProcessResult> = if let Some(reason) = held_back.iter().flatten().next()
+            .map(|held| held.error.clone().unwrap_or_default())
+        {
+            shot.into_iter().zip(held_back)
+                .map(|(result, held)| held.unwrap_or_else(|| self.left_with_shot(result, &reason)))
+                .collect()
+        } else if let Some(occupied) = blocked_sidecar {
+            let reason = format!("Sidecar target already exists: {}", occupied.display());
+            shot.into_iter()
+                .map(|result| ProcessResult { success: false, ..self.left_with_shot(result, &reason) })
+                .collect()
+        } else {
+            shot.into_iter().zip(plans)
+                .map(|(result, plan)| self.apply_rename(result, plan, existing_files, mode))
+                .collect()
+        };
+
+        results.into_iter()
+            .map(|mut process_result| {
+                process_result.paired_with = paths.iter()
+                    .filter(|path| **path != process_result.file_path)
+                    .cloned()
+                    .collect();
+                process_result
+            })
+            .collect()
+    }
+
+    /// Move `file` and its sidecars into the trash of `output_dir`, returning the trash path
+    ///
+    /// Files in the output tree keep their relative path under `.sortify-trash/<run>`, others
+    /// their file name. The move is journaled with the run that sorted the files, so `undo`
+    /// first restores the trashed files and then reverses the sort.
+    pub fn move_to_trash(&self, file: &Path, output_dir: &Path) -> Result<PathBuf> {
+        let output_dir = output_dir.canonicalize()
+            .with_context(|| format!("Output directory not found: {}", output_dir.display()))?;
+        let run_id = self.journal.as_ref().map_or("unjournaled", |journal| journal.run_id());
+        let absolute = journal::absolute_path(file)?;
+        let relative = match absolute.strip_prefix(&output_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => PathBuf::from(absolute.file_name().unwrap_or_default()),
+        };
+        let target = output_dir.join(TRASH_DIR_NAME).join(run_id).join(relative);
+
+        let mut operations = vec![(file.to_path_buf(), target.clone())];
+        for sidecar in SidecarIndex::build(&[file.to_path_buf()]).sidecars_of(file) {
+            if let Some(sidecar_target) = sidecar::sidecar_target(sidecar, file, &target) {
+                operations.push((sidecar.clone(), sidecar_target));
+            }
+        }
+        perform_transaction(&operations, "move", self.journal.as_ref())?;
+        Ok(target)
+    }
+
+    /// Result of a file left in place because another file of its shot was
+    fn left_with_shot(&self, analysis_result: AnalysisResult, reason: &str) -> ProcessResult {
+        ProcessResult {
+            success: true,
+            renamed: false,
+            new_path: None,
+            error: Some(format!("Left in place with its shot - {}", reason)),
+            provenance: analysis_result.provenance().cloned(),
+            conflicts: analysis_result.exif_data.map(|exif_data| exif_data.conflicts).unwrap_or_default(),
+            file_path: analysis_result.file_path,
+            ..Default::default()
+        }
+    }
+
+    /// Tie-breaking suffix under which every file of a shot gets a name that was neither
+    /// assigned earlier in this run nor exists in the output directory
+    ///
+    /// `files` are the source paths and extensions of the shot. The names their sidecars
+    /// would take count as well, so a leftover `.xmp` moves the whole shot to the next
+    /// suffix instead of blocking it. A file already sitting at its target does not block
+    /// its own name.
+    fn free_suffix(&self, exif_data: &ExifData, files: &[(&Path, &str)], output_dir: &Path, existing_files: &[String]) -> Option<u32> {
+        let extensions: Vec<&str> = files.iter().map(|(_, extension)| *extension).collect();
+        self.filename_generator.free_suffix(exif_data, &extensions, |extension, name| {
+            if existing_files.iter().any(|existing| existing == name) {
+                return true;
+            }
+            let target_path = output_dir.join(name);
+            files.iter()
+                .filter(|(_, file_extension)| *file_extension == extension)
+                .any(|(source, _)| {
+                    (*source != target_path && occupied(&target_path))
+                        || self.sidecars.sidecars_of(source).iter().any(|sidecar| {
+                            sidecar::sidecar_target(sidecar, source, &target_path)
+                                .is_some_and(|sidecar_target| sidecar_target != *sidecar && occupied(&sidecar_target))
+                        })
+                })
+        })
+    }
+
     fn process_single_file_rename(
         &self,
         analysis_result: AnalysisResult,
@@ -419,99 +1086,183 @@ impl FileProcessor {
         existing_files: &mut Vec<String>,
         mode: &str,
     ) -> ProcessResult {
-        if !analysis_result.success {
-            return ProcessResult {
-                file_path: analysis_result.file_path,
-                success: false,
-                renamed: false,
-                _new_path: None,
-                error: analysis_result.error,
-            };
+        if let Some(held) = self.hold_back(&analysis_result, hash_index, output_dir, existing_files, None) {
+            return held;
         }
-
-        let (exif_data, _new_filename) = match (analysis_result.exif_data, analysis_result.new_filename) {
-            (Some(exif_data), Some(filename)) => (exif_data, filename),
+        let exif_data = match &analysis_result.exif_data {
+            Some(exif_data) if analysis_result.new_filename.is_some() => exif_data,
             _ => {
                 // Files without EXIF data (like symlinks) should be skipped, not treated as errors
                 return ProcessResult {
                     file_path: analysis_result.file_path,
                     success: true,
                     renamed: false,
-                    _new_path: None,
+                    new_path: None,
                     error: analysis_result.error,
+                    ..Default::default()
                 };
             }
         };
 
+        // Generate final filename with tie-breaking; names of earlier runs are never reused
+        let extension = self.get_file_extension(&analysis_result.file_path);
+        let name = SharedName {
+            exif_data,
+            suffix: self.free_suffix(exif_data, &[(&analysis_result.file_path, &extension)], output_dir, existing_files),
+        };
+        let plan = self.plan_rename(&analysis_result.file_path, &name, output_dir);
+        self.apply_rename(analysis_result, plan, existing_files, mode)
+    }
+
+    /// Result of a file that is not renamed at all: failed analysis, a duplicate of
+    /// another file or conflicting timestamps under the skip policy
+    fn hold_back(
+        &self,
+        analysis_result: &AnalysisResult,
+        hash_index: &HashMap<PathBuf, String>,
+        output_dir: &Path,
+        existing_files: &[String],
+        name_exif: Option<&ExifData>,
+    ) -> Option<ProcessResult> {
+        let file_path = analysis_result.file_path.clone();
+        let provenance = analysis_result.provenance().cloned();
+        let duplicate = |original: PathBuf, conflicts: Vec<TimestampCandidate>| Some(ProcessResult {
+            file_path: analysis_result.file_path.clone(),
+            success: true,
+            renamed: false,
+            new_path: None,
+            error: Some("Content duplicate - file already exists with same content (safe to delete)".to_string()),
+            duplicate_of: Some(original),
+            provenance: provenance.clone(),
+            conflicts,
+            ..Default::default()
+        });
+
+        // Found by --dedupe-all, whatever its timestamp or whether it has one; a file whose
+        // analysis failed is reported as a duplicate rather than as an error
+        if let Some(original) = self.duplicates.get(&analysis_result.file_path) {
+            let error = match analysis_result.exif_data {
+                Some(_) => "Content duplicate - file already exists with same content (safe to delete)",
+                None => "Content duplicate without a capture time - another file has the same content (safe to delete)",
+            };
+            return Some(ProcessResult {
+                file_path,
+                success: true,
+                renamed: false,
+                new_path: None,
+                error: Some(error.to_string()),
+                duplicate_of: Some(original.clone()),
+                provenance,
+                ..Default::default()
+            });
+        }
+
+        if !analysis_result.success {
+            return Some(ProcessResult {
+                file_path,
+                success: false,
+                renamed: false,
+                new_path: None,
+                error: analysis_result.error.clone(),
+                ..Default::default()
+            });
+        }
+
+        // Files without EXIF data are skipped by the caller
+        let exif_data = analysis_result.exif_data.as_ref().filter(|_| analysis_result.new_filename.is_some())?;
+        let conflicts = exif_data.conflicts.clone();
+
+        // Disagreeing timestamps are left for the user to resolve under the skip policy
+        if !conflicts.is_empty() && self.options.exif_options.conflict_policy == ConflictPolicy::Skip {
+            return Some(ProcessResult {
+                file_path,
+                success: true,
+                renamed: false,
+                new_path: None,
+                error: Some("Conflicting timestamps - metadata fields disagree (--conflict-policy skip)".to_string()),
+                provenance,
+                conflicts,
+                ..Default::default()
+            });
+        }
+
         // Check for content duplicates BEFORE generating filename
         // This prevents tie-breaking from creating different paths for identical content
-        if let Some(input_hash) = hash_index.get(&analysis_result.file_path) {
+        if let Some(input_hash) = hash_index.get(&file_path) {
             for (existing_path, existing_hash) in hash_index {
-                if existing_hash == input_hash && existing_path != &analysis_result.file_path {
+                if existing_hash == input_hash && existing_path != &file_path {
                     // Found a content duplicate - check if the existing file has already been processed
                     // Since we process files sequentially within each group, we can check if the existing
                     // file has already been processed by looking at existing_files
                     let existing_filename = self.filename_generator.generate_filename(
-                        exif_data.timestamp,
-                        exif_data.milliseconds,
+                        exif_data,
                         &self.get_file_extension(existing_path),
                         &[], // Don't check existing files for this lookup
                     );
                     
                     if existing_files.contains(&existing_filename) {
                         // The existing file has already been processed, so this one is a duplicate
-                        return ProcessResult {
-                            file_path: analysis_result.file_path,
-                            success: true,
-                            renamed: false,
-                            _new_path: None,
-                            error: Some("Content duplicate - file already exists with same content (safe to delete)".to_string()),
-                        };
+                        return duplicate(existing_path.clone(), conflicts);
                     }
                 }
             }
         }
 
-        // Generate final filename with tie-breaking
-        let final_filename = self.filename_generator.generate_filename(
-            exif_data.timestamp,
-            exif_data.milliseconds,
-            &self.get_file_extension(&analysis_result.file_path),
-            existing_files,
-        );
-
-        let target_path = output_dir.join(&final_filename);
-
-        // Check for content duplicates at target location (fallback)
-        if target_path.exists() {
-            if let (Some(input_hash), Some(existing_hash)) = (
-                hash_index.get(&analysis_result.file_path),
-                hash_index.get(&target_path),
-            ) {
+        // Check for content duplicates at the target location without suffix (fallback)
+        let extension = self.get_file_extension(&file_path);
+        let name_exif = name_exif.unwrap_or(exif_data);
+        let plain_target = output_dir.join(self.filename_generator.generate_filename_with_fixed_suffix(name_exif, &extension, None));
+        if plain_target != file_path && plain_target.exists() {
+            if let (Some(input_hash), Some(existing_hash)) = (hash_index.get(&file_path), hash_index.get(&plain_target)) {
                 if input_hash == existing_hash {
-                    return ProcessResult {
-                        file_path: analysis_result.file_path,
-                        success: true,
-                        renamed: false,
-                        _new_path: None,
-                        error: Some("Content duplicate - file already exists with same content (safe to delete)".to_string()),
-                    };
+                    return duplicate(plain_target, conflicts);
                 }
                 // If hashes are different, continue with renaming (will add suffix)
             }
             // If no hash info available, continue with renaming (will add suffix)
         }
+        None
+    }
+
+    /// Target of a file and its sidecars under `name`
+    fn plan_rename(&self, file_path: &Path, name: &SharedName, output_dir: &Path) -> RenamePlan {
+        let extension = self.get_file_extension(file_path);
+        let final_filename = self.filename_generator.generate_filename_with_fixed_suffix(name.exif_data, &extension, name.suffix);
+        let target_path = output_dir.join(&final_filename);
+        let sidecars = self.sidecars.sidecars_of(file_path).iter()
+            .filter_map(|sidecar| sidecar::sidecar_target(sidecar, file_path, &target_path)
+                .map(|sidecar_target| (sidecar.clone(), sidecar_target)))
+            .collect();
+        RenamePlan { final_filename, target_path, tie_break_suffix: name.suffix, sidecars }
+    }
+
+    /// Move, copy or link a file and its sidecars as planned
+    fn apply_rename(
+        &self,
+        analysis_result: AnalysisResult,
+        plan: RenamePlan,
+        existing_files: &mut Vec<String>,
+        mode: &str,
+    ) -> ProcessResult {
+        let provenance = analysis_result.provenance().cloned();
+        let conflicts = analysis_result.exif_data.map(|exif_data| exif_data.conflicts).unwrap_or_default();
+        let RenamePlan { final_filename, target_path, tie_break_suffix, sidecars } = plan;
 
         // Create directory structure
         if let Some(parent) = target_path.parent() {
-            if let Err(e) = fs::create_dir_all(parent) {
-                return ProcessResult {
-                    file_path: analysis_result.file_path,
-                    success: false,
-                    renamed: false,
-                    _new_path: None,
-                    error: Some(format!("Failed to create directory: {}", e)),
-                };
+            if !self.options.dry_run {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    return ProcessResult {
+                        file_path: analysis_result.file_path,
+                        success: false,
+                        renamed: false,
+                        new_path: None,
+                        error: Some(format!("Failed to create directory: {}", e)),
+                        provenance,
+                        conflicts,
+                        ..Default::default()
+                    };
+                }
             }
         }
 
@@ -521,21 +1272,64 @@ impl FileProcessor {
                 file_path: analysis_result.file_path,
                 success: true,
                 renamed: false,
-                _new_path: None,
+                new_path: None,
                 error: Some("No rename needed - file already in correct location".to_string()),
+                provenance,
+                conflicts,
+                ..Default::default()
+            };
+        }
+
+        // Suffixes are picked with sidecar names taken into account, so this only catches a
+        // sidecar target that appeared meanwhile; it must not be overwritten
+        if let Some((_, occupied)) = sidecars.iter().find(|(_, sidecar_target)| sidecar_target.exists()) {
+            return ProcessResult {
+                file_path: analysis_result.file_path,
+                success: false,
+                renamed: false,
+                new_path: None,
+                error: Some(format!("Sidecar target already exists: {}", occupied.display())),
+                provenance,
+                conflicts,
+                ..Default::default()
             };
         }
 
-        // Perform file operation based on mode
-        match perform_file_operation(&analysis_result.file_path, &target_path, mode) {
+        // In dry-run mode the plan is recorded exactly as a real run would apply it
+        if self.options.dry_run {
+            existing_files.push(final_filename);
+            return ProcessResult {
+                file_path: analysis_result.file_path,
+                success: true,
+                renamed: true,
+                new_path: Some(target_path),
+                error: None,
+                tie_break_suffix,
+                provenance,
+                conflicts,
+                sidecars,
+                ..Default::default()
+            };
+        }
+
+        // Perform file operation based on mode, for the file and its sidecars together
+        let operations: Vec<(PathBuf, PathBuf)> = std::iter::once((analysis_result.file_path.clone(), target_path.clone()))
+            .chain(sidecars.iter().cloned())
+            .collect();
+        match perform_transaction(&operations, mode, self.journal.as_ref()) {
             Ok(_) => {
                 existing_files.push(final_filename);
                 ProcessResult {
                     file_path: analysis_result.file_path,
                     success: true,
                     renamed: true,
-                    _new_path: Some(target_path),
+                    new_path: Some(target_path),
                     error: None,
+                    tie_break_suffix,
+                    provenance,
+                    conflicts,
+                    sidecars,
+                    ..Default::default()
                 }
             }
             Err(e) => {
@@ -543,8 +1337,11 @@ impl FileProcessor {
                     file_path: analysis_result.file_path,
                     success: false,
                     renamed: false,
-                    _new_path: None,
+                    new_path: None,
                     error: Some(format!("Failed to {} file: {}", mode, e)),
+                    provenance,
+                    conflicts,
+                    ..Default::default()
                 }
             }
         }
@@ -563,12 +1360,12 @@ impl FileProcessor {
                     _ => ext,
                 }
             })
-            .unwrap_or_else(|| "".to_string())
+            .unwrap_or_default()
     }
 
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnalysisResult {
     pub file_path: PathBuf,
     pub success: bool,
@@ -576,3 +1373,380 @@ pub struct AnalysisResult {
     pub exif_data: Option<ExifData>,
     pub new_filename: Option<String>,
 }
+
+impl AnalysisResult {
+    /// Source and confidence of the extracted timestamp, if there is one
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.exif_data.as_ref().map(|exif_data| &exif_data.provenance)
+    }
+
+    /// Copy that keeps only the given metadata fields, small enough to cache in a checkpoint
+    pub fn slimmed(&self, fields: &HashSet<&str>) -> Self {
+        let mut result = self.clone();
+        if let Some(exif_data) = result.exif_data.as_mut() {
+            exif_data._metadata.retain(|field, _| fields.contains(field.as_str()));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn analyzed(processor: &FileProcessor, path: &Path, exif_data: ExifData) -> AnalysisResult {
+        let extension = processor.get_file_extension(path);
+        AnalysisResult {
+            file_path: path.to_path_buf(),
+            success: true,
+            error: None,
+            new_filename: Some(processor.filename_generator.generate_filename(&exif_data, &extension, &[])),
+            exif_data: Some(exif_data),
+        }
+    }
+
+    fn rename(processor: &FileProcessor, results: Vec<AnalysisResult>, output_dir: &Path) -> Vec<ProcessResult> {
+        let hash_index = processor.build_content_hash_index(&results, output_dir, None).unwrap();
+        processor.rename_files_parallel(results, &hash_index, output_dir, "move", &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn transactions_never_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, target) = (dir.path().join("a.jpg"), dir.path().join("b.jpg"));
+        write(&source, "new");
+        write(&target, "old");
+
+        let error = perform_transaction(&[(source.clone(), target.clone())], "copy", None).unwrap_err();
+        assert!(error.to_string().contains("already exists"), "{}", error);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn dry_run_leaves_the_filesystem_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        write(&output_dir.join("2024/01-Jan/20240101_100000.000.jpg"), "earlier run");
+        let photo = dir.path().join("in/IMG_20240101_100000.jpg");
+        write(&photo, "photo");
+        write(&dir.path().join("in/IMG_20240101_100000.xmp"), "sidecar");
+        let snapshot = || -> Vec<(PathBuf, Option<String>)> {
+            WalkDir::new(dir.path()).sort_by_file_name().into_iter()
+                .map(|entry| entry.unwrap().into_path())
+                .map(|path| (path.clone(), fs::read_to_string(&path).ok()))
+                .collect()
+        };
+        let before = snapshot();
+
+        let mut options = ProcessOptions { dry_run: true, ..Default::default() };
+        options.exif_options.fallbacks = vec![crate::exif::TimestampFallback::Filename];
+        let mut processor = FileProcessor::new(Some(1), options);
+        let results = processor.process_files(vec![photo.clone()], &output_dir, "move").unwrap();
+
+        assert_eq!(snapshot(), before, "no move, journal, checkpoint or trash entry");
+        assert!(results[0].renamed, "{:?}", results[0]);
+        let target = output_dir.canonicalize().unwrap().join("2024/01-Jan/20240101_100000.000-2.jpg");
+        assert_eq!(results[0].new_path.as_deref(), Some(target.as_path()));
+        assert_eq!(results[0].sidecars.len(), 1);
+    }
+
+    #[test]
+    fn checkpointed_analyses_keep_only_fields_read_later() {
+        let options = ProcessOptions {
+            naming_template: NamingTemplate::parse("{year}/{lens}_{meta:ISO}_{date}{suffix}.{ext}").unwrap(),
+            ..Default::default()
+        };
+        let processor = FileProcessor::new(Some(1), options);
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[
+            ("LensModel", "RF50"), ("ISO", "100"), ("Model", "R5"), ("ContentIdentifier", "ABC"),
+            ("MediaCreateDate", "2024:01:01 10:00:00"), ("MakerNote", "large blob"), ("FNumber", "1.8"),
+        ]);
+        let result = analyzed(&processor, Path::new("a.jpg"), exif_data);
+
+        let slimmed = result.slimmed(&processor.checkpointed_metadata_fields());
+        let mut fields: Vec<_> = slimmed.exif_data.as_ref().unwrap()._metadata.keys().map(String::as_str).collect();
+        fields.sort_unstable();
+        assert_eq!(fields, ["ContentIdentifier", "ISO", "LensModel", "MediaCreateDate", "Model"]);
+        assert_eq!(slimmed.provenance(), result.provenance());
+        assert_eq!(slimmed.new_filename, result.new_filename);
+    }
+
+    #[test]
+    fn results_carry_the_provenance_of_fallback_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let named = dir.path().join("in/IMG_20240101_100000.jpg");
+        let scanned = dir.path().join("in/scan.jpg");
+        write(&named, "photo");
+        write(&scanned, "scan");
+        fs::File::options().write(true).open(&scanned).unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000))
+            .unwrap();
+
+        let mut options = ProcessOptions { dry_run: true, ..Default::default() };
+        options.exif_options.fallbacks = vec![crate::exif::TimestampFallback::Filename, crate::exif::TimestampFallback::Mtime];
+        options.exif_options.default_timezone = Some("UTC".parse().unwrap());
+        let mut processor = FileProcessor::new(Some(1), options);
+        let results = processor.process_files(vec![named.clone(), scanned.clone()], &dir.path().join("out"), "move").unwrap();
+
+        let provenance = |path: &Path| {
+            let result = results.iter().find(|r| r.file_path == path).unwrap();
+            let provenance = result.provenance.clone().unwrap();
+            (provenance.source, provenance.field, result.new_path.as_ref().unwrap().strip_prefix(dir.path().canonicalize().unwrap()).unwrap().to_path_buf())
+        };
+        assert_eq!(provenance(&named), (crate::exif::TimestampSource::Filename, "date-time".to_string(), PathBuf::from("out/2024/01-Jan/20240101_100000.000.jpg")));
+        assert_eq!(provenance(&scanned), (crate::exif::TimestampSource::Mtime, "FileModifyDate".to_string(), PathBuf::from("out/unsorted-by-mtime/2023/11-Nov/20231114_221320.000.jpg")));
+    }
+
+    /// Analyzed file at `path` captured at `time` (RFC 3339) with the given metadata fields
+    fn shot_candidate(path: &str, time: &str, metadata: &[(&str, &str)]) -> AnalysisResult {
+        AnalysisResult {
+            file_path: PathBuf::from(path),
+            success: true,
+            error: None,
+            exif_data: Some(ExifData::for_test(time, metadata)),
+            new_filename: None,
+        }
+    }
+
+    /// Paths of each shot, leader first, shots ordered by leader
+    fn shot_paths(shots: Vec<Vec<AnalysisResult>>) -> Vec<Vec<String>> {
+        let mut paths: Vec<Vec<String>> = shots.into_iter()
+            .map(|shot| shot.into_iter().map(|result| result.file_path.to_string_lossy().to_string()).collect())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn live_photo_halves_pair_by_content_identifier() {
+        let video = |path: &str, time: &str, id: Option<&str>| {
+            let mut fields = vec![("MediaCreateDate", "2024:01:01 10:00:00")];
+            fields.extend(id.map(|id| ("com.apple.quicktime.content.identifier", id)));
+            shot_candidate(path, time, &fields)
+        };
+        let still = |path: &str, time: &str, id: Option<&str>| {
+            shot_candidate(path, time, &id.map(|id| vec![("ContentIdentifier", id)]).unwrap_or_default())
+        };
+
+        // Matching identifiers pair a HEIC and a MOV whatever their names and times
+        let paired = group_shots(vec![
+            video("b/clip.mov", "2024-01-01T10:00:10Z", Some(" abc-1\0")),
+            still("a/IMG_0001.heic", "2024-01-01T10:00:00Z", Some("ABC-1")),
+        ]);
+        assert_eq!(shot_paths(paired), [["a/IMG_0001.heic", "b/clip.mov"]]);
+
+        // Different identifiers keep files with the same name apart
+        let mismatched = group_shots(vec![
+            still("IMG_0002.heic", "2024-01-01T10:00:00Z", Some("ABC-2")),
+            video("IMG_0002.mov", "2024-01-01T10:00:00Z", Some("XYZ-9")),
+        ]);
+        assert_eq!(shot_paths(mismatched), [["IMG_0002.heic"], ["IMG_0002.mov"]]);
+
+        // Without identifiers the name pairs them when the video starts up to 3 seconds earlier
+        let unidentified = group_shots(vec![
+            still("IMG_0003.heic", "2024-01-01T10:00:03Z", None),
+            video("IMG_0003.mov", "2024-01-01T10:00:00Z", None),
+            still("IMG_0004.heic", "2024-01-01T10:00:05Z", Some("ABC-4")),
+            video("IMG_0004.mov", "2024-01-01T10:00:00Z", None),
+        ]);
+        assert_eq!(
+            shot_paths(unidentified),
+            [vec!["IMG_0003.heic", "IMG_0003.mov"], vec!["IMG_0004.heic"], vec!["IMG_0004.mov"]],
+        );
+    }
+
+    #[test]
+    fn names_of_earlier_runs_get_the_next_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let earlier = output_dir.join("2024/01-Jan/20240101_100000.000.jpg");
+        write(&earlier, "earlier run");
+        let source = dir.path().join("in/IMG_0001.jpg");
+        write(&source, "this run");
+
+        let processor = FileProcessor::new(Some(1), ProcessOptions::default());
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let results = rename(&processor, vec![analyzed(&processor, &source, exif_data)], &output_dir);
+
+        assert_eq!(results[0].tie_break_suffix, Some(2));
+        assert_eq!(fs::read_to_string(&earlier).unwrap(), "earlier run");
+        assert_eq!(fs::read_to_string(output_dir.join("2024/01-Jan/20240101_100000.000-2.jpg")).unwrap(), "this run");
+    }
+
+    #[test]
+    fn identical_file_at_the_target_is_a_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let earlier = output_dir.join("2024/01-Jan/20240101_100000.000.jpg");
+        write(&earlier, "same");
+        let source = dir.path().join("in/IMG_0001.jpg");
+        write(&source, "same");
+
+        let processor = FileProcessor::new(Some(1), ProcessOptions::default());
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let results = rename(&processor, vec![analyzed(&processor, &source, exif_data)], &output_dir);
+
+        assert_eq!(results[0].duplicate_of.as_deref(), Some(earlier.as_path()));
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn leftover_sidecar_moves_the_file_to_the_next_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let leftover = output_dir.join("2024/01-Jan/20240101_100000.000.xmp");
+        write(&leftover, "leftover");
+        let source = dir.path().join("in/IMG_0001.jpg");
+        write(&source, "photo");
+        write(&dir.path().join("in/IMG_0001.xmp"), "sidecar");
+
+        let mut processor = FileProcessor::new(Some(1), ProcessOptions::default());
+        processor.sidecars = SidecarIndex::build(std::slice::from_ref(&source));
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let results = rename(&processor, vec![analyzed(&processor, &source, exif_data)], &output_dir);
+
+        assert!(results[0].renamed, "{:?}", results[0].error);
+        assert_eq!(results[0].tie_break_suffix, Some(2));
+        assert_eq!(fs::read_to_string(&leftover).unwrap(), "leftover");
+        assert_eq!(fs::read_to_string(output_dir.join("2024/01-Jan/20240101_100000.000-2.xmp")).unwrap(), "sidecar");
+    }
+
+    #[test]
+    fn shots_are_held_back_as_a_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let earlier = output_dir.join("2024/01-Jan/20240101_100000.000.jpg");
+        write(&earlier, "jpeg");
+        let (raw, jpeg) = (dir.path().join("in/IMG_0001.cr3"), dir.path().join("in/IMG_0001.jpg"));
+        write(&raw, "raw");
+        write(&jpeg, "jpeg");
+
+        let processor = FileProcessor::new(Some(1), ProcessOptions::default());
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let results = rename(&processor, vec![
+            analyzed(&processor, &raw, exif_data.clone()),
+            analyzed(&processor, &jpeg, exif_data),
+        ], &output_dir);
+
+        let result = |path: &Path| results.iter().find(|result| result.file_path == path).unwrap();
+        assert_eq!(result(&jpeg).duplicate_of.as_deref(), Some(earlier.as_path()));
+        assert!(!result(&raw).renamed);
+        assert!(result(&raw).error.as_deref().unwrap().starts_with("Left in place with its shot"));
+        assert_eq!(result(&raw).paired_with, vec![jpeg.clone()]);
+        assert!(raw.exists() && jpeg.exists());
+    }
+
+    #[test]
+    fn conflicts_under_skip_policy_hold_back_the_whole_shot() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let (raw, jpeg) = (dir.path().join("in/IMG_0001.cr3"), dir.path().join("in/IMG_0001.jpg"));
+        write(&raw, "raw");
+        write(&jpeg, "jpeg");
+
+        let mut options = ProcessOptions::default();
+        options.exif_options.conflict_policy = ConflictPolicy::Skip;
+        let processor = FileProcessor::new(Some(1), options);
+        let mut conflicting = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        conflicting.conflicts = ["DateTimeOriginal", "CreateDate"].iter()
+            .map(|field| TimestampCandidate { field: field.to_string(), timestamp: conflicting.timestamp })
+            .collect();
+        let results = rename(&processor, vec![
+            analyzed(&processor, &raw, ExifData::for_test("2024-01-01T10:00:00Z", &[])),
+            analyzed(&processor, &jpeg, conflicting),
+        ], &output_dir);
+
+        assert!(results.iter().all(|result| !result.renamed), "{:?}", results);
+        assert!(raw.exists() && jpeg.exists());
+    }
+
+    #[test]
+    fn trashed_files_and_sidecars_are_restored_by_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().canonicalize().unwrap().join("out");
+        let file = output_dir.join("2024/01-Jan/20240101_100000.000-2.jpg");
+        let sidecar = output_dir.join("2024/01-Jan/20240101_100000.000-2.xmp");
+        write(&file, "near duplicate");
+        write(&sidecar, "sidecar");
+
+        let mut processor = FileProcessor::new(Some(1), ProcessOptions::default());
+        processor.journal = Some(Journal::open(&output_dir).unwrap());
+        let run_id = processor.journal.as_ref().unwrap().run_id().to_string();
+        let trashed = processor.move_to_trash(&file, &output_dir).unwrap();
+
+        let trash = output_dir.join(TRASH_DIR_NAME).join(&run_id).join("2024/01-Jan");
+        assert_eq!(trashed, trash.join("20240101_100000.000-2.jpg"));
+        assert_eq!(fs::read_to_string(trash.join("20240101_100000.000-2.xmp")).unwrap(), "sidecar");
+        assert!(!file.exists() && !sidecar.exists());
+
+        drop(processor);
+        let (_, results) = journal::undo_run(&output_dir, None).unwrap();
+        assert!(results.iter().all(|result| result.undone), "{:?}", results);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "near duplicate");
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), "sidecar");
+    }
+
+    #[test]
+    fn global_duplicates_include_undated_inputs_but_not_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let (dated, undated, link) = (dir.path().join("in/a.jpg"), dir.path().join("in/b.jpg"), dir.path().join("in/c.jpg"));
+        write(&dated, "same");
+        write(&undated, "same");
+        std::os::unix::fs::symlink(&dated, &link).unwrap();
+
+        let processor = FileProcessor::new(Some(1), ProcessOptions { dedupe_all: true, ..Default::default() });
+        let failed = |path: &Path| AnalysisResult {
+            file_path: path.to_path_buf(),
+            success: false,
+            error: Some("No EXIF data".to_string()),
+            exif_data: None,
+            new_filename: None,
+        };
+        let results = vec![
+            failed(&undated),
+            analyzed(&processor, &dated, ExifData::for_test("2024-01-01T10:00:00Z", &[])),
+            failed(&link),
+        ];
+        let duplicates = processor.find_global_duplicates(&results, &output_dir, None);
+        assert_eq!(duplicates, HashMap::from([(undated.clone(), dated.clone())]));
+
+        let mut processor = processor;
+        processor.duplicates = duplicates;
+        let held = processor.hold_back(&results[0], &HashMap::new(), &output_dir, &[], None).unwrap();
+        assert!(held.success);
+        assert_eq!(held.duplicate_of.as_deref(), Some(dated.as_path()));
+        assert!(held.error.unwrap().contains("without a capture time"));
+    }
+
+    #[test]
+    fn image_data_duplicates_are_found_despite_different_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let earlier = output_dir.join("2024/01-Jan/20240101_100000.000.jpg");
+        let source = dir.path().join("in/IMG_0001.jpg");
+        let jpeg = |exif: &[u8]| {
+            let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, exif.len() as u8 + 2];
+            bytes.extend(exif);
+            bytes.extend([0xFF, 0xDA, 0x00, 0x03, 0x00, 0x12, 0x34, 0xFF, 0xD9]);
+            bytes
+        };
+        fs::create_dir_all(earlier.parent().unwrap()).unwrap();
+        fs::write(&earlier, jpeg(b"Exif sorted earlier")).unwrap();
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, jpeg(b"Exif edited")).unwrap();
+
+        let options = ProcessOptions { dedupe_all: true, hash_mode: HashMode::ImageData, ..Default::default() };
+        let processor = FileProcessor::new(Some(1), options);
+        let results = vec![analyzed(&processor, &source, ExifData::for_test("2024-05-01T10:00:00Z", &[]))];
+        let duplicates = processor.find_global_duplicates(&results, &output_dir, None);
+        assert_eq!(duplicates, HashMap::from([(source, earlier)]));
+    }
+}
@@ -0,0 +1,182 @@
+/*!
+ * Filename date extraction module
+ *
+ * Screenshots, messenger images and scans often carry no EXIF timestamp but encode
+ * the capture time in their file name (`IMG-20230412-WA0003.jpg`,
+ * `PXL_20240101_123456789.jpg`, `Screenshot_2024-05-01-10-22-33.png`). Patterns are
+ * regular expressions with named groups `year`, `month`, `day` and optionally
+ * `hour`, `minute`, `second`, `ms` and `meridiem` (`AM`/`PM` for 12-hour clocks).
+ */
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use log::debug;
+use regex::{Captures, Regex};
+use std::path::Path;
+
+use crate::exif::{parse_subseconds, ParsedTimestamp};
+
+/// Built-in patterns as (name, regex, time is UTC), most specific first
+const BUILTIN_PATTERNS: [(&str, &str, bool); 7] = [
+    // Google Pixel names use UTC: PXL_20240101_123456789.jpg
+    ("pixel", r"PXL_(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})_(?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})(?P<ms>\d{3})", true),
+    // WhatsApp only records the date: IMG-20230412-WA0003.jpg, VID-20230412-WA0003.mp4
+    ("whatsapp", r"(?:IMG|VID|AUD|PTT|STK)-(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})-WA\d+", false),
+    // Android screenshots: Screenshot_2024-05-01-10-22-33.png, Screenshot_20240501-102233.png
+    ("android-screenshot", r"Screenshot_(?P<year>\d{4})-?(?P<month>\d{2})-?(?P<day>\d{2})-(?P<hour>\d{2})-?(?P<minute>\d{2})-?(?P<second>\d{2})", false),
+    // macOS screenshots: Screenshot 2024-05-01 at 10.22.33.png, Screenshot 2024-05-01 at 1.22.33 PM.png
+    ("macos-screenshot", r"Screen ?[Ss]hot (?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2}) at (?P<hour>\d{1,2})\.(?P<minute>\d{2})\.(?P<second>\d{2})(?:\s?(?P<meridiem>[AaPp][Mm]))?", false),
+    // Signal exports: signal-2024-01-01-123456.jpg, signal-2024-01-01-12-34-56-789.jpg
+    ("signal", r"signal-(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})-(?P<hour>\d{2})-?(?P<minute>\d{2})-?(?P<second>\d{2})(?:-(?P<ms>\d{3}))?", false),
+    // Camera apps: IMG_20240101_123456.jpg, VID_20240101_123456.mp4, 20240101_123456.jpg
+    ("date-time", r"(?:^|[^\d])(?P<year>(?:19|20)\d{2})(?P<month>\d{2})(?P<day>\d{2})[_-](?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})(?:[^\d]|$)", false),
+    // Any remaining date: 2024-01-01 party.jpg, scan_2019.05.17.tif
+    ("date", r"(?:^|[^\d])(?P<year>(?:19|20)\d{2})[-_.]?(?P<month>\d{2})[-_.]?(?P<day>\d{2})(?:[^\d]|$)", false),
+];
+
+/// A named regular expression that extracts a timestamp from a file name
+#[derive(Debug, Clone)]
+pub struct FilenameDatePattern {
+    pub name: String,
+    regex: Regex,
+    /// The encoded time is UTC rather than local wall-clock time
+    pub utc: bool,
+    /// The pattern captures a time of day, not only a date
+    pub has_time: bool,
+}
+
+impl FilenameDatePattern {
+    pub fn new(name: &str, pattern: &str, utc: bool) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("Invalid filename date pattern '{}'", pattern))?;
+        for group in ["year", "month", "day"] {
+            if !regex.capture_names().any(|name| name == Some(group)) {
+                anyhow::bail!("Filename date pattern '{}' has no (?P<{}>...) group", pattern, group);
+            }
+        }
+        let has_time = regex.capture_names().any(|name| name == Some("hour"));
+        Ok(Self { name: name.to_string(), regex, utc, has_time })
+    }
+
+    fn extract(&self, file_name: &str, accept: &impl Fn(&ParsedTimestamp) -> bool) -> Option<ParsedTimestamp> {
+        self.regex.captures_iter(file_name)
+            .filter_map(|captures| Self::timestamp(&captures))
+            .find(|parsed| accept(parsed))
+    }
+
+    fn timestamp(captures: &Captures) -> Option<ParsedTimestamp> {
+        // Time groups are optional and default to midnight
+        let number = |group: &str| match captures.name(group) {
+            Some(value) => value.as_str().parse::<u32>().ok(),
+            None => Some(0),
+        };
+        let mut hour = number("hour")?;
+        if let Some(meridiem) = captures.name("meridiem") {
+            // 12-hour clock: 12 AM is midnight, 12 PM is noon
+            if !(1..=12).contains(&hour) {
+                return None;
+            }
+            hour %= 12;
+            if meridiem.as_str().eq_ignore_ascii_case("pm") {
+                hour += 12;
+            }
+        }
+        let local = NaiveDate::from_ymd_opt(number("year")? as i32, number("month")?, number("day")?)?
+            .and_hms_opt(hour, number("minute")?, number("second")?)?;
+        let nanoseconds = match captures.name("ms") {
+            Some(subsec) => parse_subseconds(subsec.as_str()).ok()?,
+            None => 0,
+        };
+        Some(ParsedTimestamp { local, offset: None, nanoseconds })
+    }
+}
+
+/// Ordered list of filename date patterns; the first one that yields a valid date wins
+#[derive(Debug, Clone)]
+pub struct FilenameDateExtractor {
+    patterns: Vec<FilenameDatePattern>,
+}
+
+impl FilenameDateExtractor {
+    /// Extractor trying `custom` patterns before the built-in phone and app patterns
+    pub fn new(custom: Vec<FilenameDatePattern>) -> Self {
+        let builtin = BUILTIN_PATTERNS.iter()
+            .map(|(name, pattern, utc)| FilenameDatePattern::new(name, pattern, *utc).expect("built-in pattern is valid"));
+        Self { patterns: custom.into_iter().chain(builtin).collect() }
+    }
+
+    /// Timestamp encoded in the file name of `file_path` that `accept` agrees with,
+    /// together with the pattern that matched
+    pub fn extract(
+        &self,
+        file_path: &Path,
+        accept: impl Fn(&ParsedTimestamp) -> bool,
+    ) -> Option<(&FilenameDatePattern, ParsedTimestamp)> {
+        let file_name = file_path.file_stem()?.to_str()?;
+        let found = self.patterns.iter()
+            .find_map(|pattern| pattern.extract(file_name, &accept).map(|parsed| (pattern, parsed)));
+        if let Some((pattern, parsed)) = &found {
+            debug!("Filename pattern '{}' matched {}: {}", pattern.name, file_name, parsed.local);
+        }
+        found
+    }
+}
+
+impl Default for FilenameDateExtractor {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(file_name: &str) -> Option<(String, String)> {
+        FilenameDateExtractor::default()
+            .extract(Path::new(file_name), |_| true)
+            .map(|(pattern, parsed)| (pattern.name.clone(), parsed.local.format("%Y-%m-%d %H:%M:%S").to_string()))
+    }
+
+    #[test]
+    fn builtin_patterns_extract_dates() {
+        let cases = [
+            ("PXL_20240101_123456789.jpg", "pixel", "2024-01-01 12:34:56"),
+            ("IMG-20230412-WA0003.jpg", "whatsapp", "2023-04-12 00:00:00"),
+            ("Screenshot_2024-05-01-10-22-33.png", "android-screenshot", "2024-05-01 10:22:33"),
+            ("Screenshot_20240501-102233.png", "android-screenshot", "2024-05-01 10:22:33"),
+            ("Screenshot 2024-05-01 at 10.22.33.png", "macos-screenshot", "2024-05-01 10:22:33"),
+            ("signal-2024-01-01-12-34-56-789.jpg", "signal", "2024-01-01 12:34:56"),
+            ("IMG_20240101_123456.jpg", "date-time", "2024-01-01 12:34:56"),
+            ("scan_2019.05.17.tif", "date", "2019-05-17 00:00:00"),
+        ];
+        for (file_name, pattern, expected) in cases {
+            assert_eq!(extract(file_name), Some((pattern.to_string(), expected.to_string())), "{}", file_name);
+        }
+        assert_eq!(extract("holiday.jpg"), None);
+        assert_eq!(extract("IMG_20241399_123456.jpg"), None, "invalid dates are rejected");
+    }
+
+    #[test]
+    fn macos_screenshots_use_twelve_hour_clock() {
+        let time = |file_name: &str| extract(file_name).map(|(_, time)| time);
+        assert_eq!(time("Screenshot 2024-05-01 at 1.22.33 PM.png").as_deref(), Some("2024-05-01 13:22:33"));
+        assert_eq!(time("Screenshot 2024-05-01 at 9.05.00 AM.png").as_deref(), Some("2024-05-01 09:05:00"));
+        assert_eq!(time("Screenshot 2024-05-01 at 12.00.01 AM.png").as_deref(), Some("2024-05-01 00:00:01"));
+        assert_eq!(time("Screenshot 2024-05-01 at 12.30.00 PM.png").as_deref(), Some("2024-05-01 12:30:00"));
+        // Recent macOS versions put a narrow no-break space before the meridiem
+        assert_eq!(time("Screenshot 2024-05-01 at 1.22.33\u{202f}PM.png").as_deref(), Some("2024-05-01 13:22:33"));
+    }
+
+    #[test]
+    fn custom_patterns_need_date_groups() {
+        assert!(FilenameDatePattern::new("bad", r"(?P<year>\d{4})", false).is_err());
+        assert!(FilenameDatePattern::new("bad", r"(", false).is_err());
+        let pattern = FilenameDatePattern::new("scan", r"scan(?P<day>\d{2})(?P<month>\d{2})(?P<year>\d{4})", false).unwrap();
+        assert!(!pattern.has_time);
+        let extractor = FilenameDateExtractor::new(vec![pattern]);
+        let (matched, parsed) = extractor.extract(Path::new("scan17052019.tif"), |_| true).unwrap();
+        assert_eq!(matched.name, "scan");
+        assert_eq!(parsed.local.to_string(), "2019-05-17 00:00:00");
+    }
+}
@@ -1,23 +1,128 @@
-/**
+/*!
  * Content hashing module for duplicate detection using xxhash
+ *
+ * Exact duplicates are found by hashing the raw bytes, or only the encoded image data
+ * so that copies differing in metadata alone still match. Near duplicates (re-saved,
+ * resized or metadata-stripped copies) are found with a perceptual hash of a decoded,
+ * downscaled grayscale image, compared by Hamming distance.
  */
 
 use anyhow::{Context, Result};
-use log::warn;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use log::{debug, warn};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use xxhash_rust::xxh3;
 
+/// Bytes read from each end of a file for its partial hash
+pub const PARTIAL_HASH_BYTES: u64 = 64 * 1024;
+
+/// Largest Hamming distance between the 64-bit hashes of two near-duplicate images by default
+pub const DEFAULT_NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+/// Side length of the thumbnail the DCT hash is computed from
+const PHASH_SIZE: usize = 32;
+
+/// Lowest DCT frequencies per axis that make up the DCT hash (8x8 = 64 bits)
+const PHASH_FREQUENCIES: usize = 8;
+
+/// Most IFDs followed in a TIFF file, guarding against offset loops in corrupt files
+const MAX_TIFF_IFDS: usize = 64;
+
+/// TIFF tags locating image data as (offsets tag, byte counts tag): strips and tiles
+const TIFF_DATA_TAGS: [(u16, u16); 2] = [(273, 279), (324, 325)];
+
+/// TIFF tag listing the offsets of sub-IFDs, where RAW formats keep the full-size image
+const TIFF_SUB_IFDS_TAG: u16 = 330;
+
+/// Largest `meta` box read to locate the items of a HEIF file (HEIC, AVIF)
+const MAX_HEIF_META_BYTES: u64 = 16 * 1024 * 1024;
+
+/// HEIF item types holding metadata rather than image data: EXIF and XMP (`mime`)
+const HEIF_METADATA_ITEMS: [&[u8; 4]; 2] = [b"Exif", b"mime"];
+
+/// Box types an ISO base media file (MOV, MP4, HEIC) can start with
+const BMFF_FIRST_BOXES: [&[u8; 4]; 6] = [b"ftyp", b"moov", b"mdat", b"wide", b"free", b"skip"];
+
+/// What `ContentHasher` hashes to decide that two files are duplicates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashMode {
+    /// Every byte of the file
+    #[default]
+    Full,
+    /// Only the encoded image or video data: JPEG scans, TIFF/RAW strips and tiles, PNG
+    /// image chunks, the image items of HEIC/AVIF files and `mdat` boxes of MOV/MP4 files.
+    /// Other formats are hashed in full.
+    ImageData,
+}
+
+impl FromStr for HashMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            "image-data" => Ok(Self::ImageData),
+            _ => anyhow::bail!("Invalid hash mode '{}' (expected full or image-data)", value),
+        }
+    }
+}
+
+/// Hash state of one file that takes at most `budget` more bytes of file or image data
+///
+/// Markers, chunk types and other structure always go into the hash; the budget only cuts
+/// the data short, for partial hashes.
+struct DataHasher {
+    hasher: xxh3::Xxh3,
+    budget: u64,
+}
+
+impl DataHasher {
+    fn new(budget: u64) -> Self {
+        Self { hasher: xxh3::Xxh3::default(), budget }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+
+    fn update_data(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.budget = self.budget.saturating_sub(bytes.len() as u64);
+    }
+
+    fn hex_digest(&self) -> String {
+        format!("{:016x}", self.hasher.digest())
+    }
+}
+
 pub struct ContentHasher {
     chunk_size: usize,
+    mode: HashMode,
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ContentHasher {
     pub fn new() -> Self {
+        Self::with_mode(HashMode::Full)
+    }
+
+    pub fn with_mode(mode: HashMode) -> Self {
         Self {
             chunk_size: 65536, // 64KB chunks
+            mode,
         }
     }
 
@@ -25,26 +130,337 @@ impl ContentHasher {
     /// 
     /// Uses xxh3 algorithm for maximum performance with streaming
     pub fn calculate_file_hash(&self, file_path: &Path) -> Result<String> {
+        self.hash_file(file_path, u64::MAX)
+    }
+
+    /// Hash of the first `PARTIAL_HASH_BYTES` of image data, a cheap filter for duplicates
+    /// in `HashMode::ImageData`, where sizes and the ends of files differ with the metadata
+    ///
+    /// Only headers and the start of the image data are read. Files without recognized
+    /// image data, and every file in `HashMode::Full`, get their `calculate_partial_hash`.
+    pub fn calculate_partial_image_data_hash(&self, file_path: &Path) -> Result<String> {
+        self.hash_file(file_path, PARTIAL_HASH_BYTES)
+    }
+
+    /// Hash a file, reading at most `budget` bytes of image data in `HashMode::ImageData`
+    fn hash_file(&self, file_path: &Path, budget: u64) -> Result<String> {
+        let file = File::open(file_path)
+            .context("Failed to open file for hashing")?;
+
+        let mut reader = BufReader::new(file);
+
+        if self.mode == HashMode::ImageData {
+            let mut hasher = DataHasher::new(budget);
+            match self.hash_image_data(&mut reader, &mut hasher) {
+                Ok(true) => return Ok(hasher.hex_digest()),
+                Ok(false) => debug!("No image data found in {}, hashing the whole file", file_path.display()),
+                Err(e) => debug!("Cannot locate image data in {} ({}), hashing the whole file", file_path.display(), e),
+            }
+            reader.rewind().context("Failed to read file for hashing")?;
+        }
+        if budget != u64::MAX {
+            return self.calculate_partial_hash(file_path);
+        }
+        let mut hasher = DataHasher::new(u64::MAX);
+        self.hash_bytes(&mut reader, &mut hasher, u64::MAX)
+            .context("Failed to read file for hashing")?;
+
+        Ok(hasher.hex_digest())
+    }
+
+    /// Hash of the first and last `PARTIAL_HASH_BYTES` of a file, a cheap filter for
+    /// duplicates among files of the same size
+    ///
+    /// Files of up to twice `PARTIAL_HASH_BYTES` are read completely, so their partial hash
+    /// equals the full hash of `HashMode::Full`.
+    pub fn calculate_partial_hash(&self, file_path: &Path) -> Result<String> {
         let file = File::open(file_path)
             .context("Failed to open file for hashing")?;
+        let length = file.metadata().context("Failed to read file for hashing")?.len();
 
         let mut reader = BufReader::new(file);
+        let mut hasher = DataHasher::new(u64::MAX);
+        if length <= 2 * PARTIAL_HASH_BYTES {
+            self.hash_bytes(&mut reader, &mut hasher, u64::MAX)
+        } else {
+            self.hash_bytes(&mut reader, &mut hasher, PARTIAL_HASH_BYTES)
+                .and_then(|_| reader.seek(SeekFrom::End(-(PARTIAL_HASH_BYTES as i64))))
+                .and_then(|_| self.hash_bytes(&mut reader, &mut hasher, PARTIAL_HASH_BYTES))
+        }.context("Failed to read file for hashing")?;
+
+        Ok(hasher.hex_digest())
+    }
+
+    /// Feed up to `length` bytes from the current position of `reader` into `hasher`
+    ///
+    /// Bytes beyond the budget of `hasher` are skipped, so `reader` still ends up after them.
+    fn hash_bytes(&self, reader: &mut (impl Read + Seek), hasher: &mut DataHasher, length: u64) -> std::io::Result<()> {
+        let mut remaining = length.min(hasher.budget);
+        let skipped = length - remaining;
         let mut buffer = vec![0u8; self.chunk_size];
-        let mut hasher = xxh3::Xxh3::default();
-        
+        while remaining > 0 {
+            let wanted = buffer.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+            let bytes_read = reader.read(&mut buffer[..wanted])?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            hasher.update_data(&buffer[..bytes_read]);
+            remaining -= bytes_read as u64;
+        }
+        if skipped > 0 {
+            reader.seek(SeekFrom::Current(i64::try_from(skipped).unwrap_or(i64::MAX)))?;
+        }
+        Ok(())
+    }
+
+    /// Hash only the encoded image data of a file, returning false for formats that are
+    /// not recognized or have no image data
+    fn hash_image_data(&self, reader: &mut BufReader<File>, hasher: &mut DataHasher) -> Result<bool> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        match header {
+            [0xFF, 0xD8, ..] => self.hash_jpeg_data(reader, hasher),
+            [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => self.hash_tiff_data(reader, hasher, header[0] == b'I'),
+            [0x89, b'P', b'N', b'G', ..] => self.hash_png_data(reader, hasher),
+            [_, _, _, _, a, b, c, d] if BMFF_FIRST_BOXES.contains(&&[a, b, c, d]) => self.hash_bmff_data(reader, hasher),
+            _ => Ok(false),
+        }
+    }
+
+    /// JPEG: every segment except APPn (EXIF, XMP, ICC, ...) and comments, then everything
+    /// from the first scan up to the end-of-image marker
+    ///
+    /// Trailers after the end of the image, such as the preview images of an MPF file or
+    /// data appended by editors, are left out.
+    fn hash_jpeg_data(&self, reader: &mut BufReader<File>, hasher: &mut DataHasher) -> Result<bool> {
+        reader.seek(SeekFrom::Start(2))?;
+        loop {
+            let mut marker = [0u8; 2];
+            reader.read_exact(&mut marker)?;
+            match marker {
+                [0xFF, 0xFF] => reader.seek_relative(-1)?,
+                [0xFF, 0xDA] => {
+                    hasher.update(&marker);
+                    self.hash_until_jpeg_end(reader, hasher)?;
+                    return Ok(true);
+                }
+                [0xFF, 0xD9] => return Ok(false),
+                [0xFF, 0x01] | [0xFF, 0xD0..=0xD7] => hasher.update(&marker),
+                [0xFF, kind] => {
+                    let mut length = [0u8; 2];
+                    reader.read_exact(&mut length)?;
+                    let length = u16::from_be_bytes(length);
+                    if length < 2 {
+                        anyhow::bail!("Invalid JPEG segment length");
+                    }
+                    if matches!(kind, 0xE0..=0xEF | 0xFE) {
+                        reader.seek_relative(i64::from(length) - 2)?;
+                    } else {
+                        hasher.update(&marker);
+                        hasher.update(&length.to_be_bytes());
+                        self.hash_bytes(reader, hasher, u64::from(length) - 2)?;
+                    }
+                }
+                _ => anyhow::bail!("Invalid JPEG marker"),
+            }
+        }
+    }
+
+    /// Feed the scans of a JPEG into `hasher`, up to and including the end-of-image marker
+    ///
+    /// Inside entropy-coded data a 0xFF byte is always followed by a stuffed zero or a
+    /// marker, so the first 0xFF 0xD9 is the end of the image. A truncated file ends at EOF,
+    /// a partial hash when the budget of `hasher` is used up.
+    fn hash_until_jpeg_end(&self, reader: &mut impl Read, hasher: &mut DataHasher) -> std::io::Result<()> {
+        let mut buffer = vec![0u8; self.chunk_size];
+        let mut previous_ff = false;
         loop {
-            let bytes_read = reader.read(&mut buffer)
-                .context("Failed to read file for hashing")?;
-            
+            let wanted = buffer.len().min(usize::try_from(hasher.budget).unwrap_or(usize::MAX));
+            let bytes_read = reader.read(&mut buffer[..wanted])?;
             if bytes_read == 0 {
-                break;
+                return Ok(());
+            }
+            let chunk = &buffer[..bytes_read];
+            let end = (0..chunk.len()).find(|index| {
+                let after_ff = if *index == 0 { previous_ff } else { chunk[index - 1] == 0xFF };
+                after_ff && chunk[*index] == 0xD9
+            });
+            match end {
+                Some(end) => {
+                    hasher.update_data(&chunk[..=end]);
+                    return Ok(());
+                }
+                None => {
+                    hasher.update_data(chunk);
+                    previous_ff = chunk[bytes_read - 1] == 0xFF;
+                }
+            }
+        }
+    }
+
+    /// TIFF and TIFF-based RAW (DNG, CR2, NEF): the strips and tiles of every IFD and sub-IFD
+    ///
+    /// Value counts and data ranges are checked against the file length, so a corrupt
+    /// entry fails (and the file is hashed in full) instead of allocating or reading blindly.
+    fn hash_tiff_data(&self, reader: &mut BufReader<File>, hasher: &mut DataHasher, little_endian: bool) -> Result<bool> {
+        let u16_at = |bytes: &[u8]| {
+            let bytes = [bytes[0], bytes[1]];
+            if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+        };
+        let u32_at = |bytes: &[u8]| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+        };
+
+        let file_length = reader.get_ref().metadata()?.len();
+        reader.seek(SeekFrom::Start(4))?;
+        let mut first_ifd = [0u8; 4];
+        reader.read_exact(&mut first_ifd)?;
+
+        let mut pending = vec![u32_at(&first_ifd)];
+        let mut visited = HashSet::new();
+        let mut ranges = Vec::new();
+        while let Some(ifd_offset) = pending.pop() {
+            if ifd_offset == 0 || visited.len() >= MAX_TIFF_IFDS || !visited.insert(ifd_offset) {
+                continue;
+            }
+            reader.seek(SeekFrom::Start(u64::from(ifd_offset)))?;
+            let mut count = [0u8; 2];
+            reader.read_exact(&mut count)?;
+            let mut entries = vec![0u8; usize::from(u16_at(&count)) * 12 + 4];
+            reader.read_exact(&mut entries)?;
+
+            // Values of an entry as u32, read from the entry itself or from its offset
+            let mut values = |entry: &[u8]| -> Result<Vec<u32>> {
+                let (kind, count) = (u16_at(&entry[2..]), u32_at(&entry[4..]) as usize);
+                let size: usize = match kind {
+                    3 => 2,
+                    4 | 13 => 4,
+                    _ => return Ok(Vec::new()),
+                };
+                let length = size.checked_mul(count)
+                    .filter(|length| *length as u64 <= file_length)
+                    .context("TIFF entry is larger than the file")?;
+                let data = if length <= 4 {
+                    entry[8..12].to_vec()
+                } else {
+                    let mut data = vec![0u8; length];
+                    reader.seek(SeekFrom::Start(u64::from(u32_at(&entry[8..]))))?;
+                    reader.read_exact(&mut data)?;
+                    data
+                };
+                Ok(data.chunks(size).take(count).map(|value| match size {
+                    2 => u32::from(u16_at(value)),
+                    _ => u32_at(value),
+                }).collect())
+            };
+
+            let (entries, next_ifd) = entries.split_at(entries.len() - 4);
+            let mut tag_values = |tag: u16| match entries.chunks(12).find(|entry| u16_at(entry) == tag) {
+                Some(entry) => values(entry),
+                None => Ok(Vec::new()),
+            };
+            for (offsets_tag, counts_tag) in TIFF_DATA_TAGS {
+                let offsets = tag_values(offsets_tag)?;
+                let counts = tag_values(counts_tag)?;
+                ranges.extend(offsets.into_iter().zip(counts));
+            }
+            pending.extend(tag_values(TIFF_SUB_IFDS_TAG)?);
+            pending.push(u32_at(next_ifd));
+        }
+
+        if ranges.iter().any(|(offset, length)| u64::from(*offset) + u64::from(*length) > file_length) {
+            anyhow::bail!("TIFF image data lies outside the file");
+        }
+        for (offset, length) in &ranges {
+            reader.seek(SeekFrom::Start(u64::from(*offset)))?;
+            self.hash_bytes(reader, hasher, u64::from(*length))?;
+        }
+        Ok(!ranges.is_empty())
+    }
+
+    /// PNG: critical chunks (header, palette, image data), not text, time or EXIF chunks
+    fn hash_png_data(&self, reader: &mut BufReader<File>, hasher: &mut DataHasher) -> Result<bool> {
+        let mut found = false;
+        loop {
+            let mut chunk_header = [0u8; 8];
+            match reader.read_exact(&mut chunk_header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(found),
+                Err(e) => return Err(e.into()),
+            }
+            let length = u32::from_be_bytes([chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]]);
+            // Ancillary chunk types start with a lowercase letter
+            if chunk_header[4].is_ascii_uppercase() {
+                hasher.update(&chunk_header[4..]);
+                self.hash_bytes(reader, hasher, u64::from(length))?;
+                found = true;
+                reader.seek_relative(4)?;
+            } else {
+                reader.seek_relative(i64::from(length) + 4)?;
+            }
+            if &chunk_header[4..] == b"IEND" {
+                return Ok(found);
+            }
+        }
+    }
+
+    /// ISO base media files: the image items of HEIF files (HEIC, AVIF), otherwise (MOV,
+    /// MP4) the contents of the top-level `mdat` boxes
+    ///
+    /// HEIF files keep their EXIF and XMP as items inside `mdat` too, so when the `meta` box
+    /// locates the items, only the extents of items that are not metadata are hashed.
+    fn hash_bmff_data(&self, reader: &mut BufReader<File>, hasher: &mut DataHasher) -> Result<bool> {
+        let file_length = reader.get_ref().metadata()?.len();
+        let mut position = 0;
+        let mut meta = None;
+        let mut media_data = Vec::new();
+        while position + 8 <= file_length {
+            reader.seek(SeekFrom::Start(position))?;
+            let mut box_header = [0u8; 8];
+            reader.read_exact(&mut box_header)?;
+            let (size, header_length) = match u32::from_be_bytes([box_header[0], box_header[1], box_header[2], box_header[3]]) {
+                0 => (file_length - position, 8),
+                1 => {
+                    let mut large_size = [0u8; 8];
+                    reader.read_exact(&mut large_size)?;
+                    (u64::from_be_bytes(large_size), 16)
+                }
+                size => (u64::from(size), 8),
+            };
+            if size < header_length || position.checked_add(size).is_none_or(|end| end > file_length) {
+                anyhow::bail!("Invalid box size");
+            }
+            match &box_header[4..] {
+                b"mdat" => media_data.push((position + header_length, size - header_length)),
+                b"meta" if meta.is_none() => meta = Some((position + header_length, size - header_length)),
+                _ => {}
+            }
+            position += size;
+        }
+
+        if let Some((meta_start, meta_length)) = meta.filter(|(_, length)| *length <= MAX_HEIF_META_BYTES) {
+            let mut meta = vec![0u8; meta_length as usize];
+            reader.seek(SeekFrom::Start(meta_start))?;
+            reader.read_exact(&mut meta)?;
+            if let Some(items) = heif_image_items(&meta, meta_start, file_length)? {
+                for (item_type, extents) in items {
+                    hasher.update(&item_type);
+                    for (offset, length) in extents {
+                        reader.seek(SeekFrom::Start(offset))?;
+                        self.hash_bytes(reader, hasher, length)?;
+                    }
+                }
+                return Ok(true);
             }
-            
-            hasher.update(&buffer[..bytes_read]);
         }
 
-        let hash = hasher.digest();
-        Ok(format!("{:016x}", hash))
+        for (offset, length) in &media_data {
+            reader.seek(SeekFrom::Start(*offset))?;
+            hasher.update(b"mdat");
+            self.hash_bytes(reader, hasher, *length)?;
+        }
+        Ok(!media_data.is_empty())
     }
 
     /// Build an index of file content hashes for multiple files
@@ -119,3 +535,626 @@ impl ContentHasher {
         None
     }
 }
+
+/// Child boxes of an ISO base media box payload as (type, payload range)
+fn bmff_boxes(data: &[u8]) -> Result<Vec<([u8; 4], Range<usize>)>> {
+    let mut boxes = Vec::new();
+    let mut position = 0;
+    while position + 8 <= data.len() {
+        let kind = [data[position + 4], data[position + 5], data[position + 6], data[position + 7]];
+        let (size, header_length) = match big_endian(&data[position..position + 4]) {
+            0 => (data.len() - position, 8),
+            1 => (usize::try_from(big_endian(data.get(position + 8..position + 16).context("Truncated box")?))?, 16),
+            size => (usize::try_from(size)?, 8),
+        };
+        if size < header_length || position.checked_add(size).is_none_or(|end| end > data.len()) {
+            anyhow::bail!("Invalid box size");
+        }
+        boxes.push((kind, position + header_length..position + size));
+        position += size;
+    }
+    Ok(boxes)
+}
+
+/// Unsigned big-endian integer of up to 8 bytes
+fn big_endian(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+/// Split `length` bytes off the front of `data`
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if data.len() < length {
+        anyhow::bail!("Truncated HEIF box");
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+/// Type of a HEIF item and its extents in the file as (offset, length)
+type HeifItem = ([u8; 4], Vec<(u64, u64)>);
+
+/// Image items of a HEIF file, ordered by item ID
+///
+/// `meta` is the payload of the top-level `meta` box, which starts at `meta_start` in the
+/// file. Items are located through `iinf` (item types) and `iloc` (extents in the file or
+/// in `idat`); EXIF and XMP items are left out. Returns None when the box does not locate
+/// any image items, e.g. the `meta` box of an MP4.
+fn heif_image_items(meta: &[u8], meta_start: u64, file_length: u64) -> Result<Option<Vec<HeifItem>>> {
+    let children = bmff_boxes(meta.get(4..).context("Truncated HEIF box")?)?;
+    // Child ranges are relative to the payload after the version and flags of `meta`
+    let child = |kind: &[u8; 4]| children.iter()
+        .find(|(child_kind, _)| child_kind == kind)
+        .map(|(_, range)| &meta[4 + range.start..4 + range.end]);
+    let (Some(mut item_info), Some(mut locations)) = (child(b"iinf"), child(b"iloc")) else {
+        return Ok(None);
+    };
+    let item_data_start = children.iter()
+        .find(|(kind, _)| kind == b"idat")
+        .map(|(_, range)| (meta_start + 4 + range.start as u64, range.len() as u64));
+
+    // iinf: item types; types of old (version 0 and 1) entries are unknown and kept
+    let version = take(&mut item_info, 4)?[0];
+    take(&mut item_info, if version == 0 { 2 } else { 4 })?;
+    let mut item_types = HashMap::new();
+    for (kind, range) in bmff_boxes(item_info)? {
+        if &kind != b"infe" {
+            continue;
+        }
+        let mut entry = &item_info[range];
+        let version = take(&mut entry, 4)?[0];
+        if version >= 2 {
+            let item_id = big_endian(take(&mut entry, if version == 2 { 2 } else { 4 })?);
+            take(&mut entry, 2)?;
+            let item_type = take(&mut entry, 4)?;
+            item_types.insert(item_id, [item_type[0], item_type[1], item_type[2], item_type[3]]);
+        }
+    }
+
+    // iloc: extents of every item, as absolute ranges in the file
+    let version = take(&mut locations, 4)?[0];
+    let sizes = take(&mut locations, 2)?;
+    let (offset_size, length_size) = (usize::from(sizes[0] >> 4), usize::from(sizes[0] & 0x0F));
+    let (base_offset_size, index_size) = (usize::from(sizes[1] >> 4), usize::from(sizes[1] & 0x0F));
+    let id_size = if version < 2 { 2 } else { 4 };
+    let item_count = big_endian(take(&mut locations, id_size)?);
+    let mut items = Vec::new();
+    for _ in 0..item_count {
+        let item_id = big_endian(take(&mut locations, id_size)?);
+        let construction_method = if version == 1 || version == 2 { big_endian(take(&mut locations, 2)?) & 0x0F } else { 0 };
+        take(&mut locations, 2)?;
+        let base_offset = big_endian(take(&mut locations, base_offset_size)?);
+        let extent_count = big_endian(take(&mut locations, 2)?);
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            if (version == 1 || version == 2) && index_size > 0 {
+                take(&mut locations, index_size)?;
+            }
+            let offset = base_offset.checked_add(big_endian(take(&mut locations, offset_size)?)).context("Invalid HEIF extent")?;
+            let length = big_endian(take(&mut locations, length_size)?);
+            let (start, limit) = match (construction_method, item_data_start) {
+                (0, _) => (offset, file_length),
+                (1, Some((idat_start, idat_length))) if offset <= idat_length => (idat_start + offset, idat_start + idat_length),
+                _ => anyhow::bail!("Unsupported HEIF item construction"),
+            };
+            // A zero length means "up to the end of the data", which this walker does not resolve
+            if length == 0 || start.checked_add(length).is_none_or(|end| end > limit) {
+                anyhow::bail!("HEIF item extent lies outside the file");
+            }
+            extents.push((start, length));
+        }
+        let item_type = item_types.get(&item_id).copied().unwrap_or(*b"    ");
+        if !HEIF_METADATA_ITEMS.contains(&&item_type) && !extents.is_empty() {
+            items.push((item_id, item_type, extents));
+        }
+    }
+    if items.is_empty() {
+        return Ok(None);
+    }
+    items.sort_by_key(|(item_id, _, _)| *item_id);
+    Ok(Some(items.into_iter().map(|(_, item_type, extents)| (item_type, extents)).collect()))
+}
+
+/// Perceptual hash functions for near-duplicate detection, both 64 bits wide
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PerceptualAlgorithm {
+    /// Difference hash: brightness gradients between neighbouring pixels of a 9x8 thumbnail
+    #[default]
+    DHash,
+    /// DCT hash: lowest frequencies of a 32x32 thumbnail, more robust against edits and crops
+    PHash,
+}
+
+impl FromStr for PerceptualAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "dhash" => Ok(Self::DHash),
+            "phash" => Ok(Self::PHash),
+            _ => anyhow::bail!("Invalid perceptual hash '{}' (expected dhash or phash)", value),
+        }
+    }
+}
+
+/// Perceptual hash of an image together with its decoded size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerceptualHash {
+    pub hash: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PerceptualHash {
+    /// Number of differing bits; 0 for identical looking images
+    pub fn distance(&self, other: &Self) -> u32 {
+        (self.hash ^ other.hash).count_ones()
+    }
+}
+
+/// Images that look alike: the one to keep and the others with their distance to it
+#[derive(Debug, Clone)]
+pub struct NearDuplicateCluster {
+    pub keep: PathBuf,
+    pub others: Vec<(PathBuf, u32)>,
+}
+
+pub struct PerceptualHasher {
+    algorithm: PerceptualAlgorithm,
+}
+
+impl PerceptualHasher {
+    pub fn new(algorithm: PerceptualAlgorithm) -> Self {
+        Self { algorithm }
+    }
+
+    /// Decode `file_path` and hash a downscaled grayscale version of it
+    pub fn calculate_hash(&self, file_path: &Path) -> Result<PerceptualHash> {
+        let image = image::open(file_path)
+            .with_context(|| format!("Failed to decode image: {}", file_path.display()))?;
+        let hash = match self.algorithm {
+            PerceptualAlgorithm::DHash => dhash(&image),
+            PerceptualAlgorithm::PHash => phash(&image),
+        };
+        Ok(PerceptualHash { hash, width: image.width(), height: image.height() })
+    }
+
+    /// Hash `file_paths` in parallel, leaving out files that cannot be decoded
+    /// (videos, RAW and HEIC files)
+    pub fn hash_files(&self, file_paths: &[PathBuf]) -> Vec<(PathBuf, PerceptualHash)> {
+        file_paths.par_iter()
+            .filter_map(|file_path| match self.calculate_hash(file_path) {
+                Ok(hash) => Some((file_path.clone(), hash)),
+                Err(e) => {
+                    debug!("No perceptual hash for {}: {:#}", file_path.display(), e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Cluster images whose hashes are at most `max_distance` apart from the image each
+/// cluster keeps
+///
+/// Images are visited from the most pixels down, then the largest file, so resized and
+/// recompressed copies are the ones listed as near duplicates. Each image not yet in a
+/// cluster keeps the ones within `max_distance` of it; images that only look like a
+/// near duplicate, and not like the kept image, are not chained into its cluster.
+pub fn find_near_duplicates(hashes: &[(PathBuf, PerceptualHash)], max_distance: u32) -> Vec<NearDuplicateCluster> {
+    let file_size = |path: &Path| fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    let mut order: Vec<usize> = (0..hashes.len()).collect();
+    order.sort_by_cached_key(|index| {
+        let (path, hash) = &hashes[*index];
+        (std::cmp::Reverse((u64::from(hash.width) * u64::from(hash.height), file_size(path))), path.clone())
+    });
+    let mut rank = vec![0; hashes.len()];
+    for (position, index) in order.iter().enumerate() {
+        rank[*index] = position;
+    }
+
+    let mut tree = BkTree::default();
+    for (index, (_, hash)) in hashes.iter().enumerate() {
+        tree.insert(hash.hash, index);
+    }
+
+    let mut clustered = vec![false; hashes.len()];
+    let mut clusters = Vec::new();
+    for keep in order {
+        if clustered[keep] {
+            continue;
+        }
+        clustered[keep] = true;
+        let keep_hash = &hashes[keep].1;
+        let mut members: Vec<usize> = tree.within(keep_hash.hash, max_distance).into_iter()
+            .filter(|index| !clustered[*index])
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        members.sort_by_key(|index| rank[*index]);
+        for index in &members {
+            clustered[*index] = true;
+        }
+        clusters.push(NearDuplicateCluster {
+            keep: hashes[keep].0.clone(),
+            others: members.into_iter()
+                .map(|index| (hashes[index].0.clone(), keep_hash.distance(&hashes[index].1)))
+                .collect(),
+        });
+    }
+    clusters.sort_by(|a, b| a.keep.cmp(&b.keep));
+    clusters
+}
+
+/// BK-tree over 64-bit hashes under the Hamming distance, so finding the hashes near one
+/// hash only visits the branches that can hold them instead of every hash
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    item: usize,
+    /// Child nodes by their distance to this node
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, item: usize) {
+        let new_node = self.nodes.len();
+        let mut node = 0;
+        while node < new_node {
+            let distance = (self.nodes[node].hash ^ hash).count_ones();
+            match self.nodes[node].children.iter().find(|(child_distance, _)| *child_distance == distance) {
+                Some((_, child)) => node = *child,
+                None => {
+                    self.nodes[node].children.push((distance, new_node));
+                    break;
+                }
+            }
+        }
+        self.nodes.push(BkNode { hash, item, children: Vec::new() });
+    }
+
+    /// Items whose hash is at most `max_distance` bits from `hash`
+    fn within(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(node) = pending.pop() {
+            let node = &self.nodes[node];
+            let distance = (node.hash ^ hash).count_ones();
+            if distance <= max_distance {
+                found.push(node.item);
+            }
+            // By the triangle inequality only children this far from the node can match
+            let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+            pending.extend(node.children.iter()
+                .filter(|(child_distance, _)| range.contains(child_distance))
+                .map(|(_, child)| *child));
+        }
+        found
+    }
+}
+
+fn dhash(image: &DynamicImage) -> u64 {
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8().into_raw();
+    pixels.chunks(9)
+        .flat_map(|row| row.windows(2))
+        .fold(0, |hash, pair| (hash << 1) | u64::from(pair[0] < pair[1]))
+}
+
+fn phash(image: &DynamicImage) -> u64 {
+    let pixels: Vec<f64> = image.resize_exact(PHASH_SIZE as u32, PHASH_SIZE as u32, FilterType::Triangle)
+        .to_luma8()
+        .into_raw()
+        .into_iter()
+        .map(f64::from)
+        .collect();
+
+    // Separable DCT-II, computing only the frequencies that make up the hash
+    let cosine = |frequency: usize, position: usize| {
+        (PI * (2 * position + 1) as f64 * frequency as f64 / (2 * PHASH_SIZE) as f64).cos()
+    };
+    let mut rows = vec![0.0; PHASH_SIZE * PHASH_FREQUENCIES];
+    for y in 0..PHASH_SIZE {
+        for u in 0..PHASH_FREQUENCIES {
+            rows[y * PHASH_FREQUENCIES + u] = (0..PHASH_SIZE).map(|x| pixels[y * PHASH_SIZE + x] * cosine(u, x)).sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(PHASH_FREQUENCIES * PHASH_FREQUENCIES);
+    for v in 0..PHASH_FREQUENCIES {
+        for u in 0..PHASH_FREQUENCIES {
+            coefficients.push((0..PHASH_SIZE).map(|y| rows[y * PHASH_FREQUENCIES + u] * cosine(v, y)).sum::<f64>());
+        }
+    }
+
+    // The median leaves out the DC term, which only reflects overall brightness
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coefficients.iter().fold(0, |hash, coefficient| (hash << 1) | u64::from(*coefficient > median))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// Hashes of `bytes` in image-data mode (with tiny chunks, so markers straddle reads)
+    /// and in full
+    fn hashes(bytes: &[u8]) -> (String, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, bytes).unwrap();
+        let image_data = ContentHasher { chunk_size: 3, mode: HashMode::ImageData };
+        (image_data.calculate_file_hash(&path).unwrap(), ContentHasher::new().calculate_file_hash(&path).unwrap())
+    }
+
+    fn image_hash(bytes: &[u8]) -> String {
+        hashes(bytes).0
+    }
+
+    fn jpeg(exif: &[u8], scan: &[u8], trailer: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        bytes.extend((exif.len() as u16 + 2).to_be_bytes());
+        bytes.extend(exif);
+        bytes.extend([0xFF, 0xDB, 0x00, 0x05, 0x01, 0x02, 0x03, 0xFF, 0xDA, 0x00, 0x03, 0x00]);
+        bytes.extend(scan);
+        bytes.extend([0xFF, 0xD9]);
+        bytes.extend(trailer);
+        bytes
+    }
+
+    #[test]
+    fn jpeg_hash_covers_scans_up_to_the_end_of_image() {
+        // Stuffed bytes and restart markers do not end the scan
+        let scan = [0x10, 0xFF, 0x00, 0x20, 0xFF, 0xD0, 0x30];
+        let hash = image_hash(&jpeg(b"Exif one", &scan, b""));
+        assert_eq!(image_hash(&jpeg(b"Exif two, longer", &scan, b"MPF preview")), hash);
+        assert_ne!(image_hash(&jpeg(b"Exif one", &[0x10, 0xFF, 0x00, 0x20, 0xFF, 0xD0, 0x31], b"")), hash);
+    }
+
+    /// Little-endian TIFF with an ImageDescription and one strip
+    fn tiff(description: &[u8], strip: &[u8], strip_count: u32) -> Vec<u8> {
+        let data_start = 8 + 2 + 3 * 12 + 4;
+        let strip_offset = data_start + description.len() as u32;
+        let mut bytes = b"II*\0".to_vec();
+        bytes.extend(8u32.to_le_bytes());
+        bytes.extend(3u16.to_le_bytes());
+        for (tag, kind, count, value) in [
+            (270u16, 2u16, description.len() as u32, data_start),
+            (273, 4, strip_count, strip_offset),
+            (279, 4, 1, strip.len() as u32),
+        ] {
+            bytes.extend(tag.to_le_bytes());
+            bytes.extend(kind.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(description);
+        bytes.extend(strip);
+        bytes
+    }
+
+    #[test]
+    fn tiff_hash_covers_strips_and_checks_them_against_the_file() {
+        let hash = image_hash(&tiff(b"first description", b"pixels", 1));
+        assert_eq!(image_hash(&tiff(b"second", b"pixels", 1)), hash);
+        assert_ne!(image_hash(&tiff(b"first description", b"pixelz", 1)), hash);
+
+        // A huge value count is not allocated; the file is hashed in full instead
+        let (image_data, full) = hashes(&tiff(b"description", b"pixels", 0x4000_0000));
+        assert_eq!(image_data, full);
+        // So is a strip reaching past the end of the file
+        let mut truncated = tiff(b"description", b"pixels", 1);
+        truncated.truncate(truncated.len() - 2);
+        let (image_data, full) = hashes(&truncated);
+        assert_eq!(image_data, full);
+    }
+
+    fn png(text: &[u8], image: &[u8]) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", &[0u8; 13][..]), (b"tEXt", text), (b"IDAT", image), (b"IEND", &[])] {
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(kind);
+            bytes.extend(data);
+            bytes.extend([0u8; 4]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn png_hash_skips_ancillary_chunks() {
+        let hash = image_hash(&png(b"Comment\0one", b"pixels"));
+        assert_eq!(image_hash(&png(b"Comment\0another one", b"pixels")), hash);
+        assert_ne!(image_hash(&png(b"Comment\0one", b"pixelz")), hash);
+    }
+
+    fn bmff_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        bytes.extend(kind);
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// HEIC with an image item and an EXIF item, both stored in `mdat`
+    fn heic(exif: &[u8], image: &[u8]) -> Vec<u8> {
+        let ftyp = bmff_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let meta = |exif_offset: u32, image_offset: u32| {
+            let mut item_info = vec![0, 0, 0, 0, 0, 2];
+            for (item_id, item_type) in [(1u16, b"hvc1"), (2, b"Exif")] {
+                let mut entry = vec![2, 0, 0, 0];
+                entry.extend(item_id.to_be_bytes());
+                entry.extend([0, 0]);
+                entry.extend(item_type);
+                entry.push(0);
+                item_info.extend(bmff_box(b"infe", &entry));
+            }
+            let mut locations = vec![0, 0, 0, 0, 0x44, 0x00, 0, 2];
+            for (item_id, offset, length) in [(1u16, image_offset, image.len()), (2, exif_offset, exif.len())] {
+                locations.extend(item_id.to_be_bytes());
+                locations.extend([0, 0, 0, 1]);
+                locations.extend(offset.to_be_bytes());
+                locations.extend((length as u32).to_be_bytes());
+            }
+            let mut payload = vec![0, 0, 0, 0];
+            payload.extend(bmff_box(b"iinf", &item_info));
+            payload.extend(bmff_box(b"iloc", &locations));
+            bmff_box(b"meta", &payload)
+        };
+        let exif_offset = (ftyp.len() + meta(0, 0).len() + 8) as u32;
+        let image_offset = exif_offset + exif.len() as u32;
+        let mut bytes = ftyp;
+        bytes.extend(meta(exif_offset, image_offset));
+        bytes.extend(bmff_box(b"mdat", &[exif, image].concat()));
+        bytes
+    }
+
+    #[test]
+    fn heic_hash_covers_image_items_only() {
+        let hash = image_hash(&heic(b"Exif one", b"pixels"));
+        assert_eq!(image_hash(&heic(b"Exif two, longer", b"pixels")), hash);
+        assert_ne!(image_hash(&heic(b"Exif one", b"pixelz")), hash);
+    }
+
+    #[test]
+    fn video_hash_covers_media_data() {
+        let mp4 = |metadata: &[u8], media: &[u8]| {
+            [bmff_box(b"ftyp", b"isom\0\0\0\0isom"), bmff_box(b"moov", metadata), bmff_box(b"mdat", media)].concat()
+        };
+        let hash = image_hash(&mp4(b"udta one", b"frames"));
+        assert_eq!(image_hash(&mp4(b"udta two, longer", b"frames")), hash);
+        assert_ne!(image_hash(&mp4(b"udta one", b"framez")), hash);
+    }
+
+    #[test]
+    fn partial_image_data_hash_reads_the_start_of_the_image_data() {
+        let hash = |bytes: &[u8]| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("file");
+            fs::write(&path, bytes).unwrap();
+            let hasher = ContentHasher::with_mode(HashMode::ImageData);
+            (hasher.calculate_partial_image_data_hash(&path).unwrap(), hasher.calculate_file_hash(&path).unwrap())
+        };
+        let mut scan = vec![0x10; PARTIAL_HASH_BYTES as usize + 100];
+        let (partial, full) = hash(&jpeg(b"Exif one", &scan, b""));
+        assert_eq!(hash(&jpeg(b"Exif two, longer", &scan, b"")), (partial.clone(), full.clone()));
+
+        *scan.last_mut().unwrap() = 0x11;
+        let (other_partial, other_full) = hash(&jpeg(b"Exif one", &scan, b""));
+        assert_eq!(other_partial, partial, "only the start of the scan is read");
+        assert_ne!(other_full, full);
+    }
+
+    /// Grayscale image of `width`x`height` sampling `shade` at relative coordinates
+    fn picture(width: u32, height: u32, shade: impl Fn(f64, f64) -> f64) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            let value = shade(f64::from(x) / f64::from(width), f64::from(y) / f64::from(height));
+            Luma([value.clamp(0.0, 255.0) as u8])
+        }))
+    }
+
+    fn landscape(width: u32, height: u32) -> DynamicImage {
+        picture(width, height, |u, v| 128.0 + 70.0 * (u * 7.0 + v * 3.0).sin() + 50.0 * (v * 9.0 - u * 2.0).cos())
+    }
+
+    fn checkerboard(width: u32, height: u32) -> DynamicImage {
+        picture(width, height, |u, v| 128.0 + 120.0 * (u * 31.0).sin() * (v * 23.0).sin())
+    }
+
+    fn near(hash: u64, width: u32, height: u32) -> PerceptualHash {
+        PerceptualHash { hash, width, height }
+    }
+
+    #[test]
+    fn hashes_match_resized_copies_but_not_unrelated_images() {
+        let original = landscape(640, 480);
+        let resized = original.resize_exact(160, 120, FilterType::Lanczos3);
+        let unrelated = checkerboard(640, 480);
+
+        for hash in [dhash, phash] {
+            let distance = |a: &DynamicImage, b: &DynamicImage| (hash(a) ^ hash(b)).count_ones();
+            assert_eq!(distance(&original, &original), 0);
+            assert!(distance(&original, &resized) <= DEFAULT_NEAR_DUPLICATE_DISTANCE);
+            assert!(distance(&original, &unrelated) > 3 * DEFAULT_NEAR_DUPLICATE_DISTANCE);
+        }
+    }
+
+    #[test]
+    fn distance_counts_differing_bits() {
+        assert_eq!(near(0, 1, 1).distance(&near(0, 2, 2)), 0);
+        assert_eq!(near(0b1011, 1, 1).distance(&near(0b0110, 1, 1)), 3);
+        assert_eq!(near(0, 1, 1).distance(&near(u64::MAX, 1, 1)), 64);
+    }
+
+    #[test]
+    fn clusters_stop_at_the_threshold_and_keep_the_largest_image() {
+        let hashes = vec![
+            (PathBuf::from("/in/small.jpg"), near(0b111, 1600, 1200)),
+            (PathBuf::from("/in/large.jpg"), near(0, 4000, 3000)),
+            (PathBuf::from("/in/other.jpg"), near(0b1111 << 8, 4000, 3000)),
+        ];
+
+        let clusters = find_near_duplicates(&hashes, 3);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].keep, PathBuf::from("/in/large.jpg"));
+        assert_eq!(clusters[0].others, vec![(PathBuf::from("/in/small.jpg"), 3)]);
+
+        assert!(find_near_duplicates(&hashes, 2).is_empty());
+        let clusters = find_near_duplicates(&hashes, 4);
+        assert_eq!(clusters[0].keep, PathBuf::from("/in/large.jpg"), "ties on pixels fall back to the path");
+        assert_eq!(clusters[0].others, vec![(PathBuf::from("/in/other.jpg"), 4), (PathBuf::from("/in/small.jpg"), 3)]);
+    }
+
+    #[test]
+    fn clusters_hold_only_images_near_the_kept_one() {
+        // large ~ middle ~ small in a chain, but small is 6 bits from large
+        let hashes = vec![
+            (PathBuf::from("/in/small.jpg"), near(0b111_111, 800, 600)),
+            (PathBuf::from("/in/middle.jpg"), near(0b111, 1600, 1200)),
+            (PathBuf::from("/in/large.jpg"), near(0, 4000, 3000)),
+            (PathBuf::from("/in/copy.jpg"), near(0b111_111, 640, 480)),
+        ];
+        let clusters = find_near_duplicates(&hashes, 3);
+        let clusters: Vec<_> = clusters.iter().map(|cluster| (cluster.keep.to_str().unwrap(), cluster.others.clone())).collect();
+        assert_eq!(clusters, [
+            ("/in/large.jpg", vec![(PathBuf::from("/in/middle.jpg"), 3)]),
+            ("/in/small.jpg", vec![(PathBuf::from("/in/copy.jpg"), 0)]),
+        ]);
+    }
+
+    #[test]
+    fn bk_tree_finds_every_hash_within_the_distance() {
+        let hashes: Vec<u64> = (0..500u64).map(|index| index.wrapping_mul(0x9E37_79B9_7F4A_7C15).rotate_left(index as u32 % 64)).collect();
+        let mut tree = BkTree::default();
+        for (index, hash) in hashes.iter().enumerate() {
+            tree.insert(*hash, index);
+        }
+        for max_distance in [0, 6, 20, 32] {
+            for probe in &hashes[..50] {
+                let mut found = tree.within(*probe, max_distance);
+                found.sort_unstable();
+                let expected: Vec<usize> = (0..hashes.len())
+                    .filter(|index| (hashes[*index] ^ probe).count_ones() <= max_distance)
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn undecodable_files_get_no_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (image_path, text_path) = (dir.path().join("a.png"), dir.path().join("b.jpg"));
+        landscape(64, 48).save(&image_path).unwrap();
+        fs::write(&text_path, b"not an image").unwrap();
+
+        let hashes = PerceptualHasher::new(PerceptualAlgorithm::PHash).hash_files(&[image_path.clone(), text_path]);
+        assert_eq!(hashes.len(), 1);
+        assert_eq!((hashes[0].0.clone(), hashes[0].1.width, hashes[0].1.height), (image_path, 64, 48));
+        assert!("ahash".parse::<PerceptualAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,481 @@
+/*!
+ * Operation journal module for recording and reversing file operations
+ *
+ * Every move/copy/symlink is appended as one JSON line to a journal file in the
+ * output directory, once before the operation and once after it, so a whole run can be
+ * reversed later and an operation interrupted by a crash is still found.
+ */
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::checkpoint::FileStamp;
+use crate::hashing::ContentHasher;
+
+/// Journal file name, created inside the output directory
+pub const JOURNAL_FILE_NAME: &str = ".sortify-journal.jsonl";
+
+/// Mode recorded for entries that reverse an earlier operation
+const UNDO_MODE: &str = "undo";
+
+/// Progress of a journaled operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryState {
+    /// Written before the operation; without a later `done` entry the run stopped before
+    /// or right after the operation
+    Started,
+    /// Written once the operation completed (the only state in older journals)
+    #[default]
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub mode: String,
+    #[serde(default)]
+    pub state: EntryState,
+    /// Size and modification time of the target once the operation completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stamp: Option<FileStamp>,
+    /// Content hash of the target, written by older versions instead of `stamp`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Append-only journal shared by all workers of a single run
+pub struct Journal {
+    run_id: String,
+    writer: Mutex<File>,
+}
+
+impl Journal {
+    /// Open (or create) the journal in `output_dir` and start a new run
+    pub fn open(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(JOURNAL_FILE_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open journal: {}", path.display()))?;
+
+        let run_id = Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+        debug!("Journal {} opened for run {}", path.display(), run_id);
+
+        Ok(Self {
+            run_id,
+            writer: Mutex::new(file),
+        })
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Record a file operation that is about to happen
+    ///
+    /// Both paths are stored absolute, so `undo` does not depend on the directory it runs in;
+    /// the directory of `target` must exist.
+    pub fn begin(&self, source: &Path, target: &Path, mode: &str) -> Result<JournalEntry> {
+        let entry = JournalEntry {
+            run_id: self.run_id.clone(),
+            source: absolute_path(source)?,
+            target: absolute_path(target)?,
+            mode: mode.to_string(),
+            state: EntryState::Started,
+            stamp: None,
+            hash: None,
+            timestamp: Utc::now(),
+        };
+        self.append(&entry)?;
+        Ok(entry)
+    }
+
+    /// Record that the operation of a `begin` entry completed, with the size and modification
+    /// time of the target so undo can detect later changes without hashing
+    pub fn complete(&self, started: &JournalEntry) -> Result<()> {
+        self.append(&JournalEntry {
+            state: EntryState::Done,
+            stamp: FileStamp::of(&started.target),
+            timestamp: Utc::now(),
+            ..started.clone()
+        })
+    }
+
+    fn append(&self, entry: &JournalEntry) -> Result<()> {
+        let line = serde_json::to_string(entry).context("Failed to serialize journal entry")?;
+        let mut writer = self.writer.lock().unwrap();
+        // One write per line and an explicit flush keep the journal usable after a crash
+        writer.write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| writer.flush())
+            .context("Failed to write journal entry")
+    }
+
+    /// Load every entry from the journal in `output_dir`
+    pub fn load(output_dir: &Path) -> Result<Vec<JournalEntry>> {
+        let path = output_dir.join(JOURNAL_FILE_NAME);
+        let file = File::open(&path)
+            .with_context(|| format!("No journal found at {}", path.display()))?;
+
+        let mut entries = Vec::new();
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context("Failed to read journal")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<JournalEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                // A crash can leave a truncated last line; everything before it is still valid
+                Err(e) => warn!("Ignoring malformed journal line {}: {}", line_number + 1, e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// `path` made absolute through its canonical parent directory
+///
+/// The file name itself is kept as is, so the path of a symlink stays the path of the
+/// symlink, and a file that was just moved away still gets its original absolute path.
+pub fn absolute_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path.file_name()
+        .with_context(|| format!("Not a file path: {}", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let parent = parent.canonicalize()
+        .with_context(|| format!("Failed to resolve directory: {}", parent.display()))?;
+    Ok(parent.join(file_name))
+}
+
+/// Operations that happened and have not been reversed by a later undo, in journal order
+///
+/// A started operation without its `done` entry is checked on disk: it counts when the
+/// source is at the target, so a crash right after an operation does not lose it.
+pub fn pending_entries(entries: &[JournalEntry]) -> Vec<&JournalEntry> {
+    let key = |e: &JournalEntry| (e.run_id.clone(), e.target.clone());
+    let undone: HashSet<(String, PathBuf)> = entries.iter()
+        .filter(|e| e.mode == UNDO_MODE)
+        .map(key)
+        .collect();
+    let done: HashSet<(String, PathBuf)> = entries.iter()
+        .filter(|e| e.mode != UNDO_MODE && e.state == EntryState::Done)
+        .map(key)
+        .collect();
+
+    entries.iter()
+        .filter(|e| e.mode != UNDO_MODE)
+        .filter(|e| match e.state {
+            EntryState::Done => true,
+            EntryState::Started => !done.contains(&key(e)) && interrupted_operation_happened(e),
+        })
+        .filter(|e| !undone.contains(&key(e)))
+        .collect()
+}
+
+/// Whether the operation of a started entry took place before the run stopped
+fn interrupted_operation_happened(entry: &JournalEntry) -> bool {
+    let (source, target) = (&entry.source, &entry.target);
+    match entry.mode.as_str() {
+        "symlink" => fs::read_link(target).is_ok_and(|link| &link == source),
+        "move" => fs::symlink_metadata(source).is_err() && target.exists(),
+        // A copy cut short leaves a smaller target behind
+        "copy" => match (fs::metadata(source), fs::metadata(target)) {
+            (Ok(source), Ok(target)) => source.len() == target.len(),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Outcome of reversing a single journal entry
+#[derive(Debug, Clone)]
+pub struct UndoResult {
+    pub entry: JournalEntry,
+    pub undone: bool,
+    pub reason: Option<String>,
+}
+
+/// Reverse all operations of a run, newest first
+///
+/// Without `run_id` the most recent run that still has operations to reverse is used.
+/// Files that changed since they were journaled are never overwritten or deleted.
+pub fn undo_run(output_dir: &Path, run_id: Option<&str>) -> Result<(String, Vec<UndoResult>)> {
+    // Journaled paths are absolute, so compare against the canonical output directory
+    let output_dir = &output_dir.canonicalize()
+        .with_context(|| format!("Output directory not found: {}", output_dir.display()))?;
+    let entries = Journal::load(output_dir)?;
+    let pending = pending_entries(&entries);
+
+    let run_id = match run_id {
+        Some(id) => id.to_string(),
+        None => pending.last()
+            .map(|e| e.run_id.clone())
+            .context("Nothing to undo - every journaled run has already been reversed")?,
+    };
+
+    let run_entries: Vec<&JournalEntry> = pending.into_iter()
+        .filter(|e| e.run_id == run_id)
+        .collect();
+    if run_entries.is_empty() {
+        anyhow::bail!("No pending operations found for run {}", run_id);
+    }
+
+    let journal = Journal::open(output_dir)?;
+    let content_hasher = ContentHasher::new();
+    let mut results = Vec::new();
+
+    for entry in run_entries.into_iter().rev() {
+        let result = match undo_entry(entry, &content_hasher) {
+            Ok(()) => {
+                let mut record = entry.clone();
+                record.mode = UNDO_MODE.to_string();
+                record.timestamp = Utc::now();
+                if let Err(e) = journal.append(&record) {
+                    warn!("Reverted {} but failed to journal it: {}", entry.target.display(), e);
+                }
+                remove_empty_parents(&entry.target, output_dir);
+                UndoResult { entry: entry.clone(), undone: true, reason: None }
+            }
+            Err(e) => UndoResult { entry: entry.clone(), undone: false, reason: Some(e.to_string()) },
+        };
+        results.push(result);
+    }
+
+    Ok((run_id, results))
+}
+
+fn undo_entry(entry: &JournalEntry, content_hasher: &ContentHasher) -> Result<()> {
+    let target = &entry.target;
+    let source = &entry.source;
+
+    // A relative path would be resolved against the directory undo runs in, not the one the
+    // operation ran in
+    if !source.is_absolute() || !target.is_absolute() {
+        anyhow::bail!("Journal entry has a relative path ({} -> {})", source.display(), target.display());
+    }
+
+    if entry.mode == "symlink" {
+        let link = fs::read_link(target)
+            .with_context(|| format!("Symlink no longer exists: {}", target.display()))?;
+        if &link != source {
+            anyhow::bail!("Symlink now points to {}", link.display());
+        }
+        fs::remove_file(target).context("Failed to remove symlink")?;
+        return Ok(());
+    }
+
+    if !target.exists() {
+        anyhow::bail!("Target no longer exists");
+    }
+    match (&entry.stamp, &entry.hash) {
+        (Some(stamp), _) => {
+            if FileStamp::of(target).as_ref() != Some(stamp) {
+                anyhow::bail!("Target changed since it was written (size or modification time differs)");
+            }
+        }
+        (None, Some(hash)) => {
+            let current_hash = content_hasher.calculate_file_hash(target)?;
+            if &current_hash != hash {
+                anyhow::bail!("Target changed since it was written (hash {} != {})", current_hash, hash);
+            }
+        }
+        // Interrupted before its `done` entry: a move can always be put back, but a copy is
+        // only removed while it still matches its source
+        (None, None) => {
+            if entry.mode == "copy" && content_hasher.calculate_file_hash(target)? != content_hasher.calculate_file_hash(source)? {
+                anyhow::bail!("Target differs from its source and was not recorded as complete");
+            }
+        }
+    }
+
+    match entry.mode.as_str() {
+        "copy" => {
+            fs::remove_file(target).context("Failed to remove copied file")?;
+        }
+        "move" => {
+            if source.exists() {
+                anyhow::bail!("Original location is occupied: {}", source.display());
+            }
+            if let Some(parent) = source.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to recreate directory: {}", parent.display()))?;
+            }
+            match fs::rename(target, source) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                    fs::copy(target, source).context("Failed to copy file back")?;
+                    fs::remove_file(target).context("Failed to remove moved file")?;
+                }
+                Err(e) => return Err(e).context("Failed to move file back"),
+            }
+        }
+        other => anyhow::bail!("Unknown journal mode: {}", other),
+    }
+    Ok(())
+}
+
+/// Remove directories emptied by an undo, stopping at the output directory
+fn remove_empty_parents(target: &Path, output_dir: &Path) {
+    let mut dir = target.parent();
+    while let Some(current) = dir {
+        if current == output_dir || !current.starts_with(output_dir) || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn undo_moves_files_back_and_records_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("out");
+        let source = dir.path().join("in/IMG_0001.jpg");
+        let target = output_dir.join("2024/01-Jan/20240101_120000.000.jpg");
+        write(&source, "photo");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+
+        let journal = Journal::open(&output_dir).unwrap();
+        let started = journal.begin(&source, &target, "move").unwrap();
+        fs::rename(&source, &target).unwrap();
+        journal.complete(&started).unwrap();
+        let run_id = journal.run_id().to_string();
+        drop(journal);
+
+        let (undone_run, results) = undo_run(&output_dir, None).unwrap();
+        assert_eq!(undone_run, run_id);
+        assert!(results.iter().all(|r| r.undone), "{:?}", results);
+        assert_eq!(fs::read_to_string(&source).unwrap(), "photo");
+        assert!(!output_dir.join("2024").exists(), "emptied directories are removed");
+
+        let entries = Journal::load(&output_dir).unwrap();
+        assert!(pending_entries(&entries).is_empty());
+        assert!(undo_run(&output_dir, None).is_err(), "nothing left to undo");
+    }
+
+    #[test]
+    fn begin_stores_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().canonicalize().unwrap();
+        let target = output_dir.join("a.jpg");
+        write(&target, "photo");
+
+        let journal = Journal::open(&output_dir).unwrap();
+        // A relative path is resolved against the current directory at record time
+        let started = journal.begin(Path::new("Cargo.toml"), &target, "copy").unwrap();
+        journal.complete(&started).unwrap();
+        let entries = Journal::load(&output_dir).unwrap();
+        assert_eq!(entries[0].source, std::env::current_dir().unwrap().canonicalize().unwrap().join("Cargo.toml"));
+        assert_eq!(entries[0].target, target);
+        assert_eq!((entries[0].state, entries[1].state), (EntryState::Started, EntryState::Done));
+        assert_eq!(entries[1].stamp, FileStamp::of(&target));
+    }
+
+    #[test]
+    fn undo_rejects_relative_and_changed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().canonicalize().unwrap();
+        let target = output_dir.join("a.jpg");
+        write(&target, "photo");
+        let hasher = ContentHasher::new();
+        let entry = |source: &str, target: &Path, stamp: Option<FileStamp>, hash: Option<&str>| JournalEntry {
+            run_id: "run".to_string(),
+            source: PathBuf::from(source),
+            target: target.to_path_buf(),
+            mode: "move".to_string(),
+            state: EntryState::Done,
+            stamp,
+            hash: hash.map(str::to_string),
+            timestamp: Utc::now(),
+        };
+        let stamp = FileStamp::of(&target);
+
+        let relative = undo_entry(&entry("in/a.jpg", &target, stamp, None), &hasher).unwrap_err();
+        assert!(relative.to_string().contains("relative path"), "{}", relative);
+
+        let source = output_dir.join("in/a.jpg");
+        let resized = stamp.map(|stamp| FileStamp { size: stamp.size + 1, ..stamp });
+        let changed = undo_entry(&entry(source.to_str().unwrap(), &target, resized, None), &hasher).unwrap_err();
+        assert!(changed.to_string().contains("changed"), "{}", changed);
+        // Older journals recorded a content hash instead
+        let changed = undo_entry(&entry(source.to_str().unwrap(), &target, None, Some("0000")), &hasher).unwrap_err();
+        assert!(changed.to_string().contains("changed"), "{}", changed);
+        assert!(target.exists() && !source.exists());
+    }
+
+    #[test]
+    fn pending_entries_skip_undone_operations() {
+        let entry = |run_id: &str, target: &str, mode: &str| JournalEntry {
+            run_id: run_id.to_string(),
+            source: PathBuf::from("/in").join(target),
+            target: PathBuf::from("/out").join(target),
+            mode: mode.to_string(),
+            state: EntryState::Done,
+            stamp: None,
+            hash: None,
+            timestamp: Utc::now(),
+        };
+        let entries = vec![
+            entry("1", "a.jpg", "move"),
+            entry("1", "b.jpg", "move"),
+            entry("1", "a.jpg", UNDO_MODE),
+            entry("2", "a.jpg", "copy"),
+        ];
+        let pending: Vec<_> = pending_entries(&entries).into_iter()
+            .map(|e| (e.run_id.as_str(), e.target.to_str().unwrap()))
+            .collect();
+        assert_eq!(pending, [("1", "/out/b.jpg"), ("2", "/out/a.jpg")]);
+    }
+
+    #[test]
+    fn interrupted_operations_count_only_when_they_happened() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().canonicalize().unwrap();
+        let moved = output_dir.join("in/moved.jpg");
+        let unmoved = output_dir.join("in/unmoved.jpg");
+        let copied = output_dir.join("in/copied.jpg");
+        for source in [&moved, &unmoved, &copied] {
+            write(source, "photo");
+        }
+        let target = |name: &str| output_dir.join("2024").join(name);
+        fs::create_dir_all(output_dir.join("2024")).unwrap();
+
+        // The run stops after each operation but before its `done` entry
+        let journal = Journal::open(&output_dir).unwrap();
+        journal.begin(&moved, &target("moved.jpg"), "move").unwrap();
+        fs::rename(&moved, target("moved.jpg")).unwrap();
+        journal.begin(&unmoved, &target("unmoved.jpg"), "move").unwrap();
+        journal.begin(&copied, &target("copied.jpg"), "copy").unwrap();
+        fs::write(target("copied.jpg"), "ph").unwrap();
+        drop(journal);
+
+        let entries = Journal::load(&output_dir).unwrap();
+        let pending: Vec<_> = pending_entries(&entries).into_iter().map(|e| e.target.clone()).collect();
+        assert_eq!(pending, [target("moved.jpg")], "the unmoved file and the partial copy did not happen");
+
+        let (_, results) = undo_run(&output_dir, None).unwrap();
+        assert!(results.iter().all(|r| r.undone), "{:?}", results);
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "photo");
+    }
+}
@@ -1,5 +1,13 @@
+pub mod burst;
+pub mod checkpoint;
+pub mod config;
+pub mod events;
 pub mod exif;
 pub mod exif_writer;
+pub mod field_policy;
 pub mod file_ops;
+pub mod filename_date;
+pub mod journal;
 pub mod naming;
+pub mod sidecar;
 pub mod hashing;
@@ -1,4 +1,4 @@
-/**
+/*!
  * This code written by Claude Sonnet 4 (claude-3-5-sonnet-20241022)
  * Generated via Cursor IDE (cursor.sh) with AI assistance
  * Model: Anthropic Claude 3.5 Sonnet
@@ -14,18 +14,36 @@
  */
 
 use anyhow::{Result, Context};
-use clap::{Parser, Subcommand};
-use log::info;
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+use log::{info, warn};
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+mod burst;
+mod checkpoint;
+mod config;
+mod events;
 mod exif;
+mod field_policy;
 mod file_ops;
+mod filename_date;
+mod journal;
 mod naming;
+mod sidecar;
 mod hashing;
 
-use file_ops::{FileProcessor, ProcessResult};
-use exif::ExifProcessor;
+use burst::DEFAULT_BURST_GAP_MS;
+use checkpoint::Checkpoint;
+use events::DEFAULT_EVENT_GAP_HOURS;
+use config::Config;
+use file_ops::{FileProcessor, ProcessOptions, ProcessResult, TRASH_DIR_NAME};
+use exif::{Confidence, DefaultTimezone, ExifOptions, ExifProcessor, Provenance, SanityRange, TimestampSource, DEFAULT_CONFLICT_THRESHOLD_SECS};
+use field_policy::{FieldPolicy, DEFAULT_PRESET};
+use filename_date::{FilenameDateExtractor, FilenameDatePattern};
+use hashing::{ContentHasher, HashMode, PerceptualAlgorithm, PerceptualHasher, DEFAULT_NEAR_DUPLICATE_DISTANCE};
+use naming::{NamingTemplate, NamingTime, TieBreakOrder, DEFAULT_MTIME_FOLDER, EVENTS_TEMPLATE};
 
 #[derive(Parser)]
 #[command(name = "sortify-rs")]
@@ -39,7 +57,7 @@ File operation modes:
 - symlink: Create symbolic links to organized structure
 
 Supported file types: JPG, JPEG, PNG, TIFF, HIF, HEIC, CR2, DNG, NEF, MOV, MP4, AVI
-Output format: YYYY/MM-Mon/YYYYMMDD_HHMMSS.fff<ext>
+Output format: YYYY/MM-Mon/YYYYMMDD_HHMMSS.fff<ext> (customizable with --template)
 Tie-breaking: Files with identical timestamps get -2, -3, etc. suffixes")]
 struct Cli {
     /// Increase verbosity (-v=INFO, -vv=DEBUG, -vvv=TRACE)
@@ -72,6 +90,8 @@ enum Commands {
         /// Delete duplicate files that are not sorted (use with caution!)
         #[arg(long)]
         delete_duplicates: bool,
+        #[command(flatten)]
+        sort_args: SortArgs,
     },
     /// Process all image files in one or more directories (recursive by default)
     Batch {
@@ -95,6 +115,20 @@ enum Commands {
         /// Delete duplicate files that are not sorted (use with caution!)
         #[arg(long)]
         delete_duplicates: bool,
+        /// Ignore the checkpoint of an interrupted run and start from scratch
+        #[arg(long)]
+        no_resume: bool,
+        #[command(flatten)]
+        sort_args: SortArgs,
+    },
+    /// Reverse a previous files/batch run using the journal in its output directory
+    Undo {
+        /// Output directory of the run to undo (default: current directory)
+        #[arg(short, long, default_value = ".")]
+        output_dir: PathBuf,
+        /// Run ID to undo (default: most recent run that has not been undone)
+        #[arg(long)]
+        run: Option<String>,
     },
     /// Write EXIF data to image files
     Write {
@@ -138,6 +172,301 @@ enum Commands {
     },
 }
 
+/// Options shared by the `files` and `batch` commands
+#[derive(Args, Debug)]
+struct SortArgs {
+    /// Print the full rename plan (targets, tie-break suffixes, duplicates) without touching any files
+    #[arg(long, alias = "plan")]
+    dry_run: bool,
+    /// Naming template for target paths, e.g. "{year}/{year}-{month}-{day}/{date}_{time}.{ms}{suffix}.{ext}"
+    /// (tokens: year, month, month_abbr, month_name, day, hour, minute, second, ms, subsec, date, time, suffix,
+    /// burst, event, ext, make, model, camera, lens, serial, meta:<Field>; metadata tokens accept a fallback: {lens|NoLens})
+    #[arg(long)]
+    template: Option<String>,
+    /// Folder layout: "date" (year and month folders, default) or "events" (one folder per
+    /// shooting session, split at gaps longer than --event-gap)
+    #[arg(long, default_value = "date", conflicts_with = "template")]
+    organize: String,
+    /// Timezone assumed for timestamps without an embedded offset or OffsetTime* tag, and
+    /// that UTC video times are converted to: "UTC", an offset such as "+02:00", or "local"
+    /// for the zone of this machine (default: treat them as UTC)
+    #[arg(long)]
+    timezone: Option<String>,
+    /// Clock used for date/time in target paths: "local" capture time (default) or "utc"
+    #[arg(long, default_value = "local")]
+    name_time: String,
+    /// Whether a camera stores QuickTime video dates in "utc" (converted to the --timezone zone) or
+    /// "local" time, e.g. --video-time "GoPro=utc" (repeatable, Make or Model, "*" for all others)
+    #[arg(long, value_name = "CAMERA=POLICY")]
+    video_time: Vec<String>,
+    /// Correct a camera clock before naming, e.g. --clock-offset "Canon EOS R6=+00:03:12"
+    /// (repeatable, serial number, Model or Make; the offset is added to the timestamp)
+    #[arg(long, value_name = "CAMERA=OFFSET")]
+    clock_offset: Vec<String>,
+    /// Date files without a metadata timestamp from other sources, in order: "filename"
+    /// (dates encoded in names like IMG-20230412-WA0003.jpg) and/or "mtime" (filesystem
+    /// modification time, placed under --mtime-folder). Off by default.
+    #[arg(long, value_delimiter = ',')]
+    fallback: Vec<String>,
+    /// Folder inside the output directory for files dated by --fallback mtime
+    #[arg(long, default_value = DEFAULT_MTIME_FOLDER)]
+    mtime_folder: String,
+    /// Extra regex for the filename fallback with named groups year, month, day and optional
+    /// hour, minute, second, ms (repeatable, tried before the built-in patterns)
+    #[arg(long, value_name = "REGEX")]
+    filename_pattern: Vec<String>,
+    /// Timestamp field priority preset: "default", "original-first" or "strict" (no modification dates)
+    #[arg(long, value_name = "PRESET")]
+    timestamp_policy: Option<String>,
+    /// Timestamp fields to try first, in order, e.g. --prefer DateTimeOriginal,CreateDate
+    /// ("Field+SubSecField" combines a field with its subseconds)
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    prefer: Vec<String>,
+    /// Timestamp fields never to use, e.g. --ignore-field ModifyDate
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    ignore_field: Vec<String>,
+    /// How files with the same target name are ordered before -2, -3 suffixes are assigned:
+    /// any of "time" (full subsecond precision), "sequence" (burst/image number from the
+    /// metadata), "filename" (counter in the original name) and "path"
+    #[arg(long, value_delimiter = ',', value_name = "KEYS", default_value = "time,sequence,filename")]
+    tie_break: Vec<String>,
+    /// Timestamp to use when metadata fields disagree: "first" in field priority (default),
+    /// "earliest", "majority" or "skip" the file; conflicts are always reported
+    #[arg(long, value_name = "POLICY")]
+    conflict_policy: Option<String>,
+    /// Seconds metadata timestamps may differ before they count as a conflict [default: 60]
+    #[arg(long, value_name = "SECONDS")]
+    conflict_threshold: Option<u32>,
+    /// How XMP sidecars (IMG_1234.xmp, IMG_1234.CR3.xmp) combine with embedded metadata:
+    /// "prefer" sidecar values (default), use them as "fallback" only, or "off"
+    #[arg(long, value_name = "PRECEDENCE")]
+    xmp: Option<String>,
+    /// Reject capture times before this date (YYYY-MM-DD) and try the next field instead
+    #[arg(long, value_name = "DATE", default_value = "1900-01-01")]
+    min_date: String,
+    /// Reject capture times more than this many days in the future (camera clock drift)
+    #[arg(long, value_name = "DAYS", default_value_t = 1)]
+    future_grace_days: u32,
+    /// Group frames of one camera taken at most this many milliseconds apart into bursts for
+    /// the {burst} template token [default when the template uses {burst}: 300]
+    #[arg(long, value_name = "MS")]
+    burst_gap: Option<u32>,
+    /// Start a new event after this many hours without a file, for --organize events and the
+    /// {event} template token [default: 4]
+    #[arg(long, value_name = "HOURS")]
+    event_gap: Option<u32>,
+    /// Event folder labels: "numbered" (2024-05-01_event-1, default) or "range" (2024-05-01_2024-05-03)
+    #[arg(long, value_name = "NAMING")]
+    event_naming: Option<String>,
+    /// What must match for a file to be skipped as a content duplicate: "full" file contents
+    /// (default) or "image-data" only, so copies differing only in EXIF/XMP also count
+    #[arg(long, value_name = "MODE", default_value = "full")]
+    hash_mode: String,
+    /// With --delete-duplicates, also remove image-data duplicates whose metadata differs from
+    /// the file kept, moving them to .sortify-trash in the output directory; `undo` restores them
+    #[arg(long, requires = "delete_duplicates")]
+    delete_image_data_duplicates: bool,
+    /// Find content duplicates among all inputs and the files already in the output directory,
+    /// not only among files whose timestamps collide (compares sizes, then the first and last
+    /// 64 KiB, then full hashes)
+    #[arg(long)]
+    dedupe_all: bool,
+    /// After sorting, report clusters of visually near-identical images (re-saved, resized or
+    /// metadata-stripped copies) found with a perceptual hash; nothing is deleted
+    #[arg(long)]
+    near_duplicates: bool,
+    /// Perceptual hash for --near-duplicates: "dhash" (default) or "phash" (slower, more robust)
+    #[arg(long, value_name = "ALGORITHM", default_value = "dhash")]
+    near_duplicate_hash: String,
+    /// Largest number of differing hash bits (0-64) for two images to count as near duplicates
+    #[arg(long, value_name = "BITS", default_value_t = DEFAULT_NEAR_DUPLICATE_DISTANCE)]
+    near_duplicate_distance: u32,
+    /// Move every image of a near-duplicate cluster except the one kept (largest resolution)
+    /// to .sortify-trash in the output directory, after confirmation; `undo` restores them
+    #[arg(long, requires = "near_duplicates")]
+    delete_near_duplicates: bool,
+    /// Confirm --delete-near-duplicates without a prompt; required when no prompt can be
+    /// answered (--machine-readable or input that is not a terminal)
+    #[arg(long, requires = "delete_near_duplicates")]
+    yes: bool,
+    /// TOML config file with filename_patterns, xmp, burst_gap_ms, event_gap_hours, event_naming, [timestamp_policy] and per-camera tables
+    /// ([video_time], [clock_offset])
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+/// Settings of the near-duplicate report
+struct NearDuplicateOptions {
+    algorithm: PerceptualAlgorithm,
+    max_distance: u32,
+    delete: bool,
+    /// Deletion was confirmed with --yes, so there is no prompt
+    confirmed: bool,
+}
+
+impl SortArgs {
+    /// Near-duplicate report settings, None unless --near-duplicates is given
+    ///
+    /// Deleting without a prompt that can be answered needs --yes.
+    fn near_duplicate_options(&self, machine_readable: bool) -> Result<Option<NearDuplicateOptions>> {
+        if !self.near_duplicates {
+            return Ok(None);
+        }
+        let algorithm = self.near_duplicate_hash.parse().context("Invalid --near-duplicate-hash")?;
+        if self.near_duplicate_distance > 64 {
+            anyhow::bail!("Invalid --near-duplicate-distance {} (hashes have 64 bits)", self.near_duplicate_distance);
+        }
+        let interactive = !machine_readable && std::io::stdin().is_terminal();
+        if self.delete_near_duplicates && !self.dry_run && !self.yes && !interactive {
+            anyhow::bail!("--delete-near-duplicates cannot ask for confirmation here; pass --yes to confirm");
+        }
+        Ok(Some(NearDuplicateOptions {
+            algorithm,
+            max_distance: self.near_duplicate_distance,
+            delete: self.delete_near_duplicates,
+            confirmed: self.yes,
+        }))
+    }
+
+    /// Hash of the config file contents, so editing the file between runs is a new invocation
+    fn config_fingerprint(&self) -> Result<Option<u64>> {
+        self.config.as_ref()
+            .map(|path| std::fs::read(path)
+                .map(|contents| xxhash_rust::xxh3::xxh3_64(&contents))
+                .with_context(|| format!("Failed to read config file: {}", path.display())))
+            .transpose()
+    }
+
+    fn to_process_options(&self) -> Result<ProcessOptions> {
+        let naming_template = match (&self.template, self.organize.as_str()) {
+            (Some(template), _) => NamingTemplate::parse(template).context("Invalid --template")?,
+            (None, "date") => NamingTemplate::default(),
+            (None, "events") => NamingTemplate::parse(EVENTS_TEMPLATE)?,
+            (None, other) => anyhow::bail!("Invalid --organize '{}' (expected date or events)", other),
+        };
+
+        let default_timezone = self.timezone.as_deref()
+            .map(str::parse::<DefaultTimezone>)
+            .transpose()
+            .context("Invalid --timezone")?;
+        let naming_time: NamingTime = self.name_time.parse().context("Invalid --name-time")?;
+        let config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        // Command line entries override the config file, which overrides the built-in table
+        let mut exif_options = ExifOptions { default_timezone, ..Default::default() };
+        for (camera, policy) in config.video_time {
+            exif_options.video_time.insert(&camera, policy);
+        }
+        for entry in &self.video_time {
+            let (camera, policy) = config::parse_camera_entry(entry).context("Invalid --video-time")?;
+            exif_options.video_time.insert(&camera, policy);
+        }
+        for (camera, offset) in config.clock_offset {
+            exif_options.clock_offsets.insert(&camera, offset);
+        }
+        for entry in &self.clock_offset {
+            let (camera, offset) = config::parse_camera_entry(entry).context("Invalid --clock-offset")?;
+            exif_options.clock_offsets.insert(&camera, offset);
+        }
+        exif_options.xmp = match &self.xmp {
+            Some(precedence) => precedence.parse().context("Invalid --xmp")?,
+            None => config.xmp.unwrap_or_default(),
+        };
+        exif_options.fallbacks = self.fallback.iter()
+            .map(|fallback| fallback.parse())
+            .collect::<Result<_>>()
+            .context("Invalid --fallback")?;
+        let custom_patterns = self.filename_pattern.iter()
+            .chain(&config.filename_patterns)
+            .enumerate()
+            .map(|(index, pattern)| FilenameDatePattern::new(&format!("custom-{}", index + 1), pattern, false))
+            .collect::<Result<Vec<_>>>()?;
+        exif_options.filename_dates = FilenameDateExtractor::new(custom_patterns);
+
+        // Field policy: preset, then explicit lists from the config file, then prefer/ignore
+        let policy_config = config.timestamp_policy;
+        let preset = self.timestamp_policy.as_deref()
+            .or(policy_config.preset.as_deref())
+            .unwrap_or(DEFAULT_PRESET);
+        let mut field_policy = FieldPolicy::preset(preset)?;
+        if let Some(photo) = &policy_config.photo {
+            field_policy.photo = FieldPolicy::parse_fields(photo).context("Invalid [timestamp_policy] photo")?;
+        }
+        if let Some(video) = &policy_config.video {
+            field_policy.video = FieldPolicy::parse_fields(video).context("Invalid [timestamp_policy] video")?;
+        }
+        field_policy.prefer(&FieldPolicy::parse_fields(&policy_config.prefer).context("Invalid [timestamp_policy] prefer")?);
+        field_policy.ignore(&FieldPolicy::parse_fields(&policy_config.ignore).context("Invalid [timestamp_policy] ignore")?);
+        field_policy.prefer(&FieldPolicy::parse_fields(&self.prefer).context("Invalid --prefer")?);
+        field_policy.ignore(&FieldPolicy::parse_fields(&self.ignore_field).context("Invalid --ignore-field")?);
+        exif_options.field_policy = field_policy;
+
+        exif_options.conflict_policy = match &self.conflict_policy {
+            Some(policy) => policy.parse().context("Invalid --conflict-policy")?,
+            None => policy_config.conflict.unwrap_or_default(),
+        };
+        let conflict_threshold = self.conflict_threshold
+            .or(policy_config.conflict_threshold)
+            .map_or(DEFAULT_CONFLICT_THRESHOLD_SECS, i64::from);
+        exif_options.conflict_threshold = chrono::Duration::seconds(conflict_threshold);
+
+        let min_date = NaiveDate::parse_from_str(&self.min_date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid --min-date '{}' (expected YYYY-MM-DD)", self.min_date))?;
+        exif_options.sanity = SanityRange {
+            min: min_date.and_hms_opt(0, 0, 0).unwrap(),
+            future_grace: chrono::Duration::days(self.future_grace_days.into()),
+        };
+
+        let tie_break = self.tie_break.iter()
+            .map(|key| key.parse())
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid --tie-break")?;
+        let tie_break = TieBreakOrder::new(tie_break);
+
+        let burst_gap = self.burst_gap.or(config.burst_gap_ms);
+        if burst_gap.is_some() && !naming_template.uses_burst() {
+            warn!("--burst-gap has no effect unless the template contains {{burst}}");
+        }
+        let burst_gap = burst_gap
+            .or(naming_template.uses_burst().then_some(DEFAULT_BURST_GAP_MS))
+            .map(|gap| chrono::Duration::milliseconds(gap.into()));
+
+        let event_gap = self.event_gap.or(config.event_gap_hours);
+        if event_gap.is_some() && !naming_template.uses_event() {
+            warn!("--event-gap has no effect unless the template contains {{event}} or --organize events is used");
+        }
+        let event_gap = naming_template.uses_event()
+            .then(|| chrono::Duration::hours(event_gap.unwrap_or(DEFAULT_EVENT_GAP_HOURS).into()));
+        let event_naming = match &self.event_naming {
+            Some(naming) => naming.parse().context("Invalid --event-naming")?,
+            None => config.event_naming.unwrap_or_default(),
+        };
+
+        let mtime_folder_is_relative = Path::new(&self.mtime_folder).components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+        if self.mtime_folder.is_empty() || !mtime_folder_is_relative {
+            anyhow::bail!("Invalid --mtime-folder '{}': must be a relative path inside the output directory", self.mtime_folder);
+        }
+
+        Ok(ProcessOptions {
+            dry_run: self.dry_run,
+            naming_template,
+            naming_time,
+            exif_options,
+            mtime_folder: self.mtime_folder.clone(),
+            tie_break,
+            burst_gap,
+            event_gap,
+            event_naming,
+            hash_mode: self.hash_mode.parse().context("Invalid --hash-mode")?,
+            dedupe_all: self.dedupe_all,
+        })
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -147,11 +476,14 @@ fn main() -> Result<()> {
     info!("Starting sortify-rs");
 
     match cli.command {
-        Commands::Files { files, workers, output_dir, mode, delete_duplicates } => {
-            process_files(files, workers, output_dir, mode, cli.machine_readable, delete_duplicates)
+        Commands::Files { files, workers, output_dir, mode, delete_duplicates, sort_args } => {
+            process_files(files, workers, output_dir, mode, cli.machine_readable, delete_duplicates, sort_args)
         }
-        Commands::Batch { directories, workers, limit, output_dir, mode, no_recursive, delete_duplicates } => {
-            process_batch(directories, workers, limit, output_dir, mode, !no_recursive, cli.machine_readable, delete_duplicates)
+        Commands::Batch { directories, workers, limit, output_dir, mode, no_recursive, delete_duplicates, no_resume, sort_args } => {
+            process_batch(directories, workers, limit, output_dir, mode, !no_recursive, cli.machine_readable, delete_duplicates, !no_resume, sort_args)
+        }
+        Commands::Undo { output_dir, run } => {
+            undo_run(output_dir, run, cli.machine_readable)
         }
         Commands::Write { files, timestamp, artist, copyright, description, backup } => {
             write_exif_data(files, timestamp, artist, copyright, description, backup)
@@ -177,25 +509,32 @@ fn setup_logging(verbosity: u8) -> Result<()> {
     Ok(())
 }
 
-fn process_files(files: Vec<PathBuf>, workers: Option<usize>, output_dir: PathBuf, mode: String, machine_readable: bool, delete_duplicates: bool) -> Result<()> {
+fn process_files(files: Vec<PathBuf>, workers: Option<usize>, output_dir: PathBuf, mode: String, machine_readable: bool, delete_duplicates: bool, sort_args: SortArgs) -> Result<()> {
     if files.is_empty() {
         anyhow::bail!("No files specified");
     }
 
     info!("Processing {} files", files.len());
 
-    let mut file_processor = FileProcessor::new(workers);
+    let near_duplicates = sort_args.near_duplicate_options(machine_readable)?;
+    let mut file_processor = FileProcessor::new(workers, sort_args.to_process_options()?);
     let results = file_processor.process_files(files, &output_dir, &mode)?;
 
+    if sort_args.dry_run {
+        print_plan(&results, &mode, machine_readable);
+        return report_near_duplicates(&results, near_duplicates, None, machine_readable);
+    }
+
     // Handle duplicate deletion if requested
     if delete_duplicates {
-        delete_duplicate_files(&results, machine_readable)?;
+        delete_duplicate_files(&results, &sort_args, (&file_processor, &output_dir), machine_readable)?;
     }
 
     print_summary(&results, machine_readable);
-    Ok(())
+    report_near_duplicates(&results, near_duplicates, Some((&file_processor, &output_dir)), machine_readable)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_batch(
     directories: Vec<PathBuf>,
     workers: Option<usize>,
@@ -205,15 +544,64 @@ fn process_batch(
     recursive: bool,
     machine_readable: bool,
     delete_duplicates: bool,
+    resume: bool,
+    sort_args: SortArgs,
 ) -> Result<()> {
     if directories.is_empty() {
         anyhow::bail!("No directories specified");
     }
 
-    // Collect all image files from directories
+    // A checkpoint lets a rerun of the same invocation pick up where an interrupted run stopped
+    let mut checkpoint = if resume && !sort_args.dry_run {
+        let invocation = format!(
+            "batch|{:?}|{}|{}|{}|{}|{:?}|{:?}",
+            directories.iter().map(|d| d.canonicalize().unwrap_or_else(|_| d.clone())).collect::<Vec<_>>(),
+            output_dir.canonicalize().unwrap_or_else(|_| output_dir.clone()).display(),
+            mode, recursive, limit, sort_args, sort_args.config_fingerprint()?,
+        );
+        Some(Checkpoint::load_or_new(&output_dir, &invocation)?)
+    } else {
+        None
+    };
+
+    let all_files = match checkpoint.as_mut() {
+        Some(checkpoint) if !checkpoint.files.is_empty() => {
+            info!("Reusing file list of interrupted run ({} files)", checkpoint.files.len());
+            checkpoint.files.clone()
+        }
+        checkpoint => {
+            let all_files = scan_directories(&directories, recursive, limit)?;
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.files = all_files.clone();
+                checkpoint.save()?;
+            }
+            all_files
+        }
+    };
+
+    let near_duplicates = sort_args.near_duplicate_options(machine_readable)?;
+    let mut file_processor = FileProcessor::new(workers, sort_args.to_process_options()?);
+    let results = file_processor.process_files_with_checkpoint(all_files, &output_dir, &mode, checkpoint.as_mut())?;
+
+    if sort_args.dry_run {
+        print_plan(&results, &mode, machine_readable);
+        return report_near_duplicates(&results, near_duplicates, None, machine_readable);
+    }
+
+    // Handle duplicate deletion if requested
+    if delete_duplicates {
+        delete_duplicate_files(&results, &sort_args, (&file_processor, &output_dir), machine_readable)?;
+    }
+
+    print_summary(&results, machine_readable);
+    report_near_duplicates(&results, near_duplicates, Some((&file_processor, &output_dir)), machine_readable)
+}
+
+/// Collect all image files from directories, sorted, deduplicated and limited
+fn scan_directories(directories: &[PathBuf], recursive: bool, limit: usize) -> Result<Vec<PathBuf>> {
     let mut all_files = Vec::new();
     
-    for directory in &directories {
+    for directory in directories {
         info!("Scanning directory: {} (recursive: {})", directory.display(), recursive);
         let files = find_image_files(directory, recursive)?;
         all_files.extend(files);
@@ -231,17 +619,7 @@ fn process_batch(
     }
 
     info!("Total files to process: {}", all_files.len());
-
-    let mut file_processor = FileProcessor::new(workers);
-    let results = file_processor.process_files(all_files, &output_dir, &mode)?;
-
-    // Handle duplicate deletion if requested
-    if delete_duplicates {
-        delete_duplicate_files(&results, machine_readable)?;
-    }
-
-    print_summary(&results, machine_readable);
-    Ok(())
+    Ok(all_files)
 }
 
 fn find_image_files(directory: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
@@ -274,40 +652,104 @@ fn find_image_files(directory: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
 }
 
 /// Delete duplicate files that were skipped due to content duplication
-fn delete_duplicate_files(results: &[ProcessResult], machine_readable: bool) -> Result<()> {
+/// Delete files skipped as content duplicates
+///
+/// With `--hash-mode image-data` a duplicate only shares its image data and may carry
+/// metadata the kept file lacks. Such files are left alone unless
+/// `--delete-image-data-duplicates` is given, and then moved to the journaled trash
+/// instead of being deleted, so `undo` restores them. With `--dedupe-all` the duplicates are
+/// matched across the whole library, so every one of them goes to the trash.
+fn delete_duplicate_files(
+    results: &[ProcessResult],
+    sort_args: &SortArgs,
+    (file_processor, output_dir): (&FileProcessor, &Path),
+    machine_readable: bool,
+) -> Result<()> {
     use std::fs;
-    
+
     // Find all files that were skipped due to content duplication
     let duplicate_files: Vec<&ProcessResult> = results
         .iter()
         .filter(|r| r.success && !r.renamed)
-        .filter(|r| r.error.as_ref().map_or(false, |e| e.contains("Content duplicate")))
+        .filter(|r| r.error.as_ref().is_some_and(|e| e.contains("Content duplicate")))
         .collect();
-    
+
     if duplicate_files.is_empty() {
         if !machine_readable {
             println!("No duplicate files found to delete.");
         }
         return Ok(());
     }
-    
+
+    // Image-data duplicates whose bytes differ from the file they duplicate
+    let image_data = sort_args.hash_mode.parse::<HashMode>().context("Invalid --hash-mode")? == HashMode::ImageData;
+    let content_hasher = ContentHasher::new();
+    let differs = |result: &ProcessResult| {
+        let Some(original) = &result.duplicate_of else {
+            return true;
+        };
+        // The original may have been sorted by this run
+        let original = results.iter()
+            .find(|r| &r.file_path == original && r.renamed)
+            .and_then(|r| r.new_path.as_ref())
+            .unwrap_or(original);
+        match content_hasher.calculate_file_hash(&result.file_path) {
+            Ok(hash) => content_hasher.calculate_file_hash(original).ok() != Some(hash),
+            Err(_) => true,
+        }
+    };
+    let (to_trash, to_delete): (Vec<&ProcessResult>, Vec<&ProcessResult>) = duplicate_files.into_iter()
+        .partition(|result| image_data && differs(result));
+    let (to_trash, kept) = if sort_args.delete_image_data_duplicates {
+        (to_trash, Vec::new())
+    } else {
+        (Vec::new(), to_trash)
+    };
+    let (to_trash, to_delete) = if sort_args.dedupe_all {
+        (to_trash.into_iter().chain(to_delete).collect(), Vec::new())
+    } else {
+        (to_trash, to_delete)
+    };
+
+    for result in &kept {
+        if !machine_readable {
+            println!("Kept {}: its metadata differs from the duplicate (pass --delete-image-data-duplicates to trash it)",
+                result.file_path.display());
+        } else {
+            println!("KEPT|{}|Image-data duplicate with different metadata", result.file_path.display());
+        }
+    }
+    if to_delete.is_empty() && to_trash.is_empty() {
+        return Ok(());
+    }
+
     if !machine_readable {
-        println!("\nFound {} duplicate files to delete:", duplicate_files.len());
-        for result in &duplicate_files {
-            println!("  {}", result.file_path.display());
+        if !to_delete.is_empty() {
+            println!("\nFound {} duplicate files to delete:", to_delete.len());
+            for result in &to_delete {
+                println!("  {}", result.file_path.display());
+            }
+            println!("\n⚠️  WARNING: This will permanently delete these files!");
+        }
+        if !to_trash.is_empty() {
+            println!("\nFound {} duplicate files to move to {}:",
+                to_trash.len(), output_dir.join(TRASH_DIR_NAME).display());
+            for result in &to_trash {
+                println!("  {}", result.file_path.display());
+            }
         }
-        println!("\n⚠️  WARNING: This will permanently delete these files!");
         println!("Press Enter to continue or Ctrl+C to cancel...");
-        
+
         // Read a line to pause for user confirmation
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
     }
-    
+
     let mut deleted_count = 0;
+    let mut trashed_count = 0;
     let mut error_count = 0;
-    
-    for result in duplicate_files {
+
+    for result in to_delete {
         match fs::remove_file(&result.file_path) {
             Ok(_) => {
                 if !machine_readable {
@@ -327,15 +769,137 @@ fn delete_duplicate_files(results: &[ProcessResult], machine_readable: bool) ->
             }
         }
     }
-    
+
+    for result in to_trash {
+        match file_processor.move_to_trash(&result.file_path, output_dir) {
+            Ok(trash_path) => {
+                if !machine_readable {
+                    println!("🗑️  Trashed: {} -> {}", result.file_path.display(), trash_path.display());
+                } else {
+                    println!("TRASHED|{}|{}|Content duplicate", result.file_path.display(), trash_path.display());
+                }
+                trashed_count += 1;
+            }
+            Err(e) => {
+                if !machine_readable {
+                    println!("❌ Failed to trash {}: {:#}", result.file_path.display(), e);
+                } else {
+                    println!("TRASH_ERROR|{}|{:#}", result.file_path.display(), e);
+                }
+                error_count += 1;
+            }
+        }
+    }
+
     if !machine_readable {
         println!("\nDuplicate deletion summary:");
         println!("Files deleted: {}", deleted_count);
+        if trashed_count > 0 {
+            println!("Files trashed: {}", trashed_count);
+        }
         println!("Errors: {}", error_count);
     } else {
-        println!("DELETE_SUMMARY|deleted:{}|errors:{}", deleted_count, error_count);
+        println!("DELETE_SUMMARY|deleted:{}|trashed:{}|errors:{}", deleted_count, trashed_count, error_count);
+    }
+
+    Ok(())
+}
+
+/// Report images that look alike among the sorted files and trash them when opted in
+///
+/// Files are compared where they are now: at their target after a real run, in place after
+/// a dry run (`trash` is None). Exact content duplicates are already reported and left out.
+fn report_near_duplicates(
+    results: &[ProcessResult],
+    options: Option<NearDuplicateOptions>,
+    trash: Option<(&FileProcessor, &Path)>,
+    machine_readable: bool,
+) -> Result<()> {
+    let Some(options) = options else {
+        return Ok(());
+    };
+    let dry_run = trash.is_none();
+
+    let files: Vec<PathBuf> = results.iter()
+        .filter(|r| r.success && r.duplicate_of.is_none())
+        .filter_map(|r| if r.renamed && !dry_run { r.new_path.clone() } else { Some(r.file_path.clone()) })
+        .collect();
+    info!("Computing perceptual hashes of {} files", files.len());
+    let hashes = PerceptualHasher::new(options.algorithm).hash_files(&files);
+    let clusters = hashing::find_near_duplicates(&hashes, options.max_distance);
+    let near_duplicates: usize = clusters.iter().map(|cluster| cluster.others.len()).sum();
+
+    if machine_readable {
+        for cluster in &clusters {
+            for (other, distance) in &cluster.others {
+                println!("NEAR_DUPLICATE|{}|{}|{}", cluster.keep.display(), other.display(), distance);
+            }
+        }
+        println!("NEAR_DUPLICATE_SUMMARY|hashed:{}|clusters:{}|near_duplicates:{}",
+            hashes.len(), clusters.len(), near_duplicates);
+    } else {
+        println!("\nNear-duplicate images ({} of {} files could be decoded, distance <= {} bits):",
+            hashes.len(), files.len(), options.max_distance);
+        if clusters.is_empty() {
+            println!("  none found");
+        }
+        for cluster in &clusters {
+            println!("  keep {}", cluster.keep.display());
+            for (other, distance) in &cluster.others {
+                println!("    ~ {} (distance {})", other.display(), distance);
+            }
+        }
+    }
+
+    if !options.delete || clusters.is_empty() {
+        return Ok(());
+    }
+    let Some((file_processor, output_dir)) = trash else {
+        if !machine_readable {
+            println!("\nDry run: {} near duplicates would be moved to the trash, keeping one file per cluster.", near_duplicates);
+        }
+        return Ok(());
+    };
+    if !options.confirmed {
+        println!("\n⚠️  WARNING: This will move {} near-duplicate files and their sidecars to {}!",
+            near_duplicates, output_dir.join(TRASH_DIR_NAME).display());
+        println!("They are similar, not identical, to the file kept; `undo` restores them. Press Enter to continue or Ctrl+C to cancel...");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+    }
+
+    let mut trashed_count = 0;
+    let mut error_count = 0;
+    for cluster in &clusters {
+        for (other, _) in &cluster.others {
+            match file_processor.move_to_trash(other, output_dir) {
+                Ok(trash_path) => {
+                    if !machine_readable {
+                        println!("🗑️  Trashed: {} -> {}", other.display(), trash_path.display());
+                    } else {
+                        println!("TRASHED|{}|{}|Near duplicate of {}", other.display(), trash_path.display(), cluster.keep.display());
+                    }
+                    trashed_count += 1;
+                }
+                Err(e) => {
+                    if !machine_readable {
+                        println!("❌ Failed to trash {}: {:#}", other.display(), e);
+                    } else {
+                        println!("TRASH_ERROR|{}|{:#}", other.display(), e);
+                    }
+                    error_count += 1;
+                }
+            }
+        }
+    }
+
+    if !machine_readable {
+        println!("\nNear-duplicate trash summary:");
+        println!("Files trashed: {}", trashed_count);
+        println!("Errors: {}", error_count);
+    } else {
+        println!("TRASH_SUMMARY|trashed:{}|errors:{}", trashed_count, error_count);
     }
-    
     Ok(())
 }
 
@@ -344,9 +908,14 @@ fn print_summary(results: &[ProcessResult], machine_readable: bool) {
     let renamed = results.iter().filter(|r| r.success && r.renamed).count();
     let skipped = results.iter().filter(|r| r.success && !r.renamed).count();
     let errors = results.iter().filter(|r| !r.success).count();
+    let low_confidence = results.iter()
+        .filter(|r| r.provenance.as_ref().is_some_and(|p| p.confidence == Confidence::Low))
+        .count();
+    let conflicts = results.iter().filter(|r| !r.conflicts.is_empty()).count();
+    let sidecars: usize = results.iter().map(|r| r.sidecars.len()).sum();
 
     if machine_readable {
-        // Machine-readable format: one line per file with status
+        // Machine-readable format: one line per file with status and timestamp provenance
         for result in results {
             let status = if result.success {
                 if result.renamed {
@@ -359,19 +928,44 @@ fn print_summary(results: &[ProcessResult], machine_readable: bool) {
             };
             
             let reason = result.error.as_deref().unwrap_or("Unknown");
-            println!("{}|{}|{}", status, result.file_path.display(), reason);
+            println!("{}|{}|{}|{}", status, result.file_path.display(), reason, provenance_columns(result.provenance.as_ref()));
+            print_sidecar_lines(result);
+            print_conflict_line(result);
         }
         
         // Summary line at the end
-        println!("SUMMARY|processed:{}|renamed:{}|skipped:{}|errors:{}", processed, renamed, skipped, errors);
+        println!("SUMMARY|processed:{}|renamed:{}|skipped:{}|errors:{}|low_confidence:{}|conflicts:{}",
+            processed, renamed, skipped, errors, low_confidence, conflicts);
     } else {
         // Human-readable format (existing format)
         println!("\nProcessing complete!");
         println!("Files processed: {}", processed);
         println!("Files renamed: {}", renamed);
+        if sidecars > 0 {
+            println!("Sidecar files carried along: {}", sidecars);
+        }
         println!("Files skipped: {}", skipped);
         println!("Errors: {}", errors);
 
+        print_provenance_breakdown(results);
+        print_conflicts(results);
+
+        for source in [TimestampSource::Filename, TimestampSource::Mtime] {
+            let fallback_dated: Vec<&ProcessResult> = results.iter()
+                .filter(|r| r.provenance.as_ref().is_some_and(|p| p.source == source))
+                .collect();
+            if fallback_dated.is_empty() {
+                continue;
+            }
+            println!("\nDated from {} (no metadata timestamp): {} files", source.description(), fallback_dated.len());
+            for result in fallback_dated {
+                match &result.new_path {
+                    Some(new_path) => println!("  {} -> {}", result.file_path.display(), new_path.display()),
+                    None => println!("  {}", result.file_path.display()),
+                }
+            }
+        }
+
         // Break down skip reasons
         if skipped > 0 {
             println!("\nSkip reasons:");
@@ -404,6 +998,218 @@ fn print_summary(results: &[ProcessResult], machine_readable: bool) {
     }
 }
 
+/// Notes shown after a planned rename: suffix, timestamp source, conflicts, sidecars and pairs
+fn plan_notes(result: &ProcessResult) -> Vec<String> {
+    let mut notes = Vec::new();
+    if let Some(suffix) = result.tie_break_suffix {
+        notes.push(format!("tie-break suffix -{}", suffix));
+    }
+    if let Some(provenance) = &result.provenance {
+        if provenance.source != TimestampSource::Metadata {
+            notes.push(format!("dated from {}", provenance.source.description()));
+        } else if provenance.confidence == Confidence::Low {
+            notes.push(format!("low confidence: {}", provenance.field));
+        }
+    }
+    if !result.conflicts.is_empty() {
+        notes.push("conflicting timestamps".to_string());
+    }
+    if !result.sidecars.is_empty() {
+        let names: Vec<String> = result.sidecars.iter()
+            .filter_map(|(source, _)| source.file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect();
+        notes.push(format!("with {}", names.join(", ")));
+    }
+    if !result.paired_with.is_empty() {
+        let names: Vec<String> = result.paired_with.iter()
+            .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect();
+        notes.push(format!("paired with {}", names.join(", ")));
+    }
+    notes
+}
+
+/// Machine-readable `source|field|confidence` columns (empty when no timestamp was found)
+fn provenance_columns(provenance: Option<&Provenance>) -> String {
+    match provenance {
+        Some(p) => format!("{}|{}|{}", p.source.as_str(), p.field, p.confidence.as_str()),
+        None => "||".to_string(),
+    }
+}
+
+/// Count files per timestamp field and list metadata timestamps of low confidence
+fn print_provenance_breakdown(results: &[ProcessResult]) {
+    let mut counts: std::collections::HashMap<&Provenance, usize> = std::collections::HashMap::new();
+    for provenance in results.iter().filter_map(|r| r.provenance.as_ref()) {
+        *counts.entry(provenance).or_insert(0) += 1;
+    }
+    if counts.is_empty() {
+        return;
+    }
+
+    let mut counts: Vec<(&Provenance, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b.confidence.cmp(&a.confidence).then(b_count.cmp(a_count)));
+    println!("\nTimestamp sources:");
+    for (provenance, count) in counts {
+        println!("  {} {} ({} confidence): {} files",
+            provenance.source.description(), provenance.field, provenance.confidence.as_str(), count);
+    }
+
+    let weak: Vec<&ProcessResult> = results.iter()
+        .filter(|r| r.provenance.as_ref().is_some_and(|p| {
+            matches!(p.source, TimestampSource::Metadata | TimestampSource::Sidecar) && p.confidence == Confidence::Low
+        }))
+        .collect();
+    if !weak.is_empty() {
+        println!("\nLow-confidence metadata timestamps (review recommended):");
+        for result in weak {
+            println!("  {}: {}", result.file_path.display(), result.provenance.as_ref().unwrap().field);
+        }
+    }
+}
+
+/// Files whose metadata timestamps disagree, with every candidate and the one used
+fn print_conflicts(results: &[ProcessResult]) {
+    let conflicting: Vec<&ProcessResult> = results.iter().filter(|r| !r.conflicts.is_empty()).collect();
+    if conflicting.is_empty() {
+        return;
+    }
+    println!("\nConflicting timestamps (metadata fields disagree): {} files", conflicting.len());
+    for result in conflicting {
+        let used = result.provenance.as_ref().map(|p| p.field.as_str());
+        println!("  {}:", result.file_path.display());
+        for candidate in &result.conflicts {
+            let marker = if result.renamed && used == Some(candidate.field.as_str()) { " (used)" } else { "" };
+            println!("    {} {}{}", candidate.field, candidate.timestamp, marker);
+        }
+    }
+}
+
+/// Machine-readable `SIDECAR|source|target` lines for the sidecars handled with a file
+fn print_sidecar_lines(result: &ProcessResult) {
+    for (source, target) in &result.sidecars {
+        println!("SIDECAR|{}|{}", source.display(), target.display());
+    }
+}
+
+/// Machine-readable `CONFLICT|path|field=timestamp;...` line for a file with disagreeing timestamps
+fn print_conflict_line(result: &ProcessResult) {
+    if result.conflicts.is_empty() {
+        return;
+    }
+    let candidates: Vec<String> = result.conflicts.iter()
+        .map(|candidate| format!("{}={}", candidate.field, candidate.timestamp.to_rfc3339()))
+        .collect();
+    println!("CONFLICT|{}|{}", result.file_path.display(), candidates.join(";"));
+}
+
+/// Reverse a journaled run
+fn undo_run(output_dir: PathBuf, run: Option<String>, machine_readable: bool) -> Result<()> {
+    let (run_id, results) = journal::undo_run(&output_dir, run.as_deref())?;
+
+    let undone = results.iter().filter(|r| r.undone).count();
+    let refused = results.len() - undone;
+
+    if machine_readable {
+        for result in &results {
+            if result.undone {
+                println!("UNDONE|{}|{}", result.entry.target.display(), result.entry.source.display());
+            } else {
+                println!("UNDO_SKIPPED|{}|{}", result.entry.target.display(), result.reason.as_deref().unwrap_or("Unknown"));
+            }
+        }
+        println!("UNDO_SUMMARY|run:{}|undone:{}|skipped:{}", run_id, undone, refused);
+    } else {
+        println!("Undoing run {}", run_id);
+        for result in &results {
+            if result.undone {
+                println!("✅ Reverted {}: {} -> {}", result.entry.mode, result.entry.target.display(), result.entry.source.display());
+            } else {
+                println!("❌ Kept {}: {}", result.entry.target.display(), result.reason.as_deref().unwrap_or("Unknown reason"));
+            }
+        }
+        println!("\nUndo summary:");
+        println!("Operations reverted: {}", undone);
+        println!("Operations skipped: {}", refused);
+    }
+
+    Ok(())
+}
+
+/// Print the rename plan produced by a dry run
+fn print_plan(results: &[ProcessResult], mode: &str, machine_readable: bool) {
+    let planned = results.iter().filter(|r| r.success && r.renamed).count();
+    let collisions = results.iter().filter(|r| r.tie_break_suffix.is_some()).count();
+    let duplicates = results.iter().filter(|r| r.duplicate_of.is_some()).count();
+    let skipped = results.iter().filter(|r| r.success && !r.renamed && r.duplicate_of.is_none()).count();
+    let errors = results.iter().filter(|r| !r.success).count();
+    let conflicts = results.iter().filter(|r| !r.conflicts.is_empty()).count();
+
+    if machine_readable {
+        for result in results {
+            print_conflict_line(result);
+            if !result.success {
+                println!("ERROR|{}|{}", result.file_path.display(), result.error.as_deref().unwrap_or("Unknown"));
+            } else if let Some(new_path) = &result.new_path {
+                let suffix = result.tie_break_suffix.map(|s| format!("-{}", s)).unwrap_or_default();
+                println!("PLAN|{}|{}|{}|{}", result.file_path.display(), new_path.display(), suffix,
+                    provenance_columns(result.provenance.as_ref()));
+                print_sidecar_lines(result);
+            } else if let Some(original) = &result.duplicate_of {
+                println!("DUPLICATE|{}|{}", result.file_path.display(), original.display());
+            } else {
+                println!("SKIPPED|{}|{}", result.file_path.display(), result.error.as_deref().unwrap_or("Unknown"));
+            }
+        }
+
+        println!("PLAN_SUMMARY|mode:{}|planned:{}|collisions:{}|duplicates:{}|skipped:{}|errors:{}|conflicts:{}",
+            mode, planned, collisions, duplicates, skipped, errors, conflicts);
+    } else {
+        println!("\nDry run - rename plan ({} mode, no files were changed):", mode);
+        for result in results.iter().filter(|r| r.success && r.renamed) {
+            if let Some(new_path) = &result.new_path {
+                let notes = plan_notes(result);
+                if notes.is_empty() {
+                    println!("  {} -> {}", result.file_path.display(), new_path.display());
+                } else {
+                    println!("  {} -> {} ({})", result.file_path.display(), new_path.display(), notes.join(", "));
+                }
+            }
+        }
+
+        print_conflicts(results);
+
+        if duplicates > 0 {
+            println!("\nContent duplicates (would be skipped):");
+            for result in results.iter().filter(|r| r.duplicate_of.is_some()) {
+                println!("  {} duplicates {}", result.file_path.display(), result.duplicate_of.as_ref().unwrap().display());
+            }
+        }
+
+        if skipped > 0 {
+            println!("\nWould skip:");
+            for result in results.iter().filter(|r| r.success && !r.renamed && r.duplicate_of.is_none()) {
+                println!("  {}: {}", result.file_path.display(), result.error.as_deref().unwrap_or("Unknown reason"));
+            }
+        }
+
+        if errors > 0 {
+            println!("\nErrors:");
+            for result in results.iter().filter(|r| !r.success) {
+                println!("  {}: {}", result.file_path.display(), result.error.as_deref().unwrap_or("Unknown error"));
+            }
+        }
+
+        println!("\nPlan summary:");
+        println!("Files to {}: {}", mode, planned);
+        println!("Tie-break suffixes assigned: {}", collisions);
+        println!("Content duplicates: {}", duplicates);
+        println!("Conflicting timestamps: {}", conflicts);
+        println!("Files skipped: {}", skipped);
+        println!("Errors: {}", errors);
+    }
+}
+
 /// Write EXIF data to image files
 fn write_exif_data(
     files: Vec<PathBuf>,
@@ -549,3 +1355,90 @@ fn modify_exif_data(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planned(source: TimestampSource, field: &str, confidence: Confidence) -> ProcessResult {
+        ProcessResult {
+            success: true,
+            renamed: true,
+            provenance: Some(Provenance { source, field: field.to_string(), confidence }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plan_and_summary_show_where_timestamps_came_from() {
+        let cases = [
+            (planned(TimestampSource::Metadata, "DateTimeOriginal", Confidence::High), "metadata|DateTimeOriginal|high", None),
+            (planned(TimestampSource::Metadata, "ModifyDate", Confidence::Low), "metadata|ModifyDate|low", Some("low confidence: ModifyDate")),
+            (planned(TimestampSource::Sidecar, "DateTimeOriginal", Confidence::High), "sidecar|DateTimeOriginal|high", Some("dated from XMP sidecar")),
+            (planned(TimestampSource::Filename, "date-time", Confidence::Medium), "filename|date-time|medium", Some("dated from file name")),
+            (planned(TimestampSource::Mtime, "FileModifyDate", Confidence::Low), "mtime|FileModifyDate|low", Some("dated from file modification time")),
+        ];
+        for (result, columns, note) in cases {
+            assert_eq!(provenance_columns(result.provenance.as_ref()), columns);
+            assert_eq!(plan_notes(&result), note.into_iter().map(str::to_string).collect::<Vec<_>>(), "{}", columns);
+        }
+        assert_eq!(provenance_columns(None), "||");
+    }
+
+    fn sort_args(args: &[&str]) -> SortArgs {
+        let cli = Cli::try_parse_from(["sortify-rs", "files", "--delete-duplicates"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::Files { sort_args, .. } => sort_args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn image_data_duplicates_with_different_bytes_are_only_trashed_when_opted_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original.jpg");
+        let identical = dir.path().join("identical.jpg");
+        let retagged = dir.path().join("retagged.jpg");
+        std::fs::write(&original, "image data").unwrap();
+        std::fs::write(&identical, "image data").unwrap();
+        std::fs::write(&retagged, "image data, other tags").unwrap();
+        let duplicate = |file_path: &Path| ProcessResult {
+            file_path: file_path.to_path_buf(),
+            success: true,
+            error: Some("Content duplicate".to_string()),
+            duplicate_of: Some(original.clone()),
+            ..Default::default()
+        };
+        let results = [duplicate(&identical), duplicate(&retagged)];
+        let file_processor = FileProcessor::new(Some(1), ProcessOptions::default());
+
+        let args = sort_args(&["--hash-mode", "image-data"]);
+        delete_duplicate_files(&results, &args, (&file_processor, dir.path()), true).unwrap();
+        assert!(!identical.exists());
+        assert!(retagged.exists());
+
+        let args = sort_args(&["--hash-mode", "image-data", "--delete-image-data-duplicates"]);
+        delete_duplicate_files(&results, &args, (&file_processor, dir.path()), true).unwrap();
+        assert!(!retagged.exists());
+        assert!(original.exists());
+        assert!(dir.path().join(TRASH_DIR_NAME).join("unjournaled/retagged.jpg").exists());
+    }
+
+    #[test]
+    fn dedupe_all_duplicates_are_trashed_not_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let duplicate = dir.path().join("duplicate.jpg");
+        std::fs::write(&duplicate, "image data").unwrap();
+        let results = [ProcessResult {
+            file_path: duplicate.clone(),
+            success: true,
+            error: Some("Content duplicate".to_string()),
+            ..Default::default()
+        }];
+        let file_processor = FileProcessor::new(Some(1), ProcessOptions::default());
+
+        delete_duplicate_files(&results, &sort_args(&["--dedupe-all"]), (&file_processor, dir.path()), true).unwrap();
+        assert!(!duplicate.exists());
+        assert!(dir.path().join(TRASH_DIR_NAME).join("unjournaled/duplicate.jpg").exists());
+    }
+}
@@ -1,96 +1,797 @@
-/**
+/*!
  * File naming and organization module
+ *
+ * Target paths are rendered from a naming template such as the default
+ * `{year}/{month:02}-{month_abbr}/{date}_{time}.{ms}{suffix}.{ext}`.
  */
 
-use chrono::{DateTime, Utc, Datelike, Timelike};
-use std::collections::HashSet;
+use anyhow::Result;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::exif::{ExifData, TimestampSource};
+
+/// Default template: YYYY/MM-Mon/YYYYMMDD_HHMMSS.fff<suffix>.ext
+pub const DEFAULT_TEMPLATE: &str = "{year}/{month:02}-{month_abbr}/{date}_{time}.{ms}{suffix}.{ext}";
+
+/// Template of the events organization: one folder per shooting session
+pub const EVENTS_TEMPLATE: &str = "{event}/{date}_{time}.{ms}{suffix}.{ext}";
+
+const MONTH_ABBRS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+];
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December"
+];
+
+/// Finest fractional second precision a `{subsec}` token can render (nanoseconds)
+const MAX_SUBSEC_DIGITS: usize = 9;
+
+/// Text used for metadata tokens whose field is missing and that have no `|fallback`
+const MISSING_FIELD_FALLBACK: &str = "Unknown";
+
+/// Longest rendering of a single metadata value, to keep path components manageable
+const MAX_FIELD_LENGTH: usize = 64;
+
+/// Clock used for the date and time tokens of a naming template
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NamingTime {
+    /// Wall-clock time at the place of capture, as shown by the camera
+    #[default]
+    Local,
+    /// Capture time converted to UTC, so files from different timezones sort chronologically
+    Utc,
+}
+
+impl FromStr for NamingTime {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "utc" => Ok(Self::Utc),
+            _ => anyhow::bail!("Invalid naming time '{}' (expected local or utc)", value),
+        }
+    }
+}
+
+impl NamingTime {
+    fn datetime(&self, exif_data: &ExifData) -> NaiveDateTime {
+        match self {
+            Self::Local => exif_data.timestamp.naive_local(),
+            Self::Utc => exif_data.timestamp.naive_utc(),
+        }
+    }
+}
+
+/// Placeholders available in naming templates
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Year,
+    Month,
+    MonthAbbr,
+    MonthName,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Ms,
+    /// Fractional seconds truncated to the token width in digits, e.g. `{subsec:6}` for microseconds
+    Subsec,
+    Date,
+    Time,
+    Suffix,
+    /// Capture day and burst number of the frame (`20251019_BURST001`), empty outside bursts
+    Burst,
+    /// Event folder label such as `2024-05-01_event-1`
+    Event,
+    Ext,
+    Make,
+    Model,
+    /// Model prefixed with the make unless the model already starts with it
+    Camera,
+    Lens,
+    Serial,
+    /// Any metadata field by name, e.g. `{meta:ISO}`
+    Meta(String),
+}
+
+impl Token {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "year" => Token::Year,
+            "month" => Token::Month,
+            "month_abbr" => Token::MonthAbbr,
+            "month_name" => Token::MonthName,
+            "day" => Token::Day,
+            "hour" => Token::Hour,
+            "minute" => Token::Minute,
+            "second" => Token::Second,
+            "ms" => Token::Ms,
+            "subsec" => Token::Subsec,
+            "date" => Token::Date,
+            "time" => Token::Time,
+            "suffix" => Token::Suffix,
+            "burst" => Token::Burst,
+            "event" => Token::Event,
+            "ext" => Token::Ext,
+            "make" => Token::Make,
+            "model" => Token::Model,
+            "camera" => Token::Camera,
+            "lens" => Token::Lens,
+            "serial" => Token::Serial,
+            _ => return None,
+        })
+    }
+
+    /// Metadata fields consulted for metadata tokens, in order of preference
+    fn metadata_fields(&self) -> &[&str] {
+        match self {
+            Token::Make => &["Make"],
+            Token::Model => &["Model"],
+            Token::Lens => &["LensModel", "Lens", "LensID", "LensType"],
+            Token::Serial => &["BodySerialNumber", "SerialNumber", "InternalSerialNumber", "CameraSerialNumber"],
+            _ => &[],
+        }
+    }
+
+    fn is_metadata(&self) -> bool {
+        matches!(self, Token::Make | Token::Model | Token::Camera | Token::Lens | Token::Serial | Token::Meta(_))
+    }
+
+    /// Default zero-padding width for numeric tokens
+    fn default_width(&self) -> Option<usize> {
+        match self {
+            Token::Year => Some(4),
+            Token::Month | Token::Day | Token::Hour | Token::Minute | Token::Second => Some(2),
+            Token::Ms | Token::Subsec | Token::Burst => Some(3),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Token { token: Token, width: Option<usize>, fallback: Option<String> },
+}
+
+/// Parsed naming template, split into path components
+#[derive(Debug, Clone)]
+pub struct NamingTemplate {
+    source: String,
+    components: Vec<Vec<Segment>>,
+}
+
+impl NamingTemplate {
+    /// Parse and validate a template
+    ///
+    /// The last path component must contain `{suffix}` exactly once and `{ext}`: every
+    /// file that could receive the same name then ends up in the same directory group,
+    /// where tie-breaking assigns a suffix that is free both in the run and on disk.
+    /// Templates without time tokens are valid and simply rely on suffixes more.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut components = vec![Vec::new()];
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => anyhow::bail!("Unclosed '{{' in template: {}", template),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        components.last_mut().unwrap().push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    components.last_mut().unwrap().push(Self::parse_token(&spec)?);
+                }
+                '}' => anyhow::bail!("Unmatched '}}' in template: {}", template),
+                '/' => {
+                    if !literal.is_empty() {
+                        components.last_mut().unwrap().push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    components.push(Vec::new());
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            components.last_mut().unwrap().push(Segment::Literal(literal));
+        }
+
+        let template = Self { source: template.to_string(), components };
+        template.validate()?;
+        Ok(template)
+    }
+
+    fn parse_token(spec: &str) -> Result<Segment> {
+        let (spec, fallback) = match spec.split_once('|') {
+            Some((spec, fallback)) => (spec, Some(fallback)),
+            None => (spec, None),
+        };
+        let (name, format) = match spec.split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (spec, None),
+        };
+
+        let token = if name == "meta" {
+            match format {
+                Some(field) if !field.is_empty() => Token::Meta(field.to_string()),
+                _ => anyhow::bail!("Token {{meta}} needs a field name, e.g. {{meta:LensModel}}"),
+            }
+        } else {
+            Token::parse(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown template token: {{{}}}", name))?
+        };
+
+        let width = match format {
+            Some(_) if matches!(token, Token::Meta(_)) => None,
+            Some(format) => {
+                if token.default_width().is_none() {
+                    anyhow::bail!("Token {{{}}} does not take a width", name);
+                }
+                Some(format.parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Invalid width '{}' for token {{{}}}", format, name))?)
+            }
+            None => None,
+        };
+        if token == Token::Subsec && !matches!(width, None | Some(1..=MAX_SUBSEC_DIGITS)) {
+            anyhow::bail!("Token {{subsec}} takes 1 to {} digits: {{{}}}", MAX_SUBSEC_DIGITS, spec);
+        }
+
+        let fallback = match fallback {
+            Some(_) if !token.is_metadata() => anyhow::bail!("Only metadata tokens take a fallback: {{{}}}", spec),
+            Some(fallback) => Some(sanitize_path_component(fallback)
+                .ok_or_else(|| anyhow::anyhow!("Fallback for {{{}}} must not be empty", spec))?),
+            None => None,
+        };
+
+        Ok(Segment::Token { token, width, fallback })
+    }
+
+    /// Whether the template places or names files by burst
+    pub fn uses_burst(&self) -> bool {
+        self.uses(Token::Burst)
+    }
+
+    /// Whether the template places or names files by event
+    pub fn uses_event(&self) -> bool {
+        self.uses(Token::Event)
+    }
+
+    /// Metadata fields the template reads when rendering
+    pub fn metadata_fields(&self) -> Vec<&str> {
+        self.components.iter()
+            .flatten()
+            .flat_map(|segment| match segment {
+                Segment::Token { token: Token::Meta(field), .. } => vec![field.as_str()],
+                Segment::Token { token: Token::Camera, .. } => {
+                    [Token::Make.metadata_fields(), Token::Model.metadata_fields()].concat()
+                }
+                Segment::Token { token, .. } => token.metadata_fields().to_vec(),
+                Segment::Literal(_) => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn uses(&self, wanted: Token) -> bool {
+        self.components.iter()
+            .flatten()
+            .any(|segment| matches!(segment, Segment::Token { token, .. } if *token == wanted))
+    }
+
+    fn validate(&self) -> Result<()> {
+        let has = |component: &[Segment], wanted: Token| component.iter()
+            .filter(|segment| matches!(segment, Segment::Token { token, .. } if *token == wanted))
+            .count();
+
+        for component in &self.components {
+            match component.as_slice() {
+                [] => anyhow::bail!("Template must not contain empty path components: {}", self.source),
+                [Segment::Literal(text)] if text == "." || text == ".." => {
+                    anyhow::bail!("Template must not contain '.' or '..' components: {}", self.source)
+                }
+                _ => {}
+            }
+        }
+
+        let (file_component, directories) = self.components.split_last().unwrap();
+        if directories.iter().any(|component| has(component, Token::Suffix) > 0) {
+            anyhow::bail!("{{suffix}} may only appear in the file name, not in directories: {}", self.source);
+        }
+        if has(file_component, Token::Suffix) != 1 {
+            anyhow::bail!("Template file name must contain {{suffix}} exactly once so ties get unique names: {}", self.source);
+        }
+        if has(file_component, Token::Ext) == 0 {
+            anyhow::bail!("Template file name must contain {{ext}}: {}", self.source);
+        }
+        Ok(())
+    }
+
+    /// Render the full relative path
+    ///
+    /// Directories that render empty, such as a `{burst}` folder outside a burst, are left out.
+    pub fn render(
+        &self,
+        exif_data: &ExifData,
+        naming_time: NamingTime,
+        extension: &str,
+        suffix: Option<u32>,
+    ) -> String {
+        self.components.iter()
+            .map(|component| Self::render_component(component, exif_data, naming_time, extension, suffix))
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Render only the directory part of the path (empty when the template has no directories)
+    pub fn render_directory(&self, exif_data: &ExifData, naming_time: NamingTime) -> String {
+        let (_, directories) = self.components.split_last().unwrap();
+        directories.iter()
+            .map(|component| Self::render_component(component, exif_data, naming_time, "", None))
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn render_component(
+        component: &[Segment],
+        exif_data: &ExifData,
+        naming_time: NamingTime,
+        extension: &str,
+        suffix: Option<u32>,
+    ) -> String {
+        let dt = naming_time.datetime(exif_data);
+        let mut rendered = String::new();
+        for segment in component {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Token { token, width, fallback } => {
+                    let number = |value: u32| {
+                        format!("{:0width$}", value, width = width.or(token.default_width()).unwrap_or(0))
+                    };
+                    let text = match token {
+                        Token::Year => number(dt.year() as u32),
+                        Token::Month => number(dt.month()),
+                        Token::MonthAbbr => MONTH_ABBRS[dt.month0() as usize].to_string(),
+                        Token::MonthName => MONTH_NAMES[dt.month0() as usize].to_string(),
+                        Token::Day => number(dt.day()),
+                        Token::Hour => number(dt.hour()),
+                        Token::Minute => number(dt.minute()),
+                        Token::Second => number(dt.second()),
+                        Token::Ms => number(exif_data.nanoseconds / 1_000_000),
+                        Token::Subsec => {
+                            let digits = width.unwrap_or(3);
+                            format!("{:09}", exif_data.nanoseconds)[..digits].to_string()
+                        }
+                        Token::Date => dt.format("%Y%m%d").to_string(),
+                        Token::Time => dt.format("%H%M%S").to_string(),
+                        Token::Suffix => suffix.map(|counter| format!("-{}", counter)).unwrap_or_default(),
+                        // Separated from preceding text like the suffix, bare as a whole folder name
+                        Token::Burst => match exif_data.burst {
+                            Some(burst) => {
+                                let label = format!("{}_BURST{}", burst.day.format("%Y%m%d"), number(burst.number));
+                                if rendered.is_empty() { label } else { format!("_{}", label) }
+                            }
+                            None => String::new(),
+                        },
+                        Token::Event => exif_data.event.clone()
+                            .unwrap_or_else(|| dt.format("%Y-%m-%d").to_string()),
+                        Token::Ext => extension.to_string(),
+                        _ => metadata_value(token, &exif_data._metadata)
+                            .or_else(|| fallback.clone())
+                            .unwrap_or_else(|| MISSING_FIELD_FALLBACK.to_string()),
+                    };
+                    rendered.push_str(&text);
+                }
+            }
+        }
+        rendered
+    }
+}
+
+/// Sanitized value of a metadata token, or None when the field is missing or empty
+fn metadata_value(token: &Token, metadata: &HashMap<String, String>) -> Option<String> {
+    let lookup = |fields: &[&str]| fields.iter()
+        .filter_map(|field| metadata.get(*field))
+        .find_map(|value| sanitize_path_component(value));
+
+    match token {
+        Token::Meta(field) => lookup(&[field.as_str()]),
+        Token::Camera => {
+            let make = lookup(Token::Make.metadata_fields());
+            let model = lookup(Token::Model.metadata_fields());
+            match (make, model) {
+                (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+                (Some(make), Some(model)) => Some(format!("{}_{}", make, model)),
+                (make, model) => model.or(make),
+            }
+        }
+        _ => lookup(token.metadata_fields()),
+    }
+}
+
+/// Make an EXIF string safe to use as (part of) a single path component
+///
+/// Characters that are invalid on common filesystems and whitespace runs become a
+/// single underscore; quotes, NULs and leading/trailing dots are dropped.
+pub fn sanitize_path_component(value: &str) -> Option<String> {
+    let value = value.trim_matches(|c: char| c == '\0' || c == '"' || c.is_whitespace());
+
+    let mut sanitized = String::new();
+    for c in value.chars() {
+        let unsafe_char = c.is_control() || c.is_whitespace()
+            || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|');
+        if unsafe_char {
+            if !sanitized.ends_with('_') {
+                sanitized.push('_');
+            }
+        } else {
+            sanitized.push(c);
+        }
+    }
+
+    let sanitized: String = sanitized.trim_matches(|c: char| c == '.' || c == '_')
+        .chars()
+        .take(MAX_FIELD_LENGTH)
+        .collect();
+    (!sanitized.is_empty()).then_some(sanitized)
+}
+
+impl Default for NamingTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TEMPLATE).expect("default template is valid")
+    }
+}
+
+/// Folder for files dated by `--fallback mtime` unless another one is configured
+pub const DEFAULT_MTIME_FOLDER: &str = "unsorted-by-mtime";
 
 pub struct FilenameGenerator {
+    template: NamingTemplate,
+    naming_time: NamingTime,
+    /// Folder that keeps mtime-dated files apart from files dated by their content
+    mtime_folder: String,
     _existing_files: HashSet<String>,
 }
 
 impl FilenameGenerator {
-    pub fn new() -> Self {
+    pub fn new(template: NamingTemplate, naming_time: NamingTime, mtime_folder: &str) -> Self {
+        let mtime_folder = match mtime_folder.trim_matches('/') {
+            "" => DEFAULT_MTIME_FOLDER,
+            folder => folder,
+        };
         Self {
+            template,
+            naming_time,
+            mtime_folder: mtime_folder.to_string(),
             _existing_files: HashSet::new(),
         }
     }
 
     /// Generate filename with subsecond precision and tie-breaking
     /// 
-    /// Format: YYYY/MM-Mon/YYYYMMDD_HHMMSS.fff<ext>
+    /// Format: rendered from the naming template (default YYYY/MM-Mon/YYYYMMDD_HHMMSS.fff<ext>)
     /// Tie-breaking: Files with identical timestamps get -2, -3, etc. suffixes
     pub fn generate_filename(
         &self,
-        dt: DateTime<Utc>,
-        milliseconds: u16,
+        exif_data: &ExifData,
         extension: &str,
         existing_files: &[String],
     ) -> String {
-        let year = dt.year();
-        let month_num = dt.month();
-        let month_names = [
-            "Jan", "Feb", "Mar", "Apr", "May", "Jun",
-            "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
-        ];
-        let month_name = month_names[(month_num - 1) as usize];
-
-        let base_filename = format!(
-            "{}{:02}{:02}_{:02}{:02}{:02}.{:03}.{}",
-            year, month_num, dt.day(),
-            dt.hour(), dt.minute(), dt.second(),
-            milliseconds, extension
-        );
+        self.generate_filename_with_suffix(exif_data, extension, existing_files).0
+    }
+
+    /// Generate filename like `generate_filename`, also returning the tie-breaking
+    /// counter that was appended (None when the plain name was free)
+    pub fn generate_filename_with_suffix(
+        &self,
+        exif_data: &ExifData,
+        extension: &str,
+        existing_files: &[String],
+    ) -> (String, Option<u32>) {
+        let suffix = self.free_suffix(exif_data, &[extension], |_, name| existing_files.iter().any(|existing| existing == name));
+        (self.generate_filename_with_fixed_suffix(exif_data, extension, suffix), suffix)
+    }
 
-        let full_path = format!("{}/{:02}-{}/{}", year, month_num, month_name, base_filename);
+    /// First tie-breaking suffix (None, then 2, 3, ...) under which the name is free for
+    /// every one of `extensions`, so files of one shot can share a base name
+    ///
+    /// `is_taken` gets each extension with the relative path rendered for it; callers
+    /// check the names assigned earlier in the run as well as the files already on disk.
+    pub fn free_suffix(
+        &self,
+        exif_data: &ExifData,
+        extensions: &[&str],
+        is_taken: impl Fn(&str, &str) -> bool,
+    ) -> Option<u32> {
+        let taken = |suffix| extensions.iter()
+            .any(|extension| is_taken(extension, &self.generate_filename_with_fixed_suffix(exif_data, extension, suffix)));
 
         // Check for ties and add suffix if needed
+        let mut suffix = None;
         let mut counter = 2;
-        let mut final_path = full_path.clone();
-        
-        while existing_files.contains(&final_path) {
-            let base_filename_with_suffix = format!(
-                "{}{:02}{:02}_{:02}{:02}{:02}.{:03}-{}.{}",
-                year, month_num, dt.day(),
-                dt.hour(), dt.minute(), dt.second(),
-                milliseconds, counter, extension
-            );
-            final_path = format!("{}/{:02}-{}/{}", year, month_num, month_name, base_filename_with_suffix);
+        while taken(suffix) {
+            suffix = Some(counter);
             counter += 1;
         }
+        suffix
+    }
 
-        final_path
+    /// Filename with a tie-breaking suffix chosen by the caller
+    pub fn generate_filename_with_fixed_suffix(&self, exif_data: &ExifData, extension: &str, suffix: Option<u32>) -> String {
+        self.place(exif_data, self.template.render(exif_data, self.naming_time, extension, suffix))
     }
 
-    /// Generate filename with content-based duplicate checking
-    pub fn _generate_filename_with_duplicate_check(
-        &self,
-        dt: DateTime<Utc>,
-        milliseconds: u16,
-        extension: &str,
-        file_path: &std::path::Path,
-        existing_files: &[String],
-        existing_hash_index: &std::collections::HashMap<std::path::PathBuf, String>,
-        target_directory: &std::path::Path,
-    ) -> (String, bool) {
-        // Check for content duplicates first
-        if let Some(_duplicate_path) = self._check_content_duplicate(file_path, existing_hash_index, target_directory) {
-            return ("".to_string(), true);
+    /// Metadata fields read when naming a file
+    pub fn metadata_fields(&self) -> Vec<&str> {
+        self.template.metadata_fields()
+    }
+
+    /// Directory (relative to the output directory) a file is placed in
+    pub fn generate_directory(&self, exif_data: &ExifData) -> String {
+        self.place(exif_data, self.template.render_directory(exif_data, self.naming_time))
+    }
+
+    /// Move a rendered relative path into the mtime folder for mtime-dated files
+    fn place(&self, exif_data: &ExifData, path: String) -> String {
+        match exif_data.provenance.source {
+            TimestampSource::Mtime if path.is_empty() => self.mtime_folder.clone(),
+            TimestampSource::Mtime => format!("{}/{}", self.mtime_folder, path),
+            _ => path,
+        }
+    }
+}
+
+/// Metadata fields holding a shot counter, most specific first
+pub const SEQUENCE_FIELDS: [&str; 6] = [
+    "SequenceNumber", "ShotNumberSincePowerUp", "ImageNumber", "ShutterCount", "FileNumber", "ImageCount",
+];
+
+/// Property used to order files that render to the same name before suffixes are assigned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreakKey {
+    /// Capture time with full subsecond precision
+    Time,
+    /// Shot counter from the metadata (burst sequence number, image number, shutter count)
+    Sequence,
+    /// Counter in the original file name, e.g. 1234 in `IMG_1234.JPG`
+    Filename,
+    /// Source path
+    Path,
+}
+
+impl FromStr for TieBreakKey {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "time" => Ok(Self::Time),
+            "sequence" => Ok(Self::Sequence),
+            "filename" => Ok(Self::Filename),
+            "path" => Ok(Self::Path),
+            _ => anyhow::bail!("Invalid tie-break key '{}' (expected time, sequence, filename or path)", value),
         }
+    }
+}
 
-        // Generate filename normally if not a duplicate
-        let filename = self.generate_filename(dt, milliseconds, extension, existing_files);
-        (filename, false)
+/// Order in which files with the same target name receive their `-2`, `-3` suffixes
+///
+/// Keys are compared in turn; a file that lacks a key sorts after files that have it.
+/// The source path settles whatever the keys leave open, so the order is deterministic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TieBreakOrder {
+    keys: Vec<TieBreakKey>,
+}
+
+impl TieBreakOrder {
+    pub fn new(keys: Vec<TieBreakKey>) -> Self {
+        Self { keys }
     }
 
-    fn _check_content_duplicate(
+    pub fn compare(
         &self,
-        _file_path: &std::path::Path,
-        _existing_hash_index: &std::collections::HashMap<std::path::PathBuf, String>,
-        _target_directory: &std::path::Path,
-    ) -> Option<std::path::PathBuf> {
-        // This would need to be implemented with the content hasher
-        // For now, return None to indicate no duplicate found
-        None
+        (a_path, a_exif): (&Path, Option<&ExifData>),
+        (b_path, b_exif): (&Path, Option<&ExifData>),
+    ) -> Ordering {
+        self.keys.iter()
+            .map(|key| match key {
+                TieBreakKey::Time => present_first(
+                    a_exif.map(|exif_data| (exif_data.timestamp, exif_data.nanoseconds)),
+                    b_exif.map(|exif_data| (exif_data.timestamp, exif_data.nanoseconds)),
+                ),
+                TieBreakKey::Sequence => present_first(a_exif.and_then(sequence_number), b_exif.and_then(sequence_number)),
+                TieBreakKey::Filename => present_first(filename_counter(a_path), filename_counter(b_path)),
+                TieBreakKey::Path => a_path.cmp(b_path),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a_path.cmp(b_path))
+    }
+}
+
+impl Default for TieBreakOrder {
+    fn default() -> Self {
+        Self::new(vec![TieBreakKey::Time, TieBreakKey::Sequence, TieBreakKey::Filename])
+    }
+}
+
+/// Compare two optional values, placing missing ones last
+fn present_first<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Shot counter of the first sequence field with digits (`100-1234` reads as 1001234)
+fn sequence_number(exif_data: &ExifData) -> Option<u64> {
+    SEQUENCE_FIELDS.iter()
+        .filter_map(|field| exif_data._metadata.get(*field))
+        .find_map(|value| digits(value))
+}
+
+/// Last run of digits in the file stem, e.g. 1234 in `DSC01234.ARW`
+fn filename_counter(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end].trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    stem[start..end].parse().ok()
+}
+
+fn digits(value: &str) -> Option<u64> {
+    let digits: String = value.chars().filter(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::burst::Burst;
+
+    fn render(template: &str, exif_data: &ExifData, extension: &str, suffix: Option<u32>) -> String {
+        NamingTemplate::parse(template).unwrap().render(exif_data, NamingTime::Local, extension, suffix)
+    }
+
+    fn parse_error(template: &str) -> String {
+        NamingTemplate::parse(template).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn default_template_renders_date_folders_and_suffix() {
+        let exif_data = ExifData::for_test("2024-12-19T14:30:52.123456+01:00", &[]);
+        assert_eq!(render(DEFAULT_TEMPLATE, &exif_data, "jpg", None), "2024/12-Dec/20241219_143052.123.jpg");
+        assert_eq!(render(DEFAULT_TEMPLATE, &exif_data, "jpg", Some(2)), "2024/12-Dec/20241219_143052.123-2.jpg");
+    }
+
+    #[test]
+    fn widths_subseconds_and_naming_time() {
+        let exif_data = ExifData::for_test("2024-03-05T07:08:09.123456789+02:00", &[]);
+        assert_eq!(
+            render("{year}/{month}-{month_name}/{day:3}_{hour}{minute}{second}.{subsec:6}{suffix}.{ext}", &exif_data, "cr3", None),
+            "2024/03-March/005_070809.123456.cr3",
+        );
+        let template = NamingTemplate::parse("{date}_{time}{suffix}.{ext}").unwrap();
+        assert_eq!(template.render(&exif_data, NamingTime::Utc, "cr3", None), "20240305_050809.cr3");
+    }
+
+    #[test]
+    fn metadata_tokens_are_sanitized_and_fall_back() {
+        let exif_data = ExifData::for_test("2024-01-01T00:00:00Z", &[
+            ("Make", "Canon"),
+            ("Model", "Canon EOS R5"),
+            ("LensModel", "RF 24-70mm F2.8 L/IS"),
+        ]);
+        assert_eq!(
+            render("{camera}/{lens}/{serial|NoSerial}/{meta:ISO}{suffix}.{ext}", &exif_data, "jpg", None),
+            "Canon_EOS_R5/RF_24-70mm_F2.8_L_IS/NoSerial/Unknown.jpg",
+        );
+        assert_eq!(sanitize_path_component("  \"..a:b*c..\"\0 "), Some("a_b_c".to_string()));
+        assert_eq!(sanitize_path_component(" ... "), None);
+    }
+
+    #[test]
+    fn empty_burst_folders_are_left_out() {
+        let mut exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let template = "{year}/{burst}/{date}{burst}{suffix}.{ext}";
+        assert_eq!(render(template, &exif_data, "jpg", None), "2024/20240101.jpg");
+        exif_data.burst = Some(Burst { day: exif_data.timestamp.date_naive(), number: 7 });
+        assert_eq!(render(template, &exif_data, "jpg", None), "2024/20240101_BURST007/20240101_20240101_BURST007.jpg");
+    }
+
+    #[test]
+    fn parser_rejects_malformed_templates() {
+        assert!(parse_error("{year/{suffix}.{ext}").contains("Unknown template token"));
+        assert!(parse_error("{year}/{date{suffix}.{ext}").contains("Unknown template token"));
+        assert!(parse_error("{date}{suffix}.{ext").contains("Unclosed"));
+        assert!(parse_error("{date}}{suffix}.{ext}").contains("Unmatched"));
+        assert!(parse_error("{colour}/{date}{suffix}.{ext}").contains("Unknown template token"));
+        assert!(parse_error("{meta}/{date}{suffix}.{ext}").contains("needs a field name"));
+        assert!(parse_error("{month_abbr:2}/{date}{suffix}.{ext}").contains("does not take a width"));
+        assert!(parse_error("{month:x}/{date}{suffix}.{ext}").contains("Invalid width"));
+        assert!(parse_error("{date}.{subsec:10}{suffix}.{ext}").contains("1 to 9 digits"));
+        assert!(parse_error("{year|none}/{date}{suffix}.{ext}").contains("Only metadata tokens"));
+        assert!(parse_error("{camera|}/{date}{suffix}.{ext}").contains("must not be empty"));
+    }
+
+    #[test]
+    fn validator_requires_a_collision_safe_file_name() {
+        assert!(parse_error("{year}//{date}{suffix}.{ext}").contains("empty path components"));
+        assert!(parse_error("{year}/../{date}{suffix}.{ext}").contains("'.' or '..'"));
+        assert!(parse_error("{year}{suffix}/{date}{suffix}.{ext}").contains("only appear in the file name"));
+        assert!(parse_error("{year}/{date}.{ext}").contains("exactly once"));
+        assert!(parse_error("{year}/{date}{suffix}{suffix}.{ext}").contains("exactly once"));
+        assert!(parse_error("{year}/{date}{suffix}.jpg").contains("must contain {ext}"));
+        assert!(NamingTemplate::parse("{year}/{camera}{suffix}.{ext}").is_ok());
+        assert!(NamingTemplate::parse(EVENTS_TEMPLATE).unwrap().uses_event());
+    }
+
+    #[test]
+    fn free_suffix_skips_every_taken_name_of_a_shot() {
+        let generator = FilenameGenerator::new(NamingTemplate::parse("{date}{suffix}.{ext}").unwrap(), NamingTime::Local, "");
+        let exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let taken = ["20240101.jpg", "20240101-2.cr3"];
+        let is_taken = |_: &str, name: &str| taken.contains(&name);
+
+        assert_eq!(generator.free_suffix(&exif_data, &["cr3"], is_taken), None);
+        assert_eq!(generator.free_suffix(&exif_data, &["jpg"], is_taken), Some(2));
+        assert_eq!(generator.free_suffix(&exif_data, &["cr3", "jpg"], is_taken), Some(3));
+    }
+
+    #[test]
+    fn tie_break_order_compares_keys_in_turn() {
+        let early = ExifData::for_test("2024-01-01T10:00:00.100Z", &[("ImageNumber", "9")]);
+        let late = ExifData::for_test("2024-01-01T10:00:00.200Z", &[("ImageNumber", "1")]);
+        let same = ExifData::for_test("2024-01-01T10:00:00.100Z", &[]);
+        let (a, b) = (Path::new("/in/IMG_0002.JPG"), Path::new("/in/IMG_0010.JPG"));
+
+        let order = TieBreakOrder::default();
+        assert_eq!(order.compare((b, Some(&early)), (a, Some(&late))), Ordering::Less);
+        assert_eq!(order.compare((a, Some(&early)), (b, Some(&same))), Ordering::Less, "sequence present first");
+        assert_eq!(order.compare((a, None), (b, Some(&same))), Ordering::Greater, "missing time last");
+
+        let by_sequence = TieBreakOrder::new(vec![TieBreakKey::Sequence]);
+        assert_eq!(by_sequence.compare((a, Some(&early)), (b, Some(&late))), Ordering::Greater);
+        let by_filename = TieBreakOrder::new(vec![TieBreakKey::Filename]);
+        assert_eq!(by_filename.compare((b, None), (a, None)), Ordering::Greater, "10 after 2, unlike the path order");
+        assert_eq!(filename_counter(Path::new("DSC01234.ARW")), Some(1234));
+        assert_eq!(sequence_number(&ExifData::for_test("2024-01-01T00:00:00Z", &[("FileNumber", "100-1234")])), Some(1001234));
+        assert!("time,foo".split(',').map(str::parse::<TieBreakKey>).any(|key| key.is_err()));
+    }
+
+    #[test]
+    fn mtime_dated_files_are_placed_in_their_own_folder() {
+        let mut exif_data = ExifData::for_test("2024-01-01T10:00:00Z", &[]);
+        let generator = FilenameGenerator::new(NamingTemplate::default(), NamingTime::Local, "");
+        assert_eq!(generator.generate_filename_with_fixed_suffix(&exif_data, "jpg", None), "2024/01-Jan/20240101_100000.000.jpg");
+
+        exif_data.provenance = crate::exif::Provenance {
+            source: TimestampSource::Mtime,
+            field: "FileModifyDate".to_string(),
+            confidence: crate::exif::Confidence::Low,
+        };
+        assert_eq!(
+            generator.generate_filename_with_fixed_suffix(&exif_data, "jpg", Some(2)),
+            "unsorted-by-mtime/2024/01-Jan/20240101_100000.000-2.jpg",
+        );
+        assert_eq!(generator.generate_directory(&exif_data), "unsorted-by-mtime/2024/01-Jan");
+
+        let generator = FilenameGenerator::new(NamingTemplate::parse("{date}{suffix}.{ext}").unwrap(), NamingTime::Local, "/by-mtime/");
+        assert_eq!(generator.generate_filename_with_fixed_suffix(&exif_data, "jpg", None), "by-mtime/20240101.jpg");
+        assert_eq!(generator.generate_directory(&exif_data), "by-mtime");
     }
 }
@@ -0,0 +1,327 @@
+/*!
+ * Sidecar file module
+ *
+ * Lightroom, darktable and other editors keep edits and corrected metadata in XMP
+ * sidecars next to the original (`IMG_1234.xmp` or `IMG_1234.CR3.xmp`); phones and
+ * action cameras add `.aae` edits, `.thm` thumbnails, `.lrv` proxies and `.srt`
+ * telemetry. Sidecars are found by name and travel with their primary file. The XMP
+ * packet is read with a small property scanner rather than a full RDF parser.
+ */
+
+use anyhow::{Context, Result};
+use log::debug;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::exif::parse_timestamp;
+
+/// XMP timestamp properties as (XMP name, metadata field, embedded fields it supersedes)
+///
+/// The superseded fields carry the subseconds of the embedded value, which would
+/// otherwise win over a corrected sidecar date in the field policy.
+const XMP_TIMESTAMP_FIELDS: [(&str, &str, &[&str]); 3] = [
+    ("exif:DateTimeOriginal", "DateTimeOriginal", &["SubSecDateTimeOriginal", "SubSecTimeOriginal"]),
+    ("exif:DateTimeDigitized", "DateTimeDigitized", &["SubSecTimeDigitized"]),
+    ("xmp:CreateDate", "Create Date", &["SubSecCreateDate"]),
+];
+
+/// XMP properties written by the editor when the sidecar changes, never merged
+///
+/// `xmp:ModifyDate` would otherwise replace the ModifyDate of the file with the time
+/// of the last edit.
+const XMP_EDIT_TIME_PROPERTIES: [&str; 2] = ["xmp:ModifyDate", "xmp:MetadataDate"];
+
+/// Extensions of files that belong to a primary media file with the same name
+pub const SIDECAR_EXTENSIONS: [&str; 6] = ["xmp", "aae", "thm", "lrv", "srt", "dop"];
+
+/// Namespaces of the packet structure itself, never merged as metadata
+const STRUCTURAL_PREFIXES: [&str; 3] = ["xmlns", "rdf", "x"];
+
+/// How metadata from an XMP sidecar is combined with the metadata embedded in the file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XmpPrecedence {
+    /// Sidecar values replace embedded ones, e.g. capture times corrected in an editor
+    #[default]
+    Prefer,
+    /// Sidecar values only fill in fields the file does not have
+    Fallback,
+    /// Sidecars are ignored
+    Off,
+}
+
+impl FromStr for XmpPrecedence {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "prefer" => Ok(Self::Prefer),
+            "fallback" => Ok(Self::Fallback),
+            "off" => Ok(Self::Off),
+            _ => anyhow::bail!("Invalid XMP precedence '{}' (expected prefer, fallback or off)", value),
+        }
+    }
+}
+
+/// XMP sidecar of `file_path`: `IMG_1234.CR3.xmp` (darktable) or `IMG_1234.xmp` (Lightroom)
+pub fn find_xmp(file_path: &Path) -> Option<PathBuf> {
+    let file_name = file_path.file_name()?.to_str()?;
+    let stem = file_path.file_stem()?.to_str()?;
+    [file_name, stem].iter()
+        .flat_map(|base| ["xmp", "XMP"].map(|ext| file_path.with_file_name(format!("{}.{}", base, ext))))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Sidecar files of a set of primary media files
+#[derive(Debug, Default)]
+pub struct SidecarIndex {
+    sidecars: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl SidecarIndex {
+    /// Find the sidecars of `primaries`, reading each of their directories once
+    ///
+    /// `IMG_1234.CR3.xmp` belongs to `IMG_1234.CR3`. `IMG_1234.xmp` belongs to the first
+    /// primary named `IMG_1234.*` in path order, so every sidecar is handled exactly once.
+    pub fn build(primaries: &[PathBuf]) -> Self {
+        let mut by_directory: HashMap<&Path, Vec<&PathBuf>> = HashMap::new();
+        for primary in primaries {
+            by_directory.entry(primary.parent().unwrap_or(Path::new(""))).or_default().push(primary);
+        }
+
+        let mut sidecars: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for (directory, mut files) in by_directory {
+            files.sort();
+            let listing = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+            let entries = match fs::read_dir(listing) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    debug!("Cannot list {} for sidecars: {}", listing.display(), e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_lowercase();
+                let base = match file_name.rsplit_once('.') {
+                    Some((base, extension)) if SIDECAR_EXTENSIONS.contains(&extension) => base.to_string(),
+                    _ => continue,
+                };
+                if !entry.file_type().is_ok_and(|file_type| file_type.is_file()) {
+                    continue;
+                }
+                let lowercase = |name: Option<&std::ffi::OsStr>| name.map(|name| name.to_string_lossy().to_lowercase());
+                let owner = files.iter()
+                    .find(|primary| lowercase(primary.file_name()).as_deref() == Some(base.as_str()))
+                    .or_else(|| files.iter().find(|primary| lowercase(primary.file_stem()).as_deref() == Some(base.as_str())));
+                if let Some(owner) = owner {
+                    sidecars.entry((*owner).clone()).or_default().push(directory.join(entry.file_name()));
+                }
+            }
+        }
+        for files in sidecars.values_mut() {
+            files.sort();
+        }
+        debug!("Found sidecars for {} files", sidecars.len());
+        Self { sidecars }
+    }
+
+    pub fn sidecars_of(&self, primary: &Path) -> &[PathBuf] {
+        self.sidecars.get(primary).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Target of `sidecar` when its primary file goes from `primary` to `primary_target`
+///
+/// The sidecar takes the new name of the primary: `IMG_1234.CR3.xmp` becomes
+/// `20240101_120000.000.cr3.xmp`, `IMG_1234.xmp` becomes `20240101_120000.000.xmp`.
+pub fn sidecar_target(sidecar: &Path, primary: &Path, primary_target: &Path) -> Option<PathBuf> {
+    let sidecar_name = sidecar.file_name()?.to_str()?;
+    let extension = sidecar.extension()?.to_str()?.to_lowercase();
+    let primary_name = primary.file_name()?.to_str()?;
+
+    let base = if sidecar_name.len() > primary_name.len()
+        && sidecar_name.get(..primary_name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(primary_name))
+    {
+        primary_target.file_name()?
+    } else {
+        primary_target.file_stem()?
+    };
+    Some(primary_target.with_file_name(format!("{}.{}", base.to_str()?, extension)))
+}
+
+/// Simple properties of an XMP packet keyed by their qualified name (`exif:DateTimeOriginal`)
+///
+/// Both attribute (`exif:DateTimeOriginal="..."`) and element
+/// (`<exif:DateTimeOriginal>...</exif:DateTimeOriginal>`) forms are read; structured
+/// properties such as edit histories are skipped.
+pub fn read_xmp(path: &Path) -> Result<HashMap<String, String>> {
+    let packet = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read XMP sidecar: {}", path.display()))?;
+
+    static ATTRIBUTE_RE: OnceLock<Regex> = OnceLock::new();
+    static ELEMENT_RE: OnceLock<Regex> = OnceLock::new();
+    let attribute_re = ATTRIBUTE_RE.get_or_init(|| {
+        Regex::new(r#"\s([A-Za-z][\w.-]*):([A-Za-z][\w.-]*)\s*=\s*"([^"]*)""#).expect("attribute regex is valid")
+    });
+    let element_re = ELEMENT_RE.get_or_init(|| {
+        Regex::new(r"<([A-Za-z][\w.-]*):([A-Za-z][\w.-]*)>([^<]*)</([A-Za-z][\w.-]*):([A-Za-z][\w.-]*)>")
+            .expect("element regex is valid")
+    });
+
+    let mut properties = HashMap::new();
+    for captures in attribute_re.captures_iter(&packet) {
+        properties.entry(format!("{}:{}", &captures[1], &captures[2]))
+            .or_insert_with(|| unescape(&captures[3]));
+    }
+    for captures in element_re.captures_iter(&packet) {
+        if captures[1] != captures[4] || captures[2] != captures[5] {
+            continue;
+        }
+        properties.entry(format!("{}:{}", &captures[1], &captures[2]))
+            .or_insert_with(|| unescape(captures[3].trim()));
+    }
+    properties.retain(|name, value| {
+        let prefix = name.split(':').next().unwrap_or_default();
+        !value.is_empty() && !STRUCTURAL_PREFIXES.contains(&prefix)
+    });
+    debug!("Read {} properties from XMP sidecar {}", properties.len(), path.display());
+    Ok(properties)
+}
+
+/// Merge XMP properties into embedded metadata, returning the fields taken from the sidecar
+///
+/// Timestamp properties are mapped to the field names of the timestamp policy and only
+/// merged when they parse as a full timestamp, so an XMP date without seconds never
+/// replaces a valid embedded one; every other property is merged under its local name
+/// (`tiff:Model` becomes `Model`).
+pub fn merge_xmp(
+    metadata: &mut HashMap<String, String>,
+    properties: HashMap<String, String>,
+    precedence: XmpPrecedence,
+) -> HashSet<String> {
+    let mut merged = HashSet::new();
+    if precedence == XmpPrecedence::Off {
+        return merged;
+    }
+
+    let mut entries = Vec::new();
+    for (name, value) in properties {
+        if XMP_EDIT_TIME_PROPERTIES.contains(&name.as_str()) {
+            continue;
+        }
+        match XMP_TIMESTAMP_FIELDS.iter().find(|(xmp_name, _, _)| *xmp_name == name) {
+            Some((_, field, superseded)) => {
+                if let Err(e) = parse_timestamp(&value) {
+                    debug!("Ignoring XMP {}: {}", name, e);
+                    continue;
+                }
+                entries.push((field.to_string(), value, *superseded));
+            }
+            None => {
+                let local_name = name.split_once(':').map_or(name.as_str(), |(_, local)| local);
+                entries.push((local_name.to_string(), value, &[][..]));
+            }
+        }
+    }
+
+    for (field, value, superseded) in entries {
+        if precedence == XmpPrecedence::Fallback && metadata.contains_key(&field) {
+            continue;
+        }
+        if precedence == XmpPrecedence::Prefer {
+            for embedded in superseded {
+                metadata.remove(*embedded);
+            }
+        }
+        metadata.insert(field.clone(), value);
+        merged.insert(field);
+    }
+    merged
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn embedded() -> HashMap<String, String> {
+        fields(&[
+            ("DateTimeOriginal", "2024:01:01 12:00:00"),
+            ("SubSecTimeOriginal", "25"),
+            ("ModifyDate", "2024:01:01 12:00:00"),
+            ("SubSecTime", "25"),
+        ])
+    }
+
+    #[test]
+    fn prefer_replaces_timestamps_and_their_subseconds() {
+        let mut metadata = embedded();
+        let merged = merge_xmp(&mut metadata, fields(&[
+            ("exif:DateTimeOriginal", "2024-01-01T13:00:00.50+01:00"),
+            ("tiff:Model", "X100V"),
+        ]), XmpPrecedence::Prefer);
+        assert_eq!(metadata["DateTimeOriginal"], "2024-01-01T13:00:00.50+01:00");
+        assert!(!metadata.contains_key("SubSecTimeOriginal"));
+        assert_eq!(metadata["Model"], "X100V");
+        assert_eq!(merged, HashSet::from(["DateTimeOriginal".to_string(), "Model".to_string()]));
+    }
+
+    #[test]
+    fn edit_times_and_unparseable_dates_are_not_merged() {
+        let mut metadata = embedded();
+        let merged = merge_xmp(&mut metadata, fields(&[
+            ("exif:DateTimeOriginal", "2024-01-01T13:00"),
+            ("xmp:ModifyDate", "2025-06-01T09:00:00"),
+            ("xmp:MetadataDate", "2025-06-01T09:00:00"),
+        ]), XmpPrecedence::Prefer);
+        assert!(merged.is_empty());
+        assert_eq!(metadata, embedded());
+    }
+
+    #[test]
+    fn fallback_only_fills_missing_fields() {
+        let mut metadata = embedded();
+        let merged = merge_xmp(&mut metadata, fields(&[
+            ("exif:DateTimeOriginal", "2024-01-01T13:00:00"),
+            ("xmp:CreateDate", "2024-01-01T13:00:00"),
+        ]), XmpPrecedence::Fallback);
+        assert_eq!(metadata["DateTimeOriginal"], "2024:01:01 12:00:00");
+        assert_eq!(metadata["Create Date"], "2024-01-01T13:00:00");
+        assert_eq!(merged, HashSet::from(["Create Date".to_string()]));
+
+        let mut metadata = embedded();
+        assert!(merge_xmp(&mut metadata, fields(&[("tiff:Model", "X100V")]), XmpPrecedence::Off).is_empty());
+        assert_eq!(metadata, embedded());
+    }
+
+    #[test]
+    fn reads_attribute_and_element_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("IMG_1234.xmp");
+        fs::write(&path, r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+            exif:DateTimeOriginal="2024-01-01T13:00:00" tiff:Make="Fuji &amp; Co">
+            <tiff:Model> X100V </tiff:Model>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#).unwrap();
+        let properties = read_xmp(&path).unwrap();
+        assert_eq!(properties, fields(&[
+            ("exif:DateTimeOriginal", "2024-01-01T13:00:00"),
+            ("tiff:Make", "Fuji & Co"),
+            ("tiff:Model", "X100V"),
+        ]));
+    }
+}